@@ -1,12 +1,31 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use crate::miner::Handle as MinerHandle;
+use crate::network::generator::Handle as GeneratorHandle;
 use crate::network::server::Handle as NetworkServerHandle;
 use crate::network::message::Message;
+use crate::event::EventBus;
+use crate::metrics::Metrics;
+use crate::blockchain::Blockchain;
+use crate::crypto::address::H160;
+use crate::block::Block;
+use crate::crypto::hash::{Hashable, H256};
+use crate::event::Event;
+use crate::inclusion;
+use crate::snapshot::Snapshot;
+use crate::transaction::{account_info, AccountInfo, AccountProof, ChainId, LedgerMode, Mempool, SignedTransaction, SkipReason, StatePerBlock, Transaction, TxBlockMempool};
+use crate::multisig::Multisig;
+use crate::utxo::{total_value, Output};
+use crate::wallet::{self, Wallet};
 
 use log::info;
 use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::SystemTime;
 use tiny_http::Header;
+use tiny_http::Method;
 use tiny_http::Response;
 use tiny_http::Server as HTTPServer;
 use url::Url;
@@ -14,7 +33,19 @@ use url::Url;
 pub struct Server {
     handle: HTTPServer,
     miner: MinerHandle,
+    generator: GeneratorHandle,
     network: NetworkServerHandle,
+    events: EventBus,
+    metrics: Arc<Metrics>,
+    blockchain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
+    txBlockmempool: Arc<Mutex<TxBlockMempool>>,
+    spb: Arc<Mutex<StatePerBlock>>,
+    wallet: Arc<Mutex<Wallet>>,
+    ledger: LedgerMode,
+    chain_id: ChainId,
+    /// Where `/snapshot/export` writes.
+    snapshot_dir: PathBuf,
 }
 
 #[derive(Serialize)]
@@ -23,6 +54,229 @@ struct ApiResponse {
     message: String,
 }
 
+/// JSON body of the requests that give out wallet secrets.
+#[derive(Deserialize)]
+struct Unlock {
+    passphrase: String,
+}
+
+/// Longest request body read, a passphrase fits in far less.
+const MAX_BODY: u64 = 4096;
+
+#[derive(Serialize)]
+struct WalletAddress {
+    label: String,
+    address: String,
+    public_key: String,
+    #[serde(flatten)]
+    account: AccountInfo,
+}
+
+#[derive(Serialize)]
+struct MinerStatus {
+    threads: usize,
+    hash_rate: u64,
+    hash_attempts: u64,
+}
+
+#[derive(Serialize)]
+struct PeerView {
+    addr: String,
+    direction: String,
+}
+
+/// An account transaction has a sender, a recipient and a nonce, a batch a sender, a nonce and
+/// outputs, a UTXO transaction has inputs and outputs; the value is the total paid either way.
+/// A multisig sender also shows its policy.
+#[derive(Serialize)]
+struct TransactionView {
+    hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+    value: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_time: Option<u128>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    inputs: Vec<InputView>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    outputs: Vec<OutputView>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    multisig: Option<MultisigView>,
+}
+
+#[derive(Serialize)]
+struct MultisigView {
+    address: String,
+    threshold: u32,
+    /// Hex encoded, sorted
+    public_keys: Vec<String>,
+    /// Indices of the keys that signed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    signers: Vec<u32>,
+}
+
+impl MultisigView {
+    fn new(multisig: &Multisig, signers: Vec<u32>) -> Self {
+        MultisigView {
+            address: format!("{:x}", multisig.address()),
+            threshold: multisig.threshold,
+            public_keys: multisig.public_keys.iter().map(hex::encode).collect(),
+            signers,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct InputView {
+    /// The spent output, as `<transaction hash>:<index>`
+    outpoint: String,
+    from: String,
+}
+
+#[derive(Serialize)]
+struct OutputView {
+    to: String,
+    value: u64,
+}
+
+impl OutputView {
+    fn new(o: &Output) -> Self {
+        OutputView { to: format!("{:x}", o.recipientAddr), value: o.value }
+    }
+}
+
+impl TransactionView {
+    fn new(t: &SignedTransaction) -> Self {
+        match t {
+            SignedTransaction::Account(t) => {
+                let sender: H160 = t.public_key[..].into();
+                TransactionView {
+                    hash: t.hash().to_string(),
+                    from: Some(format!("{:x}", sender)),
+                    to: Some(format!("{:x}", t.transaction.recipientAddr)),
+                    value: t.transaction.value,
+                    nonce: Some(t.transaction.accountNonce),
+                    lock_height: t.transaction.lock_height,
+                    lock_time: t.transaction.lock_time,
+                    inputs: vec![],
+                    outputs: vec![],
+                    multisig: None,
+                }
+            }
+            SignedTransaction::Batch(t) => {
+                let sender: H160 = t.public_key[..].into();
+                TransactionView {
+                    hash: t.hash().to_string(),
+                    from: Some(format!("{:x}", sender)),
+                    to: None,
+                    value: t.transaction.outputs.iter().map(|o| o.value).fold(0, u64::saturating_add),
+                    nonce: Some(t.transaction.accountNonce),
+                    lock_height: None,
+                    lock_time: None,
+                    inputs: vec![],
+                    outputs: t.transaction.outputs.iter().map(OutputView::new).collect(),
+                    multisig: None,
+                }
+            }
+            SignedTransaction::Multisig(t) => {
+                let signers = t.signatures.iter().map(|s| s.index).collect();
+                TransactionView {
+                    hash: t.hash().to_string(),
+                    from: Some(format!("{:x}", t.multisig.address())),
+                    to: Some(format!("{:x}", t.transaction.recipientAddr)),
+                    value: t.transaction.value,
+                    nonce: Some(t.transaction.accountNonce),
+                    lock_height: t.transaction.lock_height,
+                    lock_time: t.transaction.lock_time,
+                    inputs: vec![],
+                    outputs: vec![],
+                    multisig: Some(MultisigView::new(&t.multisig, signers)),
+                }
+            }
+            SignedTransaction::Utxo(t) => TransactionView {
+                hash: t.hash().to_string(),
+                from: None,
+                to: None,
+                value: t.outputs.iter().map(|o| o.value).fold(0, u64::saturating_add),
+                nonce: None,
+                lock_height: None,
+                lock_time: None,
+                inputs: t.inputs.iter().map(|i| {
+                    let owner: H160 = i.public_key[..].into();
+                    InputView {
+                        outpoint: format!("{}:{}", i.outpoint.tx, i.outpoint.index),
+                        from: format!("{:x}", owner),
+                    }
+                }).collect(),
+                outputs: t.outputs.iter().map(OutputView::new).collect(),
+                multisig: None,
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BlockView {
+    hash: String,
+    kind: &'static str,
+    parent: String,
+    /// Height in the proposer chain, only known for proposer blocks.
+    height: Option<u32>,
+    nonce: u32,
+    timestamp: u128,
+    tx_root: String,
+    pointer_root: String,
+    vote_root: String,
+    state_root: String,
+    /// Votes received, only known for proposer blocks.
+    vote_count: Option<u32>,
+    tx_pointers: Vec<String>,
+    transactions: Vec<TransactionView>,
+    votes: Vec<String>,
+}
+
+impl BlockView {
+    fn new(block: &Block, kind: &'static str, height: Option<u32>, vote_count: Option<u32>) -> Self {
+        BlockView {
+            hash: block.hash().to_string(),
+            kind,
+            parent: block.header.parent.to_string(),
+            height,
+            nonce: block.header.nonce,
+            timestamp: block.header.timestamp,
+            tx_root: block.header.tx_root.to_string(),
+            pointer_root: block.header.pointer_root.to_string(),
+            vote_root: block.header.vote_root.to_string(),
+            state_root: block.header.state_root.to_string(),
+            vote_count,
+            tx_pointers: block.txPointer.tp.iter().map(|h| h.to_string()).collect(),
+            transactions: block.content.data.iter().map(TransactionView::new).collect(),
+            votes: block.votes.pr.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TransactionStatus {
+    #[serde(flatten)]
+    transaction: TransactionView,
+    /// "locked" while in the mempool waiting for its time lock, "pending" while in the mempool,
+    /// "included" once in a transaction block, and "confirmed" once that transaction block is
+    /// referenced by the longest proposer chain and the transaction applied. A confirmed transaction block can also carry a transaction that is
+    /// "skipped", e.g. because another block already applied it.
+    status: &'static str,
+    tx_block: Option<String>,
+    /// The proposer block that confirmed the transaction block.
+    proposer_block: Option<String>,
+    skip_reason: Option<SkipReason>,
+}
+
 macro_rules! respond_result {
     ( $req:expr, $success:expr, $message:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
@@ -36,22 +290,175 @@ macro_rules! respond_result {
     }};
 }
 
+macro_rules! respond_json {
+    ( $req:expr, $payload:expr ) => {{
+        let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+        let resp = Response::from_string(serde_json::to_string_pretty(&$payload).unwrap())
+            .with_header(content_type);
+        $req.respond(resp).unwrap();
+    }};
+}
+
+/// Get a query parameter, or respond with an error and return from the request handler.
+macro_rules! require_param {
+    ( $req:expr, $params:expr, $name:expr ) => {{
+        match $params.get($name) {
+            Some(v) => v.clone(),
+            None => {
+                respond_result!($req, false, format!("missing {}", $name));
+                return;
+            }
+        }
+    }};
+}
+
+/// Parse an optional query parameter, or respond with an error and return.
+macro_rules! optional_param {
+    ( $req:expr, $params:expr, $name:expr, $t:ty ) => {{
+        match $params.get($name).map(|v| v.parse::<$t>()) {
+            None => None,
+            Some(Ok(v)) => Some(v),
+            Some(Err(e)) => {
+                respond_result!($req, false, format!("error parsing {}: {}", $name, e));
+                return;
+            }
+        }
+    }};
+}
+
+/// Parse a hex encoded hash parameter, or respond with an error and return.
+macro_rules! require_hash {
+    ( $req:expr, $params:expr, $name:expr ) => {{
+        let value = require_param!($req, $params, $name);
+        match value.parse::<H256>() {
+            Ok(v) => v,
+            Err(e) => {
+                respond_result!($req, false, format!("error parsing {}: {}", $name, e));
+                return;
+            }
+        }
+    }};
+}
+
+/// Parse a hex encoded address parameter, or respond with an error and return.
+macro_rules! require_address {
+    ( $req:expr, $params:expr, $name:expr ) => {{
+        let value = require_param!($req, $params, $name);
+        match value.parse::<H160>() {
+            Ok(v) => v,
+            Err(e) => {
+                respond_result!($req, false, format!("error parsing {}: {}", $name, e));
+                return;
+            }
+        }
+    }};
+}
+
+/// Read the wallet passphrase from the JSON body of a POST request, or respond with an error and
+/// return. It is never taken from the URL, which shows up in logs and shell histories.
+macro_rules! require_passphrase {
+    ( $req:expr ) => {{
+        if *$req.method() != Method::Post {
+            respond_result!($req, false, "expected a POST request with the wallet passphrase");
+            return;
+        }
+        let mut body = String::new();
+        let unlock = $req
+            .as_reader()
+            .take(MAX_BODY)
+            .read_to_string(&mut body)
+            .map_err(|e| e.to_string())
+            .and_then(|_| serde_json::from_str::<Unlock>(&body).map_err(|e| e.to_string()));
+        match unlock {
+            Ok(unlock) => unlock.passphrase,
+            Err(e) => {
+                respond_result!($req, false, format!("error reading passphrase: {}", e));
+                return;
+            }
+        }
+    }};
+}
+
+/// Build a multisig policy from the `threshold` and the comma separated hex `keys` parameters,
+/// or respond with an error and return.
+macro_rules! require_multisig {
+    ( $req:expr, $params:expr ) => {{
+        let threshold = require_param!($req, $params, "threshold");
+        let threshold = match threshold.parse::<u32>() {
+            Ok(v) => v,
+            Err(e) => {
+                respond_result!($req, false, format!("error parsing threshold: {}", e));
+                return;
+            }
+        };
+        let keys = require_param!($req, $params, "keys");
+        let keys: Result<Vec<Vec<u8>>, _> = keys.split(',').map(hex::decode).collect();
+        let keys = match keys {
+            Ok(v) => v,
+            Err(e) => {
+                respond_result!($req, false, format!("error parsing keys: {}", e));
+                return;
+            }
+        };
+        match Multisig::new(threshold, keys) {
+            Ok(v) => v,
+            Err(e) => {
+                respond_result!($req, false, e);
+                return;
+            }
+        }
+    }};
+}
+
 impl Server {
     pub fn start(
         addr: std::net::SocketAddr,
         miner: &MinerHandle,
+        generator: &GeneratorHandle,
         network: &NetworkServerHandle,
+        events: &EventBus,
+        metrics: &Arc<Metrics>,
+        blockchain: &Arc<Mutex<Blockchain>>,
+        mempool: &Arc<Mutex<Mempool>>,
+        txBlockmempool: &Arc<Mutex<TxBlockMempool>>,
+        spb: &Arc<Mutex<StatePerBlock>>,
+        wallet: &Arc<Mutex<Wallet>>,
+        ledger: LedgerMode,
+        chain_id: ChainId,
+        snapshot_dir: &Path,
     ) {
         let handle = HTTPServer::http(&addr).unwrap();
         let server = Self {
             handle,
             miner: miner.clone(),
+            generator: generator.clone(),
             network: network.clone(),
+            events: events.clone(),
+            metrics: Arc::clone(metrics),
+            blockchain: Arc::clone(blockchain),
+            mempool: Arc::clone(mempool),
+            txBlockmempool: Arc::clone(txBlockmempool),
+            spb: Arc::clone(spb),
+            wallet: Arc::clone(wallet),
+            ledger,
+            chain_id,
+            snapshot_dir: snapshot_dir.to_path_buf(),
         };
         thread::spawn(move || {
-            for req in server.handle.incoming_requests() {
+            for mut req in server.handle.incoming_requests() {
                 let miner = server.miner.clone();
+                let generator = server.generator.clone();
                 let network = server.network.clone();
+                let events = server.events.clone();
+                let metrics = Arc::clone(&server.metrics);
+                let blockchain = Arc::clone(&server.blockchain);
+                let mempool = Arc::clone(&server.mempool);
+                let txBlockmempool = Arc::clone(&server.txBlockmempool);
+                let spb = Arc::clone(&server.spb);
+                let wallet = Arc::clone(&server.wallet);
+                let ledger = server.ledger;
+                let chain_id = server.chain_id;
+                let snapshot_dir = server.snapshot_dir.clone();
                 thread::spawn(move || {
                     // a valid url requires a base
                     let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
@@ -62,6 +469,7 @@ impl Server {
                             return;
                         }
                     };
+                    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
                     match url.path() {
                         "/miner/start" => {
                             let params = url.query_pairs();
@@ -87,6 +495,444 @@ impl Server {
                             miner.start(lambda);
                             respond_result!(req, true, "ok");
                         }
+                        "/events" => {
+                            // server-sent events: one JSON object per `data:` line, until the
+                            // client goes away
+                            let subscription = events.subscribe();
+                            let mut writer = req.into_writer();
+                            let head = "HTTP/1.1 200 OK\r\n\
+                                        Content-Type: text/event-stream\r\n\
+                                        Cache-Control: no-cache\r\n\
+                                        Connection: keep-alive\r\n\r\n";
+                            if writer.write_all(head.as_bytes()).and_then(|_| writer.flush()).is_err() {
+                                return;
+                            }
+                            for event in subscription.iter() {
+                                let line = format!("data: {}\n\n", serde_json::to_string(&event).unwrap());
+                                if writer.write_all(line.as_bytes()).and_then(|_| writer.flush()).is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                        "/metrics" => {
+                            let content_type = "Content-Type: text/plain; version=0.0.4"
+                                .parse::<Header>()
+                                .unwrap();
+                            let resp = Response::from_string(metrics.render()).with_header(content_type);
+                            req.respond(resp).unwrap();
+                        }
+                        "/wallet/addresses" => {
+                            let wallet = wallet.lock().unwrap();
+                            let blockchain = blockchain.lock().unwrap();
+                            let mempool = mempool.lock().unwrap();
+                            let txBlockmempool = txBlockmempool.lock().unwrap();
+                            let spb = spb.lock().unwrap();
+                            let state = &spb.spb[&blockchain.tip()];
+                            let addresses: Vec<WalletAddress> = wallet
+                                .keys()
+                                .iter()
+                                .map(|k| WalletAddress {
+                                    label: k.label.clone(),
+                                    address: format!("{:x}", k.address),
+                                    public_key: hex::encode(k.public_key()),
+                                    account: account_info(&k.address, state, &txBlockmempool.txBlocks, &mempool),
+                                })
+                                .collect();
+                            respond_json!(req, addresses);
+                        }
+                        "/wallet/new" => {
+                            let label = params.get("label").cloned().unwrap_or_default();
+                            match wallet.lock().unwrap().generate(&label) {
+                                Ok(address) => respond_result!(req, true, format!("{:x}", address)),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/wallet/import" => {
+                            let pkcs8 = require_param!(req, params, "pkcs8");
+                            let label = params.get("label").cloned().unwrap_or_default();
+                            let pkcs8 = match hex::decode(&pkcs8) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, format!("error parsing pkcs8: {}", e));
+                                    return;
+                                }
+                            };
+                            match wallet.lock().unwrap().import(&pkcs8, &label) {
+                                Ok(address) => respond_result!(req, true, format!("{:x}", address)),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/wallet/export" => {
+                            let address = require_address!(req, params, "address");
+                            let passphrase = require_passphrase!(req);
+                            match wallet.lock().unwrap().export(&address, &passphrase) {
+                                Ok(pkcs8) => respond_result!(req, true, hex::encode(pkcs8)),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/wallet/mnemonic/new" => {
+                            let passphrase = require_passphrase!(req);
+                            match wallet.lock().unwrap().new_mnemonic(&passphrase) {
+                                Ok(phrase) => respond_result!(req, true, phrase),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/wallet/mnemonic/export" => {
+                            let passphrase = require_passphrase!(req);
+                            match wallet.lock().unwrap().export_mnemonic(&passphrase) {
+                                Ok(phrase) => respond_result!(req, true, phrase),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/wallet/mnemonic/import" => {
+                            let phrase = require_param!(req, params, "phrase");
+                            let gap = match params.get("gap").map(|g| g.parse::<u32>()) {
+                                None => wallet::DEFAULT_GAP_LIMIT,
+                                Some(Ok(v)) => v,
+                                Some(Err(e)) => {
+                                    respond_result!(req, false, format!("error parsing gap: {}", e));
+                                    return;
+                                }
+                            };
+                            let mut wallet = wallet.lock().unwrap();
+                            let blockchain = blockchain.lock().unwrap();
+                            let spb = spb.lock().unwrap();
+                            let state = &spb.spb[&blockchain.tip()];
+                            match wallet.restore_mnemonic(&phrase, state, gap) {
+                                Ok(recovered) => {
+                                    let recovered: Vec<String> = recovered.iter().map(|a| format!("{:x}", a)).collect();
+                                    respond_json!(req, recovered);
+                                }
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/wallet/derive" => {
+                            let label = params.get("label").cloned().unwrap_or_default();
+                            match wallet.lock().unwrap().derive_next(&label) {
+                                Ok(address) => respond_result!(req, true, format!("{:x}", address)),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/wallet/contacts" => {
+                            let wallet = wallet.lock().unwrap();
+                            let contacts: HashMap<&String, String> = wallet
+                                .contacts()
+                                .iter()
+                                .map(|(l, a)| (l, format!("{:x}", a)))
+                                .collect();
+                            respond_json!(req, contacts);
+                        }
+                        "/wallet/contacts/add" => {
+                            let label = require_param!(req, params, "label");
+                            let address = require_address!(req, params, "address");
+                            match wallet.lock().unwrap().add_contact(&label, address) {
+                                Ok(()) => respond_result!(req, true, "ok"),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/wallet/send" | "/wallet/send_batch" => {
+                            let from = require_address!(req, params, "from");
+                            let lock_height = optional_param!(req, params, "lock_height", u32);
+                            let lock_time = optional_param!(req, params, "lock_time", u128);
+                            let locked = lock_height.is_some() || lock_time.is_some();
+                            if locked && (url.path() != "/wallet/send" || ledger != LedgerMode::Account) {
+                                respond_result!(req, false, "only single account payments can be time-locked");
+                                return;
+                            }
+                            // (recipient, value) pairs, `to` and `value` for one payment or
+                            // `outputs=<to>:<value>,<to>:<value>` for a batch
+                            let pairs: Vec<(String, String)> = if url.path() == "/wallet/send" {
+                                vec![(require_param!(req, params, "to"), require_param!(req, params, "value"))]
+                            } else {
+                                let outputs = require_param!(req, params, "outputs");
+                                let pairs: Option<Vec<(String, String)>> = outputs
+                                    .split(',')
+                                    .map(|o| o.rfind(':').map(|i| (o[..i].to_string(), o[i + 1..].to_string())))
+                                    .collect();
+                                match pairs {
+                                    Some(v) => v,
+                                    None => {
+                                        respond_result!(req, false, "outputs must be <to>:<value> separated by commas");
+                                        return;
+                                    }
+                                }
+                            };
+                            let wallet = wallet.lock().unwrap();
+                            let mut payments: Vec<Output> = vec![];
+                            for (to, value) in pairs {
+                                let value = match value.parse::<u64>() {
+                                    Ok(v) => v,
+                                    Err(e) => {
+                                        respond_result!(req, false, format!("error parsing value: {}", e));
+                                        return;
+                                    }
+                                };
+                                let to = match wallet.resolve(&to) {
+                                    Some(v) => v,
+                                    None => {
+                                        respond_result!(req, false, format!("unknown recipient {}", to));
+                                        return;
+                                    }
+                                };
+                                payments.push(Output { recipientAddr: to, value });
+                            }
+                            let value = match total_value(&payments) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let blockchain = blockchain.lock().unwrap();
+                            let mut mempool = mempool.lock().unwrap();
+                            let txBlockmempool = txBlockmempool.lock().unwrap();
+                            let spb = spb.lock().unwrap();
+                            let state = &spb.spb[&blockchain.tip()];
+                            let txBlocks = &txBlockmempool.txBlocks;
+                            if account_info(&from, state, txBlocks, &mempool).balance < value {
+                                respond_result!(req, false, "insufficient balance");
+                                return;
+                            }
+                            let signed = match ledger {
+                                LedgerMode::Account if url.path() == "/wallet/send" => {
+                                    let payment = &payments[0];
+                                    let transaction = Transaction { recipientAddr: payment.recipientAddr, value: payment.value, accountNonce: 0, lock_height, lock_time };
+                                    wallet.transfer(&from, transaction, state, txBlocks, &mempool, &chain_id).map(SignedTransaction::Account)
+                                }
+                                LedgerMode::Account => wallet.transfer_batch(&from, payments, state, txBlocks, &mempool, &chain_id).map(SignedTransaction::Batch),
+                                LedgerMode::Utxo => wallet.transfer_utxo(&from, payments, state, &mempool, &chain_id).map(SignedTransaction::Utxo),
+                            };
+                            let signed = match signed {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let hash = signed.hash();
+                            let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+                            mempool.insert_or_hold(&signed, blockchain.height[&blockchain.tip()] + 1, now);
+                            network.broadcast(Message::NewTransactionHashes(vec![hash]));
+                            events.publish(Event::TransactionAccepted { hash: hash.to_string() });
+                            respond_result!(req, true, hash);
+                        }
+                        "/multisig/address" => {
+                            let multisig = require_multisig!(req, params);
+                            respond_json!(req, MultisigView::new(&multisig, vec![]));
+                        }
+                        "/wallet/multisig/send" => {
+                            let multisig = require_multisig!(req, params);
+                            let lock_height = optional_param!(req, params, "lock_height", u32);
+                            let lock_time = optional_param!(req, params, "lock_time", u128);
+                            let to = require_param!(req, params, "to");
+                            let value = require_param!(req, params, "value");
+                            let value = match value.parse::<u64>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, format!("error parsing value: {}", e));
+                                    return;
+                                }
+                            };
+                            if ledger != LedgerMode::Account {
+                                respond_result!(req, false, "multisig addresses need --ledger account");
+                                return;
+                            }
+                            let wallet = wallet.lock().unwrap();
+                            let to = match wallet.resolve(&to) {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, format!("unknown recipient {}", to));
+                                    return;
+                                }
+                            };
+                            let blockchain = blockchain.lock().unwrap();
+                            let mut mempool = mempool.lock().unwrap();
+                            let txBlockmempool = txBlockmempool.lock().unwrap();
+                            let spb = spb.lock().unwrap();
+                            let state = &spb.spb[&blockchain.tip()];
+                            let txBlocks = &txBlockmempool.txBlocks;
+                            if account_info(&multisig.address(), state, txBlocks, &mempool).balance < value {
+                                respond_result!(req, false, "insufficient balance");
+                                return;
+                            }
+                            let transaction = Transaction { recipientAddr: to, value, accountNonce: 0, lock_height, lock_time };
+                            let signed = match wallet.transfer_multisig(multisig, transaction, state, txBlocks, &mempool, &chain_id) {
+                                Ok(v) => SignedTransaction::Multisig(v),
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let hash = signed.hash();
+                            let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+                            mempool.insert_or_hold(&signed, blockchain.height[&blockchain.tip()] + 1, now);
+                            network.broadcast(Message::NewTransactionHashes(vec![hash]));
+                            events.publish(Event::TransactionAccepted { hash: hash.to_string() });
+                            respond_result!(req, true, hash);
+                        }
+                        "/miner/stop" => {
+                            miner.pause();
+                            respond_result!(req, true, "ok");
+                        }
+                        "/miner/status" => {
+                            let status = MinerStatus {
+                                threads: miner.threads(),
+                                hash_rate: miner.hash_rate(),
+                                hash_attempts: miner.hash_attempts(),
+                            };
+                            respond_json!(req, status);
+                        }
+                        "/generator/start" => {
+                            generator.start();
+                            respond_result!(req, true, "ok");
+                        }
+                        "/generator/stop" => {
+                            generator.stop();
+                            respond_result!(req, true, "ok");
+                        }
+                        "/network/peers" => {
+                            let peers: Vec<PeerView> = network
+                                .peers()
+                                .iter()
+                                .map(|p| PeerView {
+                                    addr: p.addr.to_string(),
+                                    direction: format!("{:?}", p.direction).to_lowercase(),
+                                })
+                                .collect();
+                            respond_json!(req, peers);
+                        }
+                        "/account" => {
+                            let address = require_address!(req, params, "address");
+                            let blockchain = blockchain.lock().unwrap();
+                            let mempool = mempool.lock().unwrap();
+                            let txBlockmempool = txBlockmempool.lock().unwrap();
+                            let spb = spb.lock().unwrap();
+                            let state = &spb.spb[&blockchain.tip()];
+                            respond_json!(req, account_info(&address, state, &txBlockmempool.txBlocks, &mempool));
+                        }
+                        "/account/proof" => {
+                            let address = require_address!(req, params, "address");
+                            let block = optional_param!(req, params, "block", H256);
+                            let blockchain = blockchain.lock().unwrap();
+                            let spb = spb.lock().unwrap();
+                            // without a block, prove against the tip of the longest chain
+                            let block = block.unwrap_or(blockchain.tip());
+                            match (blockchain.blocks.get(&block), spb.spb.get(&block)) {
+                                (Some(b), Some(state)) => respond_json!(req, AccountProof::new(&address, &b.header, state)),
+                                _ => respond_result!(req, false, format!("proposer block {} not found or its state pruned", block)),
+                            }
+                        }
+                        "/blockchain/block" => {
+                            let blockchain = blockchain.lock().unwrap();
+                            let txBlockmempool = txBlockmempool.lock().unwrap();
+                            // without a hash, show the tip of the longest chain
+                            let hash = if params.contains_key("hash") {
+                                require_hash!(req, params, "hash")
+                            } else {
+                                blockchain.tip()
+                            };
+                            if let Some(block) = blockchain.blocks.get(&hash) {
+                                let votes = blockchain.votes.get(&hash).cloned().unwrap_or(0);
+                                respond_json!(req, BlockView::new(block, "proposer", Some(blockchain.height[&hash]), Some(votes)));
+                            } else if let Some(block) = txBlockmempool.txBlocks.get(&hash) {
+                                respond_json!(req, BlockView::new(block, "transaction", None, None));
+                            } else if let Some(block) = blockchain.voter_blocks.get(&hash) {
+                                respond_json!(req, BlockView::new(block, "voter", None, None));
+                            } else {
+                                respond_result!(req, false, format!("block {} not found", hash));
+                            }
+                        }
+                        "/transaction" => {
+                            let hash = require_hash!(req, params, "hash");
+                            let blockchain = blockchain.lock().unwrap();
+                            let mempool = mempool.lock().unwrap();
+                            let txBlockmempool = txBlockmempool.lock().unwrap();
+                            let spb = spb.lock().unwrap();
+                            if let Some(t) = mempool.get(&hash) {
+                                let locked = mempool.immature.contains_key(&hash);
+                                respond_json!(req, TransactionStatus {
+                                    transaction: TransactionView::new(t),
+                                    status: if locked { "locked" } else { "pending" },
+                                    tx_block: None,
+                                    proposer_block: None,
+                                    skip_reason: None,
+                                });
+                                return;
+                            }
+                            // a transaction may be in several transaction blocks; report where it was
+                            // applied if anywhere, else where it was skipped, else where it is included
+                            let mut best: Option<TransactionStatus> = None;
+                            let mut tx_blocks: Vec<&H256> = txBlockmempool.txBlocks.keys().collect();
+                            tx_blocks.sort();
+                            for block_hash in tx_blocks {
+                                let block = &txBlockmempool.txBlocks[block_hash];
+                                let t = match block.content.data.iter().find(|t| t.hash() == hash) {
+                                    Some(t) => t,
+                                    None => continue,
+                                };
+                                let mut status = TransactionStatus {
+                                    transaction: TransactionView::new(t),
+                                    status: "included",
+                                    tx_block: Some(block_hash.to_string()),
+                                    proposer_block: None,
+                                    skip_reason: None,
+                                };
+                                if let Some(pr) = spb.confirmed_by(&blockchain, block_hash) {
+                                    status.proposer_block = Some(pr.to_string());
+                                    status.skip_reason = spb.spb[&pr].skipped.iter()
+                                        .find(|s| s.tx_block == *block_hash && s.transaction == hash)
+                                        .map(|s| s.reason);
+                                    status.status = if status.skip_reason.is_some() { "skipped" } else { "confirmed" };
+                                }
+                                let rank = |s: &TransactionStatus| match s.status {
+                                    "confirmed" => 2,
+                                    "skipped" => 1,
+                                    _ => 0,
+                                };
+                                if best.as_ref().map_or(true, |b| rank(&status) > rank(b)) {
+                                    best = Some(status);
+                                }
+                            }
+                            if let Some(status) = best {
+                                respond_json!(req, status);
+                                return;
+                            }
+                            respond_result!(req, false, format!("transaction {} not found", hash));
+                        }
+                        "/transaction/proof" => {
+                            let hash = require_hash!(req, params, "hash");
+                            let checkpoint = optional_param!(req, params, "checkpoint", H256);
+                            let blockchain = blockchain.lock().unwrap();
+                            let txBlockmempool = txBlockmempool.lock().unwrap();
+                            let spb = spb.lock().unwrap();
+                            let checkpoint = checkpoint.unwrap_or(blockchain.genesis);
+                            match inclusion::prove(&hash, &checkpoint, &blockchain, &txBlockmempool.txBlocks, &spb) {
+                                Ok(proof) => respond_json!(req, proof),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/snapshot/export" => {
+                            let name = require_param!(req, params, "name");
+                            let block = optional_param!(req, params, "block", H256);
+                            let snapshot = {
+                                let blockchain = blockchain.lock().unwrap();
+                                let spb = spb.lock().unwrap();
+                                // without a block, export the tip of the longest chain
+                                let block = block.unwrap_or(blockchain.tip());
+                                match Snapshot::new(&block, &blockchain, &spb) {
+                                    Some(snapshot) => snapshot,
+                                    None => {
+                                        respond_result!(req, false, format!("proposer block {} not found or its state pruned", block));
+                                        return;
+                                    }
+                                }
+                            };
+                            // written without holding the locks
+                            match snapshot.export(&snapshot_dir, &name) {
+                                Ok(path) => respond_result!(req, true, format!("snapshot of block {} at height {} written to {}", snapshot.block(), snapshot.height(), path.display())),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
                         "/network/ping" => {
                             network.broadcast(Message::Ping(String::from("Test ping")));
                             respond_result!(req, true, "ok");
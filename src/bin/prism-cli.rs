@@ -0,0 +1,362 @@
+//! Command-line client for the node's API server.
+
+use clap::{clap_app, ArgMatches};
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process;
+
+/// Send a GET request to the API server and return the decoded JSON body.
+fn get(api: &str, path: &str, params: &[(&str, &str)]) -> Result<Value, String> {
+    let mut target = path.to_string();
+    if !params.is_empty() {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params)
+            .finish();
+        target = format!("{}?{}", path, query);
+    }
+    let mut stream = TcpStream::connect(api).map_err(|e| format!("cannot connect to {}: {}", api, e))?;
+    // HTTP/1.0 makes the server send a Content-Length and close the connection when done
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", target, api);
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).map_err(|e| e.to_string())?;
+    let body = match response.find("\r\n\r\n") {
+        Some(i) => &response[i + 4..],
+        None => return Err("malformed response from the API server".to_string()),
+    };
+    serde_json::from_str(body).map_err(|e| format!("malformed response from the API server: {}", e))
+}
+
+/// Print a `{success, message}` reply, exiting with an error status if the request failed.
+fn print_result(reply: &Value, json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(reply).unwrap());
+    }
+    match (reply["success"].as_bool(), reply["message"].as_str()) {
+        (Some(true), Some(message)) => {
+            if !json {
+                println!("{}", message);
+            }
+        }
+        (Some(false), Some(message)) => {
+            if !json {
+                eprintln!("error: {}", message);
+            }
+            process::exit(1);
+        }
+        _ => {
+            if !json {
+                println!("{}", serde_json::to_string_pretty(reply).unwrap());
+            }
+        }
+    }
+}
+
+/// Print a JSON value, or hand it to `human` for the readable form. Error replies are reported
+/// like `print_result` does.
+fn print_value(reply: &Value, json: bool, human: impl Fn(&Value)) {
+    if reply["success"] == Value::Bool(false) {
+        print_result(reply, json);
+    } else if json {
+        println!("{}", serde_json::to_string_pretty(reply).unwrap());
+    } else {
+        human(reply);
+    }
+}
+
+fn print_account(account: &Value) {
+    println!(
+        "{:<40}  {:>12}  {:>6}  {:>6}  {}",
+        account["address"].as_str().unwrap_or("-"),
+        account["balance"].to_string(),
+        account["nonce"].to_string(),
+        account["pending_nonce"].to_string(),
+        account["label"].as_str().unwrap_or(""),
+    );
+}
+
+fn print_transaction(t: &Value) {
+    // a UTXO transaction lists inputs and outputs instead of a sender, recipient and nonce, a
+    // batch has a sender and a nonce but outputs instead of a recipient
+    if t["to"].is_null() {
+        match t["from"].as_str() {
+            Some(from) => println!("  {}  {}  value {}  nonce {}", t["hash"].as_str().unwrap_or("-"), from, t["value"], t["nonce"]),
+            None => println!("  {}  value {}", t["hash"].as_str().unwrap_or("-"), t["value"]),
+        }
+        for i in t["inputs"].as_array().into_iter().flatten() {
+            println!("    spends {}  from {}", i["outpoint"].as_str().unwrap_or("-"), i["from"].as_str().unwrap_or("-"));
+        }
+        for o in t["outputs"].as_array().into_iter().flatten() {
+            println!("    pays   {}  value {}", o["to"].as_str().unwrap_or("-"), o["value"]);
+        }
+        return;
+    }
+    println!(
+        "  {}  {} -> {}  value {}  nonce {}",
+        t["hash"].as_str().unwrap_or("-"),
+        t["from"].as_str().unwrap_or("-"),
+        t["to"].as_str().unwrap_or("-"),
+        t["value"],
+        t["nonce"],
+    );
+    if let Some(height) = t["lock_height"].as_u64() {
+        println!("    locked until height {}", height);
+    }
+    if !t["lock_time"].is_null() {
+        println!("    locked until time {}", t["lock_time"]);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let api = matches.value_of("api").unwrap();
+    let json = matches.is_present("json");
+    match matches.subcommand() {
+        ("balance", Some(m)) => {
+            let address = m.value_of("ADDRESS").unwrap();
+            let mut reply = get(api, "/account", &[("address", address)])?;
+            if reply.is_object() && reply.get("success").is_none() {
+                reply["address"] = Value::from(address);
+            }
+            print_value(&reply, json, |a| {
+                println!("{:<40}  {:>12}  {:>6}  {:>6}", "ADDRESS", "BALANCE", "NONCE", "NEXT");
+                print_account(a);
+            });
+        }
+        ("send", Some(m)) => {
+            let mut params = vec![
+                ("from", m.value_of("FROM").unwrap()),
+                ("to", m.value_of("TO").unwrap()),
+                ("value", m.value_of("VALUE").unwrap()),
+            ];
+            if let Some(height) = m.value_of("lock_height") {
+                params.push(("lock_height", height));
+            }
+            if let Some(time) = m.value_of("lock_time") {
+                params.push(("lock_time", time));
+            }
+            print_result(&get(api, "/wallet/send", &params)?, json);
+        }
+        ("send-batch", Some(m)) => {
+            let outputs: Vec<&str> = m.values_of("OUTPUTS").unwrap().collect();
+            let outputs = outputs.join(",");
+            let params = [("from", m.value_of("FROM").unwrap()), ("outputs", outputs.as_str())];
+            print_result(&get(api, "/wallet/send_batch", &params)?, json);
+        }
+        ("block", Some(m)) => {
+            let reply = match m.value_of("HASH") {
+                Some(hash) => get(api, "/blockchain/block", &[("hash", hash)])?,
+                None => get(api, "/blockchain/block", &[])?,
+            };
+            print_value(&reply, json, |b| {
+                println!("{} block {}", b["kind"].as_str().unwrap_or("-"), b["hash"].as_str().unwrap_or("-"));
+                println!("  parent       {}", b["parent"].as_str().unwrap_or("-"));
+                if let Some(height) = b["height"].as_u64() {
+                    println!("  height       {}", height);
+                }
+                println!("  timestamp    {}", b["timestamp"]);
+                println!("  nonce        {}", b["nonce"]);
+                if let Some(votes) = b["vote_count"].as_u64() {
+                    println!("  votes        {}", votes);
+                }
+                // a block carries only the content of its own type
+                match b["kind"].as_str() {
+                    Some("proposer") => {
+                        println!("  pointer root {}", b["pointer_root"].as_str().unwrap_or("-"));
+                        for pointer in b["tx_pointers"].as_array().into_iter().flatten() {
+                            println!("  tx block     {}", pointer.as_str().unwrap_or("-"));
+                        }
+                    }
+                    Some("voter") => {
+                        println!("  vote root    {}", b["vote_root"].as_str().unwrap_or("-"));
+                        for vote in b["votes"].as_array().into_iter().flatten() {
+                            println!("  votes for    {}", vote.as_str().unwrap_or("-"));
+                        }
+                    }
+                    _ => {
+                        println!("  tx root      {}", b["tx_root"].as_str().unwrap_or("-"));
+                        let transactions = b["transactions"].as_array().cloned().unwrap_or_default();
+                        println!("  {} transactions", transactions.len());
+                        for t in transactions.iter() {
+                            print_transaction(t);
+                        }
+                    }
+                }
+            });
+        }
+        ("tx", Some(m)) => {
+            let reply = get(api, "/transaction", &[("hash", m.value_of("HASH").unwrap())])?;
+            print_value(&reply, json, |t| {
+                print!("{}", t["status"].as_str().unwrap_or("-"));
+                if let Some(block) = t["tx_block"].as_str() {
+                    print!(" in transaction block {}", block);
+                }
+                if let Some(block) = t["proposer_block"].as_str() {
+                    print!(" by proposer block {}", block);
+                }
+                if let Some(reason) = t["skip_reason"].as_str() {
+                    print!(" ({})", reason.replace('_', " "));
+                }
+                println!();
+                print_transaction(t);
+            });
+        }
+        ("snapshot", Some(m)) => {
+            let mut params = vec![("name", m.value_of("NAME").unwrap())];
+            if let Some(block) = m.value_of("block") {
+                params.push(("block", block));
+            }
+            print_result(&get(api, "/snapshot/export", &params)?, json);
+        }
+        ("peers", Some(_)) => {
+            let reply = get(api, "/network/peers", &[])?;
+            print_value(&reply, json, |peers| {
+                for p in peers.as_array().into_iter().flatten() {
+                    println!("{:<24} {}", p["addr"].as_str().unwrap_or("-"), p["direction"].as_str().unwrap_or("-"));
+                }
+            });
+        }
+        ("miner", Some(m)) => match m.subcommand() {
+            ("start", Some(s)) => {
+                let lambda = s.value_of("lambda").unwrap();
+                print_result(&get(api, "/miner/start", &[("lambda", lambda)])?, json);
+            }
+            ("stop", Some(_)) => print_result(&get(api, "/miner/stop", &[])?, json),
+            ("status", Some(_)) => {
+                let reply = get(api, "/miner/status", &[])?;
+                print_value(&reply, json, |status| {
+                    println!("threads        {}", status["threads"]);
+                    println!("hash rate      {} H/s", status["hash_rate"]);
+                    println!("hash attempts  {}", status["hash_attempts"]);
+                });
+            }
+            _ => return Err(m.usage().to_string()),
+        },
+        ("generator", Some(m)) => match m.subcommand_name() {
+            Some("start") => print_result(&get(api, "/generator/start", &[])?, json),
+            Some("stop") => print_result(&get(api, "/generator/stop", &[])?, json),
+            _ => return Err(m.usage().to_string()),
+        },
+        ("multisig", Some(m)) => match m.subcommand() {
+            ("address", Some(s)) => {
+                let keys: Vec<&str> = s.values_of("KEYS").unwrap().collect();
+                let keys = keys.join(",");
+                let params = [("threshold", s.value_of("THRESHOLD").unwrap()), ("keys", keys.as_str())];
+                let reply = get(api, "/multisig/address", &params)?;
+                print_value(&reply, json, |m| {
+                    println!("{}  {} of {} keys", m["address"].as_str().unwrap_or("-"), m["threshold"], m["public_keys"].as_array().map_or(0, |k| k.len()));
+                });
+            }
+            ("send", Some(s)) => {
+                let keys: Vec<&str> = s.values_of("KEYS").unwrap().collect();
+                let keys = keys.join(",");
+                let params = [
+                    ("threshold", s.value_of("THRESHOLD").unwrap()),
+                    ("keys", keys.as_str()),
+                    ("to", s.value_of("TO").unwrap()),
+                    ("value", s.value_of("VALUE").unwrap()),
+                ];
+                print_result(&get(api, "/wallet/multisig/send", &params)?, json);
+            }
+            _ => return Err(m.usage().to_string()),
+        },
+        ("keys", Some(m)) => match m.subcommand() {
+            ("new", Some(s)) => {
+                let label = s.value_of("label").unwrap_or("");
+                print_result(&get(api, "/wallet/new", &[("label", label)])?, json);
+            }
+            ("list", Some(_)) => {
+                let reply = get(api, "/wallet/addresses", &[])?;
+                print_value(&reply, json, |keys| {
+                    println!("{:<40}  {:>12}  {:>6}  {:>6}  {}", "ADDRESS", "BALANCE", "NONCE", "NEXT", "LABEL");
+                    for k in keys.as_array().into_iter().flatten() {
+                        print_account(k);
+                    }
+                });
+            }
+            ("import", Some(s)) => {
+                let params = [
+                    ("pkcs8", s.value_of("PKCS8").unwrap()),
+                    ("label", s.value_of("label").unwrap_or("")),
+                ];
+                print_result(&get(api, "/wallet/import", &params)?, json);
+            }
+            _ => return Err(m.usage().to_string()),
+        },
+        _ => return Err(matches.usage().to_string()),
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = clap_app!(prism_cli =>
+     (version: "0.1")
+     (about: "Command-line client for the Bitcoin client API server")
+     (@arg api: --api [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server")
+     (@arg json: --json "Prints the raw JSON replies")
+     (@subcommand balance =>
+        (about: "Shows the balance and nonces of an address")
+        (@arg ADDRESS: +required "Hex address"))
+     (@subcommand send =>
+        (about: "Sends coins from a wallet address")
+        (@arg FROM: +required "Hex address of a wallet key")
+        (@arg TO: +required "Address book label, wallet key label or hex address")
+        (@arg VALUE: +required "Amount to send")
+        (@arg lock_height: --("lock-height") [HEIGHT] "Lowest proposer height that may apply the payment")
+        (@arg lock_time: --("lock-time") [MILLIS] "Earliest proposer timestamp, in ms since the epoch, that may apply the payment"))
+     (@subcommand send_batch =>
+        (name: "send-batch")
+        (about: "Sends coins from a wallet address to several recipients in one transaction")
+        (@arg FROM: +required "Hex address of a wallet key")
+        (@arg OUTPUTS: +required +multiple "Payments as <recipient>:<amount>"))
+     (@subcommand block =>
+        (about: "Shows a proposer, transaction or voter block, the tip by default")
+        (@arg HASH: "Block hash"))
+     (@subcommand tx =>
+        (about: "Shows a transaction and whether it is confirmed")
+        (@arg HASH: +required "Transaction hash"))
+     (@subcommand snapshot =>
+        (about: "Exports the ledger state at a proposer block, for another node to start from with --snapshot")
+        (@arg NAME: +required "Name of the file to write, in the snapshot directory of the node")
+        (@arg block: --block [HASH] "Proposer block hash, the tip by default"))
+     (@subcommand peers =>
+        (about: "Lists connected peers"))
+     (@subcommand miner =>
+        (about: "Controls the miner")
+        (@subcommand start =>
+            (@arg lambda: --lambda [MICROS] default_value("0") "Sleep between mining attempts"))
+        (@subcommand stop =>)
+        (@subcommand status =>
+            (about: "Shows the number of search threads and the hash rate")))
+     (@subcommand generator =>
+        (about: "Controls the transaction generator")
+        (@subcommand start =>)
+        (@subcommand stop =>))
+     (@subcommand multisig =>
+        (about: "Uses M-of-N multisig addresses")
+        (@subcommand address =>
+            (about: "Shows the address of a threshold of public keys")
+            (@arg THRESHOLD: +required "Number of keys that must sign")
+            (@arg KEYS: +required +multiple "Hex encoded Ed25519 public keys"))
+        (@subcommand send =>
+            (about: "Sends coins from a multisig address, signed by the wallet keys of it")
+            (@arg THRESHOLD: +required "Number of keys that must sign")
+            (@arg TO: +required "Address book label, wallet key label or hex address")
+            (@arg VALUE: +required "Amount to send")
+            (@arg KEYS: +required +multiple "Hex encoded Ed25519 public keys")))
+     (@subcommand keys =>
+        (about: "Manages wallet keys")
+        (@subcommand new =>
+            (@arg label: --label [LABEL] "Label of the new key"))
+        (@subcommand list =>)
+        (@subcommand import =>
+            (@arg PKCS8: +required "Hex encoded PKCS#8 key")
+            (@arg label: --label [LABEL] "Label of the imported key")))
+    )
+    .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
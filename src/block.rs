@@ -5,14 +5,58 @@ use ring::digest;
 use rand::Rng;
 use crate::crypto::merkle::{MerkleTree};
 
+/// One proof-of-work solution is mined over a header that commits to the content of every block
+/// type, and sortition of its hash decides which type the block becomes (see `sortition`).
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Header {
     pub parent:H256,
     pub nonce:u32,
     pub difficultyForPr:H256,
     pub difficultyForTx:H256,
+    pub difficultyForVoter:H256,
     pub timestamp:u128,
-    pub merkle_root:H256
+    /// Merkle root of the transactions, the content of a transaction block
+    pub tx_root:H256,
+    /// Merkle root of the transaction block pointers, the content of a proposer block
+    pub pointer_root:H256,
+    /// Merkle root of the votes, the content of a voter block
+    pub vote_root:H256,
+    /// Sparse Merkle root of the accounts after a proposer block applied the transaction blocks
+    /// it points to (see `State::state_root`), only checked for proposer blocks
+    pub state_root:H256,
+}
+
+impl Header {
+    /// The block type a header with this hash belongs to. The hash space is split into
+    /// consecutive ranges: [0, difficultyForPr] makes a proposer block, up to difficultyForTx a
+    /// transaction block and up to difficultyForVoter a voter block.
+    pub fn sortition(&self, hash: &H256) -> Option<BlockKind> {
+        if *hash <= self.difficultyForPr {
+            Some(BlockKind::Proposer)
+        } else if *hash <= self.difficultyForTx {
+            Some(BlockKind::Transaction)
+        } else if *hash <= self.difficultyForVoter {
+            Some(BlockKind::Voter)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the difficulties are the ones of `parent`; they never change along a chain.
+    pub fn same_difficulty(&self, parent: &Header) -> bool {
+        self.difficultyForPr == parent.difficultyForPr
+            && self.difficultyForTx == parent.difficultyForTx
+            && self.difficultyForVoter == parent.difficultyForVoter
+    }
+
+    /// The content root committed to for blocks of the given type.
+    pub fn content_root(&self, kind: BlockKind) -> H256 {
+        match kind {
+            BlockKind::Proposer => self.pointer_root,
+            BlockKind::Transaction => self.tx_root,
+            BlockKind::Voter => self.vote_root,
+        }
+    }
 }
 
 impl Hashable for Header {
@@ -23,27 +67,85 @@ impl Hashable for Header {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Content {
     pub data:Vec<SignedTransaction>
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl Content {
+    pub fn root(&self) -> H256 {
+        MerkleTree::new(&self.data).root()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct TxPointer {
     pub tp:Vec<H256>
 }
 
+impl TxPointer {
+    pub fn root(&self) -> H256 {
+        MerkleTree::new(&self.tp).root()
+    }
+}
+
+/// Proposer blocks a voter block votes for.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Votes {
+    pub pr:Vec<H256>
+}
+
+impl Votes {
+    pub fn root(&self) -> H256 {
+        MerkleTree::new(&self.pr).root()
+    }
+}
+
 // #[derive(Serialize, Deserialize, Debug, Clone)]
 // pub struct Block {
 //     pub header:Header,
 //     pub content:Content,
 // }
 
+/// A block carries the content of its own type only, the content of the other types is dropped
+/// once the type is known (see `for_kind`).
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
     pub header:Header,
     pub txPointer:TxPointer,
     pub content:Content,
+    pub votes:Votes,
+}
+
+/// Which kind of block a header qualifies as, decided by the sortition of its hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    Proposer,
+    Transaction,
+    Voter,
+}
+
+impl Block {
+    /// Whether the content of the given type matches the root in the header. The content of the
+    /// other types is not looked at.
+    pub fn verify_content(&self, kind: BlockKind) -> bool {
+        let root = match kind {
+            BlockKind::Proposer => self.txPointer.root(),
+            BlockKind::Transaction => self.content.root(),
+            BlockKind::Voter => self.votes.root(),
+        };
+        root == self.header.content_root(kind)
+    }
+
+    /// The block with only the content of the given type.
+    pub fn for_kind(&self, kind: BlockKind) -> Block {
+        Block {
+            header: self.header.clone(),
+            txPointer: if kind == BlockKind::Proposer { self.txPointer.clone() } else { TxPointer::default() },
+            content: if kind == BlockKind::Transaction { self.content.clone() } else { Content::default() },
+            votes: if kind == BlockKind::Voter { self.votes.clone() } else { Votes::default() },
+        }
+    }
 }
 
 impl Hashable for Block {
@@ -62,10 +164,51 @@ pub mod test {
         let n1: u32 = rng.gen();
         let n2: u128 = rng.gen();
         let data = vec![];
-        let merkle_root = MerkleTree::new(&data).root();
-        let header:Header = Header{parent:*parent,nonce:n1,difficulty:*parent,timestamp:n2,merkle_root:merkle_root};
         let content:Content = Content{data:data};
-        let block: Block = Block{header: header, content: content};
+        let txPointer:TxPointer = TxPointer{tp:vec![]};
+        let votes = Votes{pr:vec![]};
+        let header:Header = Header{parent:*parent,nonce:n1,difficultyForPr:*parent,difficultyForTx:*parent,difficultyForVoter:*parent,timestamp:n2,
+                                   tx_root:content.root(),pointer_root:txPointer.root(),vote_root:votes.root(),state_root:H256::default()};
+        let block: Block = Block{header: header, txPointer: txPointer, content: content, votes: votes};
         return block;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test::generate_random_block;
+
+    #[test]
+    fn sortition_ranges() {
+        let mut header = generate_random_block(&[0u8; 32].into()).header;
+        header.difficultyForPr = hex!("0001ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff").into();
+        header.difficultyForTx = hex!("0010ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff").into();
+        header.difficultyForVoter = hex!("0018ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff").into();
+        let hash = |h: [u8; 32]| -> H256 { h.into() };
+        assert_eq!(header.sortition(&hash(hex!("0001ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"))), Some(BlockKind::Proposer));
+        assert_eq!(header.sortition(&hash(hex!("0002000000000000000000000000000000000000000000000000000000000000"))), Some(BlockKind::Transaction));
+        assert_eq!(header.sortition(&hash(hex!("0011000000000000000000000000000000000000000000000000000000000000"))), Some(BlockKind::Voter));
+        assert_eq!(header.sortition(&hash(hex!("0019000000000000000000000000000000000000000000000000000000000000"))), None);
+    }
+
+    #[test]
+    fn content_per_kind() {
+        let mut block = generate_random_block(&[0u8; 32].into());
+        block.txPointer.tp.push([1u8; 32].into());
+        block.votes.pr.push([2u8; 32].into());
+        block.header.pointer_root = block.txPointer.root();
+        block.header.vote_root = block.votes.root();
+
+        let proposer = block.for_kind(BlockKind::Proposer);
+        assert_eq!(proposer.hash(), block.hash());
+        assert_eq!(proposer.votes.pr.len(), 0);
+        assert!(proposer.verify_content(BlockKind::Proposer));
+        assert!(!proposer.verify_content(BlockKind::Voter));
+
+        let voter = block.for_kind(BlockKind::Voter);
+        assert_eq!(voter.txPointer.tp.len(), 0);
+        assert!(voter.verify_content(BlockKind::Voter));
+        assert!(voter.verify_content(BlockKind::Transaction));
+    }
+}
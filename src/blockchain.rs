@@ -1,15 +1,21 @@
-use crate::block::{Block,Header,Content,TxPointer};
+use crate::block::{Block,Header,Content,TxPointer,Votes};
 use crate::crypto::hash::H256;
-use std::collections::HashMap;
-use crate::crypto::merkle::{MerkleTree};
+use std::collections::{HashMap, HashSet};
 use crate::crypto::hash::Hashable;
+use crate::crypto::sparse_merkle::EMPTY_ROOT;
 
 #[derive(Debug, Default, Clone)]
 pub struct Blockchain {
     pub blocks:HashMap<H256, Block>,
     pub height:HashMap<H256, u32>,
     pub last_block_of_longest_chain: H256,
-    pub genesis:H256
+    pub genesis:H256,
+    pub voter_blocks:HashMap<H256, Block>,
+    /// Number of votes each proposer block received
+    pub votes:HashMap<H256, u32>,
+    /// Proposer blocks known by their header only, restored from a snapshot. Their content is
+    /// unknown, so they are never sent to peers.
+    pub headers_only:HashSet<H256>,
 }
 
 impl Blockchain {
@@ -17,45 +23,40 @@ impl Blockchain {
     pub fn new() -> Self {
         let mut blocks: HashMap<H256,Block> = HashMap::new();
         let mut height: HashMap<H256,u32> = HashMap::new();
-        let data = vec![];
-        let pointer = vec![];
-        let merkle_root = MerkleTree::new(&data).root();
+        let content:Content = Content{data:vec![]};
+        let txPointer:TxPointer = TxPointer{tp:vec![]};
+        let votes:Votes = Votes{pr:vec![]};
+        let merkle_root = content.root();
         // let t1 = [255; 16];
         // let t2 = [0; 8];
         let mut difficultyForPr: [u8; 32] = [0,1,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255];
         let mut difficultyForTx: [u8; 32] = [0,16,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255];
+        let mut difficultyForVoter: [u8; 32] = [0,24,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255,255];
         // difficulty.copy_from_slice(&([t2,t1].concat())[..]);
         let header:Header = Header{parent:merkle_root,
                                    nonce:0,
                                    difficultyForPr:difficultyForPr.into(),
                                    difficultyForTx:difficultyForTx.into(),
+                                   difficultyForVoter:difficultyForVoter.into(),
                                    timestamp:0,
-                                   merkle_root:merkle_root
+                                   tx_root:content.root(),
+                                   pointer_root:txPointer.root(),
+                                   vote_root:votes.root(),
+                                   // the genesis state has no accounts
+                                   state_root:(&EMPTY_ROOT).into(),
                                 };
-        let content:Content = Content{data:data};
-        let txPointer:TxPointer = TxPointer{tp:pointer};
-        let genesis: Block = Block{header: header, txPointer: txPointer, content: content};
+        let genesis: Block = Block{header: header, txPointer: txPointer, content: content, votes: votes};
         let hash = genesis.hash();
         blocks.insert(hash,genesis);
         height.insert(hash,0);
-        return Blockchain{blocks:blocks,height:height,last_block_of_longest_chain:hash,genesis:hash};
+        return Blockchain{blocks:blocks,height:height,last_block_of_longest_chain:hash,genesis:hash,voter_blocks:HashMap::new(),votes:HashMap::new(),headers_only:HashSet::new()};
     }
 
     /// Insert a block into blockchain
     pub fn insert(&mut self, block: &Block) {
         let last = block.header.parent;
         let h = self.height[&last];
-        self.blocks.insert( block.hash(),
-                            Block{header:Header{parent:last,
-                                                nonce:block.header.nonce,
-                                                difficultyForPr:block.header.difficultyForPr,
-                                                difficultyForTx:block.header.difficultyForTx,
-                                                timestamp:block.header.timestamp,
-                                                merkle_root:block.header.merkle_root
-                                            },
-                                  content:Content{data:(&block.content.data).to_vec()},
-                                  txPointer:TxPointer{tp:(&block.txPointer.tp).to_vec()}
-                                });
+        self.blocks.insert(block.hash(), block.clone());
         self.height.insert(block.hash(),h+1);
         // The state of (tip of) longest chain is updated as longest chain grows. CODE
         // The state is reverted when a fork becomes the new longest chain. CODE
@@ -64,11 +65,61 @@ impl Blockchain {
         }
     }
 
+    /// Insert a proposer block known by its header only
+    pub fn insert_header(&mut self, header: &Header) {
+        let block = Block{header: header.clone(), txPointer: TxPointer::default(), content: Content::default(), votes: Votes::default()};
+        self.insert(&block);
+        self.headers_only.insert(block.hash());
+    }
+
     /// Get the last block's hash of the longest chain
     pub fn tip(&self) -> H256 {
         return self.last_block_of_longest_chain;
     }
 
+    /// Insert a voter block and count its votes
+    pub fn insert_voter(&mut self, block: &Block) {
+        for pr in block.votes.pr.iter() {
+            *self.votes.entry(*pr).or_insert(0) += 1;
+        }
+        self.voter_blocks.insert(block.hash(), block.clone());
+    }
+
+    /// Proposer blocks of the longest chain after the last one that has a vote, oldest first and
+    /// at most `limit` of them. These are what the next voter block votes for.
+    pub fn unvoted(&self, limit: usize) -> Vec<H256> {
+        let mut unvoted = vec![];
+        let mut curBlock = self.last_block_of_longest_chain;
+        while curBlock != self.genesis && !self.votes.contains_key(&curBlock) {
+            unvoted.push(curBlock);
+            curBlock = self.blocks[&curBlock].header.parent;
+        }
+        unvoted.reverse();
+        unvoted.truncate(limit);
+        return unvoted;
+    }
+
+    /// Number of blocks of the chain ending at `old_tip` that are not on the chain ending at
+    /// `new_tip`, i.e. how many blocks a switch from `old_tip` to `new_tip` rolls back.
+    pub fn reorg_depth(&self, old_tip: &H256, new_tip: &H256) -> u32 {
+        let mut old = *old_tip;
+        let mut new = *new_tip;
+        let mut depth = 0;
+        while self.height[&new] > self.height[&old] {
+            new = self.blocks[&new].header.parent;
+        }
+        while self.height[&old] > self.height[&new] {
+            old = self.blocks[&old].header.parent;
+            depth += 1;
+        }
+        while old != new {
+            old = self.blocks[&old].header.parent;
+            new = self.blocks[&new].header.parent;
+            depth += 1;
+        }
+        return depth;
+    }
+
     /// Get the last block's hash of the longest chain
     #[cfg(any(test, test_utilities))]
     pub fn all_blocks_in_longest_chain(&self) -> Vec<H256> {
@@ -99,4 +150,23 @@ mod tests {
         assert_eq!(blockchain.tip(), block.hash());
 
     }
+
+    #[test]
+    fn reorg_depth() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let a1 = generate_random_block(&genesis_hash);
+        blockchain.insert(&a1);
+        let a2 = generate_random_block(&a1.hash());
+        blockchain.insert(&a2);
+        let b1 = generate_random_block(&genesis_hash);
+        blockchain.insert(&b1);
+        let b2 = generate_random_block(&b1.hash());
+        blockchain.insert(&b2);
+        let b3 = generate_random_block(&b2.hash());
+        blockchain.insert(&b3);
+        assert_eq!(blockchain.tip(), b3.hash());
+        assert_eq!(blockchain.reorg_depth(&a2.hash(), &b3.hash()), 2);
+        assert_eq!(blockchain.reorg_depth(&b2.hash(), &b3.hash()), 0);
+    }
 }
@@ -0,0 +1,220 @@
+//! The transaction blocks a proposer block and its ancestors confirmed, in order, indexed by hash.
+//!
+//! The state of every proposer block has its own, starting as a clone of its parent's. A clone
+//! costs O(1) and shares its storage with the original: the ordered list is a chain of segments,
+//! one per proposer block, linked from the newest to the oldest, and the index is a persistent
+//! hash trie, 16 children per node, branching on the hash nibble by nibble, where confirming a
+//! transaction block copies only the nodes on the path of its hash. Forks thus share everything
+//! before the block they fork at.
+
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::sync::Arc;
+use crate::crypto::hash::H256;
+
+/// Where a transaction block was first confirmed on a fork: the height of the proposer block, the
+/// one block at that height among the ancestors of the state, and the position of the
+/// transaction block in the ordered list.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Confirmation {
+    pub height: u32,
+    pub position: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(into = "Vec<(H256, u32)>", from = "Vec<(H256, u32)>")]
+pub struct Confirmations {
+    index: Option<Arc<Node>>,
+    last: Option<Arc<Segment>>,
+    len: u32,
+}
+
+#[derive(Clone)]
+enum Node {
+    Leaf(H256, Confirmation),
+    Branch([Option<Arc<Node>>; 16]),
+}
+
+/// The transaction blocks confirmed at one height, after those of `prev`.
+struct Segment {
+    height: u32,
+    tx_blocks: Vec<H256>,
+    prev: Option<Arc<Segment>>,
+}
+
+impl Confirmations {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The number of confirmed transaction blocks.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, tx_block: &H256) -> bool {
+        self.get(tx_block).is_some()
+    }
+
+    pub fn get(&self, tx_block: &H256) -> Option<Confirmation> {
+        let mut node = self.index.as_ref();
+        let mut depth = 0;
+        while let Some(n) = node {
+            match &**n {
+                Node::Leaf(key, confirmation) => return if key == tx_block { Some(*confirmation) } else { None },
+                Node::Branch(children) => node = children[nibble(tx_block, depth)].as_ref(),
+            }
+            depth += 1;
+        }
+        None
+    }
+
+    /// Append `tx_block`, confirmed by the proposer block at `height`, unless it is confirmed
+    /// already. Returns whether it was appended.
+    pub fn confirm(&mut self, tx_block: H256, height: u32) -> bool {
+        if self.contains(&tx_block) {
+            return false;
+        }
+        let confirmation = Confirmation { height, position: self.len };
+        self.index = Some(insert(self.index.as_ref(), tx_block, confirmation, 0));
+        // the last segment is only extended while no clone shares it
+        let extended = match self.last.as_mut().and_then(Arc::get_mut) {
+            Some(segment) if segment.height == height => {
+                segment.tx_blocks.push(tx_block);
+                true
+            }
+            _ => false,
+        };
+        if !extended {
+            self.last = Some(Arc::new(Segment { height, tx_blocks: vec![tx_block], prev: self.last.take() }));
+        }
+        self.len += 1;
+        true
+    }
+
+    /// The confirmed transaction blocks, the last one first.
+    pub fn iter_rev(&self) -> impl Iterator<Item = &H256> {
+        std::iter::successors(self.last.as_deref(), |s| s.prev.as_deref()).flat_map(|s| s.tx_blocks.iter().rev())
+    }
+
+    /// The confirmed transaction blocks, in order.
+    pub fn to_vec(&self) -> Vec<H256> {
+        let mut list: Vec<H256> = self.iter_rev().copied().collect();
+        list.reverse();
+        list
+    }
+}
+
+/// The trie `node` at `depth` with `key` added, `key` is not in it.
+fn insert(node: Option<&Arc<Node>>, key: H256, confirmation: Confirmation, depth: usize) -> Arc<Node> {
+    let node = match node {
+        None => return Arc::new(Node::Leaf(key, confirmation)),
+        Some(node) => node,
+    };
+    let mut children = match &**node {
+        Node::Branch(children) => children.clone(),
+        // the leaf moves one level down, next to `key` if they share the nibble
+        Node::Leaf(other, _) => {
+            let mut children: [Option<Arc<Node>>; 16] = Default::default();
+            children[nibble(other, depth)] = Some(Arc::clone(node));
+            children
+        }
+    };
+    let i = nibble(&key, depth);
+    children[i] = Some(insert(children[i].as_ref(), key, confirmation, depth + 1));
+    Arc::new(Node::Branch(children))
+}
+
+/// Nibble `depth` of `key`, from the most significant one.
+fn nibble(key: &H256, depth: usize) -> usize {
+    let byte = key.as_ref()[depth / 2];
+    (if depth % 2 == 0 { byte >> 4 } else { byte & 0x0f }) as usize
+}
+
+impl Drop for Segment {
+    // unlink the chain one segment at a time, dropping it recursively could overflow the stack
+    fn drop(&mut self) {
+        let mut prev = self.prev.take();
+        while let Some(segment) = prev {
+            prev = match Arc::try_unwrap(segment) {
+                Ok(mut segment) => segment.prev.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+impl fmt::Debug for Confirmations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.to_vec()).finish()
+    }
+}
+
+impl From<Confirmations> for Vec<(H256, u32)> {
+    fn from(confirmations: Confirmations) -> Self {
+        let mut list: Vec<(H256, u32)> = std::iter::successors(confirmations.last.as_deref(), |s| s.prev.as_deref())
+            .flat_map(|s| s.tx_blocks.iter().rev().map(move |h| (*h, s.height)))
+            .collect();
+        list.reverse();
+        list
+    }
+}
+
+impl From<Vec<(H256, u32)>> for Confirmations {
+    fn from(list: Vec<(H256, u32)>) -> Self {
+        let mut confirmations = Confirmations::new();
+        for (tx_block, height) in list {
+            confirmations.confirm(tx_block, height);
+        }
+        confirmations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn forks_share_and_diverge() {
+        let mut rng = rand::thread_rng();
+        let hashes: Vec<H256> = (0..500).map(|_| (&rng.gen::<[u8; 32]>()).into()).collect();
+        let mut chain = Confirmations::new();
+        for (i, h) in hashes[..300].iter().enumerate() {
+            assert!(chain.confirm(*h, 1 + i as u32 / 10));
+        }
+        assert!(!chain.confirm(hashes[0], 31));
+        assert_eq!(chain.get(&hashes[25]), Some(Confirmation { height: 3, position: 25 }));
+
+        // two forks from the same parent
+        let mut a = chain.clone();
+        let mut b = chain.clone();
+        for h in hashes[300..400].iter() {
+            a.confirm(*h, 31);
+        }
+        for h in hashes[350..500].iter() {
+            b.confirm(*h, 31);
+        }
+        assert_eq!((chain.len(), a.len(), b.len()), (300, 400, 450));
+        assert!(!chain.contains(&hashes[300]) && a.contains(&hashes[300]) && !b.contains(&hashes[300]));
+        assert!(!a.contains(&hashes[450]) && b.contains(&hashes[450]));
+        assert_eq!(a.get(&hashes[360]).unwrap().position, 360);
+        assert_eq!(b.get(&hashes[360]).unwrap().position, 310);
+        assert_eq!(a.to_vec(), hashes[..400].to_vec());
+        assert_eq!(b.iter_rev().next(), Some(&hashes[499]));
+        assert_eq!(chain.to_vec(), hashes[..300].to_vec());
+        for h in hashes[..300].iter() {
+            assert_eq!(a.get(h), chain.get(h));
+            assert_eq!(b.get(h), chain.get(h));
+        }
+
+        // serialized as the ordered list with the heights
+        let decoded: Confirmations = bincode::deserialize(&bincode::serialize(&b).unwrap()).unwrap();
+        assert_eq!(decoded.to_vec(), b.to_vec());
+        assert_eq!(decoded.get(&hashes[499]), b.get(&hashes[499]));
+    }
+}
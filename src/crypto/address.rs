@@ -1,7 +1,7 @@
 use serde::{Serialize, Deserialize};
 use std::fmt;
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq, Hash, Copy)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Eq, PartialEq, Hash, Copy, PartialOrd, Ord)]
 pub struct H160([u8; 20]);
 
 impl std::convert::From<&[u8]> for H160 {
@@ -20,4 +20,41 @@ impl std::fmt::Display for H160 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "H160({},{},{},{})", &self.0[0], &self.0[1], &self.0[2], &self.0[3])
     }
-}
\ No newline at end of file
+}
+
+impl std::fmt::LowerHex for H160 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+impl std::convert::AsRef<[u8]> for H160 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Parse an address from its 40 hex digit form (as printed by `{:x}`).
+impl std::str::FromStr for H160 {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<H160, Self::Err> {
+        let mut buffer: [u8; 20] = [0; 20];
+        hex::decode_to_slice(s, &mut buffer)?;
+        Ok(H160(buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::H160;
+
+    #[test]
+    fn hex_roundtrip() {
+        let address: H160 = (&b"some public key"[..]).into();
+        let printed = format!("{:x}", address);
+        assert_eq!(printed.len(), 40);
+        assert_eq!(printed.parse::<H160>().unwrap(), address);
+        assert!("not an address".parse::<H160>().is_err());
+    }
+}
@@ -87,6 +87,17 @@ impl std::convert::From<ring::digest::Digest> for H256 {
     }
 }
 
+/// Parse a hash from its 64 hex digit form (as printed by `{}`).
+impl std::str::FromStr for H256 {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<H256, Self::Err> {
+        let mut buffer: [u8; 32] = [0; 32];
+        hex::decode_to_slice(s, &mut buffer)?;
+        Ok(H256(buffer))
+    }
+}
+
 impl Ord for H256 {
     fn cmp(&self, other: &H256) -> std::cmp::Ordering {
         let self_higher = u128::from_be_bytes(self.0[0..16].try_into().unwrap());
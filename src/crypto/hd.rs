@@ -0,0 +1,108 @@
+use ring::hmac;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+/// Index offset of hardened children. Ed25519 (SLIP-0010) only supports hardened derivation, so
+/// every index passed to `ExtendedKey::child` is hardened.
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// BIP-44 purpose and the coin type used for wallet accounts, i.e. accounts live at
+/// m/44'/1'/account'.
+const PURPOSE: u32 = 44;
+const COIN_TYPE: u32 = 1;
+
+/// DER prefix of an Ed25519 PKCS#8 v2 document (RFC 5958), followed by the 32 byte seed, the
+/// public key tag and the 32 byte public key. This is the layout `Ed25519KeyPair::generate_pkcs8`
+/// produces, so derived keys can be stored exactly like random ones.
+const PKCS8_PREFIX: [u8; 16] = [0x30, 0x53, 0x02, 0x01, 0x01, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20];
+const PKCS8_PUBLIC_KEY_TAG: [u8; 5] = [0xa1, 0x23, 0x03, 0x21, 0x00];
+
+/// A SLIP-0010 extended private key for Ed25519.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedKey {
+    pub key: [u8; 32],
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    /// The master key of a seed, e.g. the 64 byte seed of a BIP-39 mnemonic.
+    pub fn master(seed: &[u8]) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA512, b"ed25519 seed");
+        split(hmac::sign(&key, seed).as_ref())
+    }
+
+    /// The hardened child at `index` (`HARDENED` is added if missing).
+    pub fn child(&self, index: u32) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA512, &self.chain_code);
+        let mut ctx = hmac::Context::with_key(&key);
+        ctx.update(&[0]);
+        ctx.update(&self.key);
+        ctx.update(&(index | HARDENED).to_be_bytes());
+        split(ctx.sign().as_ref())
+    }
+
+    /// Follow a derivation path of (hardened) indices.
+    pub fn derive(&self, path: &[u32]) -> Self {
+        let mut key = self.clone();
+        for index in path {
+            key = key.child(*index);
+        }
+        key
+    }
+
+    /// The key of wallet account `account`, m/44'/1'/account'.
+    pub fn account(seed: &[u8], account: u32) -> Self {
+        Self::master(seed).derive(&[PURPOSE, COIN_TYPE, account])
+    }
+
+    pub fn key_pair(&self) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&self.key).unwrap()
+    }
+
+    /// The derived key as a PKCS#8 v2 document.
+    pub fn pkcs8(&self) -> Vec<u8> {
+        let public_key = self.key_pair().public_key().as_ref().to_vec();
+        let mut document = PKCS8_PREFIX.to_vec();
+        document.extend_from_slice(&self.key);
+        document.extend_from_slice(&PKCS8_PUBLIC_KEY_TAG);
+        document.extend_from_slice(&public_key);
+        document
+    }
+}
+
+fn split(i: &[u8]) -> ExtendedKey {
+    let mut key = [0u8; 32];
+    let mut chain_code = [0u8; 32];
+    key.copy_from_slice(&i[..32]);
+    chain_code.copy_from_slice(&i[32..]);
+    ExtendedKey { key, chain_code }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key_pair;
+
+    // SLIP-0010 test vector 1 for ed25519
+    #[test]
+    fn slip10_vector() {
+        let seed = hex!("000102030405060708090a0b0c0d0e0f");
+        let master = ExtendedKey::master(&seed);
+        assert_eq!(master.chain_code, hex!("90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"));
+        assert_eq!(master.key, hex!("2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"));
+        assert_eq!(
+            master.key_pair().public_key().as_ref(),
+            &hex!("a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed")[..]
+        );
+        let child = master.child(0);
+        assert_eq!(child.chain_code, hex!("8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69"));
+        assert_eq!(child.key, hex!("68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"));
+        assert_eq!(master.derive(&[HARDENED]), child);
+    }
+
+    #[test]
+    fn pkcs8_loads() {
+        let key = ExtendedKey::account(&[7u8; 64], 3);
+        let loaded = key_pair::from_pkcs8(&key.pkcs8()).unwrap();
+        assert_eq!(loaded.public_key().as_ref(), key.key_pair().public_key().as_ref());
+    }
+}
@@ -3,7 +3,17 @@ use ring::signature::Ed25519KeyPair;
 
 /// Generate a random key pair.
 pub fn random() -> Ed25519KeyPair {
+    from_pkcs8(&random_pkcs8()).unwrap()
+}
+
+/// Generate a random key pair, serialized as a PKCS#8 document so that it can be stored.
+pub fn random_pkcs8() -> Vec<u8> {
     let rng = rand::SystemRandom::new();
     let pkcs8_bytes = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
-    Ed25519KeyPair::from_pkcs8(pkcs8_bytes.as_ref().into()).unwrap()
+    pkcs8_bytes.as_ref().to_vec()
+}
+
+/// Load a key pair from a PKCS#8 document.
+pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Ed25519KeyPair, ring::error::KeyRejected> {
+    Ed25519KeyPair::from_pkcs8(pkcs8)
 }
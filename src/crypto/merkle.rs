@@ -1,283 +1,203 @@
+//! Merkle trees over the content of blocks.
+//!
+//! Layout: level 0 holds a node per leaf in order, the SHA256 of `0x00` and the hash of the leaf.
+//! Every following level pairs the nodes of the level below from the left, a parent being the
+//! SHA256 of `0x01`, the left child and the right child. A level with an odd number of nodes
+//! pairs its last node with itself, as Bitcoin does. The top level holds a single node, and the
+//! root is the SHA256 of `0x02`, the number of leaves as a big-endian u64 and that node. The root
+//! of no leaves is `EMPTY_ROOT`.
+//!
+//! The prefixes keep a leaf from passing for an inner node, and as the root commits to the
+//! number of leaves, `[a, b, c]` and `[a, b, c, c]` have different roots even though the last
+//! node is paired with itself. Proofs carry the number of leaves too.
+
+use serde::{Serialize, Deserialize};
 use super::hash::{Hashable, H256};
 use ring::digest;
 
+/// The root of a tree without leaves.
+pub const EMPTY_ROOT: [u8; 32] = *b"00000000000000000000000000000000";
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+const SIZE: u8 = 2;
+
 /// A Merkle tree.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct MerkleTree {
-    hash_idx: Vec<H256>,
+    /// `levels[0]` are the leaf nodes and the last level is the top node alone, no levels without
+    /// leaves.
+    levels: Vec<Vec<H256>>,
+}
+
+/// Proves that a leaf hash is at `index` among `size` leaves under a root. The siblings go from
+/// the leaf level up, leaving out the levels where the node is paired with itself.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    pub index: usize,
+    pub size: usize,
+    pub siblings: Vec<H256>,
+}
+
+/// Proves several leaves at once, sharing the nodes their paths have in common. `indices`
+/// increase; `hashes` are the nodes the verifier cannot compute from the leaves, level by level
+/// from the leaves up and from left to right within a level.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MultiProof {
+    pub indices: Vec<usize>,
+    pub size: usize,
+    pub hashes: Vec<H256>,
 }
 
 impl MerkleTree {
     pub fn new<T>(data: &[T]) -> Self where T: Hashable, {
-        let leaf_size = data.len();
-        // let mut temp: Vec<MerkleNode> = [].to_vec();
-        let mut temp: Vec<H256> = [].to_vec();
-        // let mut high: Vec<MerkleNode> = [].to_vec();
-        if leaf_size == 0{
-            let fake_data: H256 = (b"00000000000000000000000000000000").into();
-            temp.push(fake_data);
-        }
-        else if leaf_size == 1 {
-            temp.push(data[0].hash());
+        let mut levels: Vec<Vec<H256>> = vec![];
+        let mut level: Vec<H256> = data.iter().map(|d| leaf(&d.hash())).collect();
+        while level.len() > 1 {
+            let parents = level.chunks(2).map(|pair| parent(&pair[0], pair.last().unwrap())).collect();
+            levels.push(level);
+            level = parents;
         }
-        else{
-            for count in 0..leaf_size {
-                // temp.push(MerkleNode{value: data[count].hash(), l: None, r: None});
-                temp.push(data[count].hash());
-            }
-            // duplicate
-            let mut count = 1usize;
-            loop{
-                if count < leaf_size{
-                    count *= 2;
-                }
-                else{
-                    break;
-                }
-            }
-            while temp.len() < count {
-                let left_length = temp.len() - count/2;  //1
-                let mut inner_count = 2usize;
-                loop{
-                    if inner_count < left_length{
-                        inner_count *= 2;
-                    }
-                    else{
-                        break;
-                    }
-                }
-                let mut append_length = 0;
-                if inner_count == left_length{
-                    append_length = inner_count;
-                }
-                else{
-                    append_length = inner_count - left_length;
-                }
-                let mut appendVec: Vec<H256> = [].to_vec();
-                // for idx in (count/2)..(count/2 + append_length){
-                for idx in (temp.len()-append_length)..(temp.len()){
-                    appendVec.push(temp[idx]);
-                }
-                temp.extend(appendVec.iter().copied());
-            }
-            // after duplicate
-            let mut level_len = temp.len();
-            let mut anchor = 0;
-            while level_len > 1 {
-                for idx in 0..(level_len/2){
-                    let mut ctx = digest::Context::new(&digest::SHA256);
-                    ctx.update(&(<[u8; 32]>::from(temp[2*idx+anchor])));
-                    ctx.update(&(<[u8; 32]>::from(temp[2*idx+anchor+1])));
-                    let multi_part = ctx.finish();
-                    temp.push(<H256>::from(multi_part));
-                }
-                anchor += level_len;
-                level_len /= 2;
-            }
+        if !level.is_empty() {
+            levels.push(level);
         }
-        let tree_root = temp[temp.len()-1];
-        let tree = MerkleTree{hash_idx: temp};
-        tree
-        // unimplemented!()
+        MerkleTree { levels }
+    }
 
-        // let mut hash_index = vec![];
-        // let mut num = data.len();
-        // let mut base = 0;
-        // for i in 0..num {
-        //     hash_index.push(data[i].hash());
-        // }
-        // let mut p = 0;
-        // let n:usize = 2;
-        // while num > 1 {
-        //     if num % 2 == 1 {
-        //         let len = hash_index.len();
-        //         for k in len-n.pow(p)..len {
-        //             hash_index.push(hash_index[k]);
-        //         num += 1;
-        //         }
-        //     }
-        //     num /= 2;
-        //     p += 1;
-        // }
-        // num = hash_index.len();
-        // while num > 1 {
-        //     for i in 0..num/2 {
-        //         let mut ctx = digest::Context::new(&digest::SHA256);
-        //         ctx.update(&<[u8;32]>::from(&hash_index[base+i*2]));
-        //         ctx.update(&<[u8;32]>::from(&hash_index[base+i*2+1]));
-        //         hash_index.push((ctx.finish()).into());
-        //     }
-        //     base = base + num;
-        //     num = num/2;
-        //     if num % 2 > 0 && num > 1 {
-        //         hash_index.push(hash_index[base+num-1]);
-        //         num = num + 1;
-        //     }
-        // }
+    pub fn root(&self) -> H256 {
+        match self.levels.last() {
+            Some(top) => sized(self.len(), &top[0]),
+            None => (&EMPTY_ROOT).into(),
+        }
+    }
 
-        // hash_index.reverse();
-        // return MerkleTree{hash_idx:hash_index};
+    /// The number of leaves.
+    pub fn len(&self) -> usize {
+        self.levels.first().map_or(0, |leaves| leaves.len())
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
 
+    /// Returns the Merkle proof of the leaf at `index`, if there is one.
+    pub fn proof(&self, index: usize) -> Option<Proof> {
+        let proof = self.multiproof(&[index])?;
+        Some(Proof { index, size: proof.size, siblings: proof.hashes })
+    }
 
-pub fn new1<T>(data: &[T], txPointer: &[H256]) -> Self where T: Hashable {
-        let leaf_size = data.len() + txPointer.len();
-        // let mut temp: Vec<MerkleNode> = [].to_vec();
-        let mut temp: Vec<H256> = [].to_vec();
-        // let mut high: Vec<MerkleNode> = [].to_vec();
-        if leaf_size == 0{
-            let fake_data: H256 = (b"00000000000000000000000000000000").into();
-            temp.push(fake_data);
+    /// Returns one proof for the leaves at `indices`, in any order, if there are such leaves.
+    pub fn multiproof(&self, indices: &[usize]) -> Option<MultiProof> {
+        let mut known = indices.to_vec();
+        known.sort();
+        known.dedup();
+        if known.is_empty() || *known.last().unwrap() >= self.len() {
+            return None;
         }
-        else if leaf_size == 1 {
-            if data.len() == 1{
-                temp.push(data[0].hash());
-            }
-            else{
-                temp.push(txPointer[0].hash());
-            }
-            
-        }
-        else{
-            for count in 0..data.len() {
-                // temp.push(MerkleNode{value: data[count].hash(), l: None, r: None});
-                temp.push(data[count].hash());
-            }
-            for count in 0..txPointer.len() {
-                // temp.push(MerkleNode{value: data[count].hash(), l: None, r: None});
-                temp.push(txPointer[count].hash());
-            }
-            // duplicate
-            let mut count = 1usize;
-            loop{
-                if count < leaf_size{
-                    count *= 2;
-                }
-                else{
-                    break;
-                }
-            }
-            while temp.len() < count {
-                let left_length = temp.len() - count/2;  //1
-                let mut inner_count = 2usize;
-                loop{
-                    if inner_count < left_length{
-                        inner_count *= 2;
-                    }
-                    else{
-                        break;
-                    }
-                }
-                let mut append_length = 0;
-                if inner_count == left_length{
-                    append_length = inner_count;
-                }
-                else{
-                    append_length = inner_count - left_length;
-                }
-                let mut appendVec: Vec<H256> = [].to_vec();
-                // for idx in (count/2)..(count/2 + append_length){
-                for idx in (temp.len()-append_length)..(temp.len()){
-                    appendVec.push(temp[idx]);
-                }
-                temp.extend(appendVec.iter().copied());
-            }
-            // after duplicate
-            let mut level_len = temp.len();
-            let mut anchor = 0;
-            while level_len > 1 {
-                for idx in 0..(level_len/2){
-                    let mut ctx = digest::Context::new(&digest::SHA256);
-                    ctx.update(&(<[u8; 32]>::from(temp[2*idx+anchor])));
-                    ctx.update(&(<[u8; 32]>::from(temp[2*idx+anchor+1])));
-                    let multi_part = ctx.finish();
-                    temp.push(<H256>::from(multi_part));
+        let proven = known.clone();
+        let mut hashes = vec![];
+        for level in &self.levels[..self.levels.len() - 1] {
+            let mut parents = vec![];
+            let mut i = 0;
+            while i < known.len() {
+                let position = known[i];
+                let sibling = position ^ 1;
+                if position % 2 == 0 && known.get(i + 1) == Some(&sibling) {
+                    i += 1;
+                } else if sibling < level.len() {
+                    hashes.push(level[sibling]);
                 }
-                anchor += level_len;
-                level_len /= 2;
+                parents.push(position / 2);
+                i += 1;
             }
+            known = parents;
         }
-        let tree_root = temp[temp.len()-1];
-        let tree = MerkleTree{hash_idx: temp};
-        tree
-        // unimplemented!()
+        Some(MultiProof { indices: proven, size: self.len(), hashes })
     }
+}
 
+/// Verify that `datum`, the hash of a leaf, is where `proof` says under `root`.
+pub fn verify(root: &H256, datum: &H256, proof: &Proof) -> bool {
+    if proof.index >= proof.size {
+        return false;
+    }
+    fold(vec![(proof.index, *datum)], proof.size, &proof.siblings) == Some(*root)
+}
 
+/// Verify that `leaves`, the hashes of the leaves at `proof.indices` in that order, are under
+/// `root`.
+pub fn verify_multi(root: &H256, leaves: &[H256], proof: &MultiProof) -> bool {
+    let indices = &proof.indices;
+    if indices.is_empty() || indices.len() != leaves.len() || indices.windows(2).any(|w| w[0] >= w[1]) || *indices.last().unwrap() >= proof.size {
+        return false;
+    }
+    let known = indices.iter().copied().zip(leaves.iter().copied()).collect();
+    fold(known, proof.size, &proof.hashes) == Some(*root)
+}
 
-    pub fn root(&self) -> H256 {
-        if self.hash_idx.len() == 0{
-            return (b"00000000000000000000000000000000").into();
+/// Compute the root from the nodes `known` at increasing positions of the leaf level of a tree
+/// of `size` leaves and the other nodes `hashes` in proof order. None if `hashes` are too few or
+/// too many.
+fn fold(known: Vec<(usize, H256)>, size: usize, hashes: &[H256]) -> Option<H256> {
+    let mut known: Vec<(usize, H256)> = known.into_iter().map(|(position, datum)| (position, leaf(&datum))).collect();
+    let mut hashes = hashes.iter();
+    let mut width = size;
+    while width > 1 {
+        let mut parents = vec![];
+        let mut i = 0;
+        while i < known.len() {
+            let (position, node) = known[i];
+            let hash = if position % 2 == 1 {
+                parent(hashes.next()?, &node)
+            } else if position + 1 == width {
+                parent(&node, &node)
+            } else if known.get(i + 1).map(|k| k.0) == Some(position + 1) {
+                i += 1;
+                parent(&node, &known[i].1)
+            } else {
+                parent(&node, hashes.next()?)
+            };
+            parents.push((position / 2, hash));
+            i += 1;
         }
-        return self.hash_idx[0];
+        known = parents;
+        width = (width + 1) / 2;
     }
-    /// Returns the Merkle Proof of data at index i
-    pub fn proof(&self, index: usize) -> Vec<H256> {
-        let mut res = vec![];
-        let len = self.hash_idx.len();
-        let mut pos = len - index;
-        while pos > 1 {
-            if pos % 2 == 0 {
-                res.push(self.hash_idx[pos]);
-            }
-            else {
-                res.push(self.hash_idx[pos-2]);
-            }
-            pos = pos / 2;
-        }
-        
-        return res;
+    if hashes.next().is_some() {
+        return None;
     }
+    Some(sized(size, &known[0].1))
 }
 
-/// Verify that the datum hash with a vector of proofs will produce the Merkle root. Also need the
-/// index of datum and `leaf_size`, the total number of leaves.
-pub fn verify(root: &H256, datum: &H256, proof: &[H256], index: usize, leaf_size: usize) -> bool {
-    let mut p = 1;
-    let n:usize = 2;
-    while n.pow(p)<leaf_size {
-        p+=1;
-    }
-    let mut len = n.pow(p);
-    let mut level = len/2;
-    while level > 0 {
-        len = len + level;
-        level = level / 2;
-    }
-    let mut pos = len - index;
-    let mut i = 0;
-    let mut res: H256 = *datum;
-    while pos > 1 {
-        if pos % 2 ==0 {
-            let mut ctx = digest::Context::new(&digest::SHA256);
-            ctx.update(&<[u8;32]>::from(&proof[i]));
-            ctx.update(&<[u8;32]>::from(&res));
-            res = (ctx.finish()).into();
-            i = i + 1;             
-        }
-        else {
-            let mut ctx = digest::Context::new(&digest::SHA256);
-            ctx.update(&<[u8;32]>::from(&res));
-            ctx.update(&<[u8;32]>::from(&proof[i]));            
-            res = (ctx.finish()).into();
-            i = i + 1;
-        }
-        pos = pos / 2;
-    }
+fn leaf(datum: &H256) -> H256 {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&[LEAF]);
+    ctx.update(datum.as_ref());
+    ctx.finish().into()
+}
 
-    if res == *root {
-        return true;
-    }
-    else {
-        return false;
-    }
+fn parent(left: &H256, right: &H256) -> H256 {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&[NODE]);
+    ctx.update(left.as_ref());
+    ctx.update(right.as_ref());
+    ctx.finish().into()
+}
+
+/// The root over the top node of a tree of `size` leaves.
+fn sized(size: usize, top: &H256) -> H256 {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&[SIZE]);
+    ctx.update(&(size as u64).to_be_bytes());
+    ctx.update(top.as_ref());
+    ctx.finish().into()
 }
 
 #[cfg(test)]
 mod tests {
     use crate::crypto::hash::H256;
     use super::*;
+    use rand::Rng;
 
     macro_rules! gen_merkle_tree_data {
         () => {{
@@ -298,37 +218,130 @@ mod tests {
         let root = merkle_tree.root();
         assert_eq!(
             root,
-            //(hex!("6b787718210e0b3b608814e04e61fde06d0df794319a12162f287412df3ec920")).into()
-            (hex!("f0de10bbc0838878b0c4175d9a9d900dc5fc26dfbc0bc182e629d197b3b32c5f")).into()
+            (hex!("655ba03f839a10dc54c7175816a9c9117e8e547524d9b65b0fb452a097ada5f5")).into()
         );
         // "b69566be6e1720872f73651d1851a0eae0060a132cf0f64a0ffaea248de6cba0" is the hash of
-        // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d"
-        // "965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f" is the hash of
-        // "0101010101010101010101010101010101010101010101010101010101010202"
-        // "6b787718210e0b3b608814e04e61fde06d0df794319a12162f287412df3ec920" is the hash of
-        // the concatenation of these two hashes "b69..." and "965..."
-        // notice that the order of these two matters
+        // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d", its leaf node is the
+        // hash of 0x00 followed by it; the root is the hash of 0x02, the 5 leaves as 8 bytes and
+        // the top node
+        assert_eq!(MerkleTree::new::<H256>(&[]).root(), (&EMPTY_ROOT).into());
+        assert_eq!(MerkleTree::new(&input_data[..1]).root(), (hex!("650ea7abcea7e39b1d2dae24dacc141807feef8987840889bfce2739579ac371")).into());
     }
 
     #[test]
     fn proof() {
         let input_data: Vec<H256> = gen_merkle_tree_data!();
         let merkle_tree = MerkleTree::new(&input_data);
-        let proof = merkle_tree.proof(0);
-        assert_eq!(proof,
-                   vec![hex!("965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f").into(),
-                        hex!("8c56ff4c190d4f6cd98b87661e77da02ce4c1436de294382278bfb915c30576c").into(),
-                        hex!("7e85800ae9e1754abd05983bda78efa0834a16163927bef710cd7f49e315b58b").into()]                
+        let proof = merkle_tree.proof(0).unwrap();
+        assert_eq!(proof.siblings,
+                   vec![hex!("e12bdca0d07284b30ce3b2ec0df4c955b26f3b79239cb5bc97629f1a2c5886d1").into(),
+                        hex!("dbe270d8226fbc8fe609b2d15d30a9cdd5f4659702c0d0d55624a76ebb43ac22").into(),
+                        hex!("932be4c9460d284e35b1e4f6e5096d2aa0ef7f019d65563a1edeb96f80dab776").into()]
         );
-        // "965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f" is the hash of
+        assert_eq!((proof.index, proof.size), (0, 5));
+        // "e12bdca0d07284b30ce3b2ec0df4c955b26f3b79239cb5bc97629f1a2c5886d1" is the leaf node of
         // "0101010101010101010101010101010101010101010101010101010101010202"
+        // the last leaf is paired with itself twice, which the proof leaves out
+        assert_eq!(merkle_tree.proof(4).unwrap().siblings.len(), 1);
+        assert_eq!(merkle_tree.proof(5), None);
     }
 
     #[test]
     fn verifying() {
         let input_data: Vec<H256> = gen_merkle_tree_data!();
         let merkle_tree = MerkleTree::new(&input_data);
-        let proof = merkle_tree.proof(0);
-        assert!(verify(&merkle_tree.root(), &input_data[0].hash(), &proof, 0, input_data.len()));
+        let proof = merkle_tree.proof(0).unwrap();
+        assert!(verify(&merkle_tree.root(), &input_data[0].hash(), &proof));
+        assert!(!verify(&merkle_tree.root(), &input_data[1].hash(), &proof));
+
+        // repeating the last leaf, or the last pair, changes the root
+        let mut repeated = input_data.clone();
+        repeated.push(input_data[4]);
+        assert_ne!(MerkleTree::new(&repeated).root(), merkle_tree.root());
+        let pairs: Vec<H256> = vec![input_data[0], input_data[1], input_data[0], input_data[1]];
+        assert_ne!(MerkleTree::new(&pairs).root(), MerkleTree::new(&pairs[..2]).root());
+        // a leaf does not pass for the node above two leaves
+        let node = merkle_tree.levels[1][0];
+        assert!(merkle_tree.multiproof(&[0, 1]).is_some());
+        assert!(!verify(&merkle_tree.root(), &node, &Proof { index: 0, size: 3, siblings: merkle_tree.proof(0).unwrap().siblings[1..].to_vec() }));
+        let last = merkle_tree.proof(4).unwrap();
+        assert!(!verify(&merkle_tree.root(), &input_data[4].hash(), &Proof { size: 6, ..last.clone() }));
+        assert!(!verify(&merkle_tree.root(), &input_data[4].hash(), &Proof { index: 5, size: 6, ..last }));
+    }
+
+    /// The root as the layout describes it, level by level.
+    fn reference_root(leaves: &[H256]) -> H256 {
+        if leaves.is_empty() {
+            return (&EMPTY_ROOT).into();
+        }
+        let mut level: Vec<H256> = leaves.iter().map(|l| leaf(&l.hash())).collect();
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                level.push(*level.last().unwrap());
+            }
+            level = level.chunks(2).map(|pair| parent(&pair[0], &pair[1])).collect();
+        }
+        sized(leaves.len(), &level[0])
+    }
+
+    #[test]
+    fn every_proof_verifies() {
+        let mut rng = rand::thread_rng();
+        let mut sizes: Vec<usize> = (0..=33).collect();
+        sizes.extend((0..8).map(|_| rng.gen_range(34, 300)));
+        for size in sizes {
+            let leaves: Vec<H256> = (0..size).map(|_| (&rng.gen::<[u8; 32]>()).into()).collect();
+            let tree = MerkleTree::new(&leaves);
+            let root = tree.root();
+            assert_eq!(root, reference_root(&leaves));
+            assert_eq!(tree.len(), size);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let datum = leaf.hash();
+                let proof = tree.proof(index).unwrap();
+                assert!(verify(&root, &datum, &proof));
+                // the proof holds for its leaf, position and size only
+                let other: H256 = (&rng.gen::<[u8; 32]>()).into();
+                assert!(!verify(&root, &other, &proof));
+                if size > 1 {
+                    let moved = Proof { index: (index + 1) % size, ..proof.clone() };
+                    assert!(!verify(&root, &datum, &moved) || leaves[moved.index] == *leaf);
+                    let mut tampered = proof.clone();
+                    tampered.siblings[rng.gen_range(0, proof.siblings.len())] = other;
+                    assert!(!verify(&root, &datum, &tampered));
+                    let mut short = proof.clone();
+                    short.siblings.pop();
+                    assert!(!verify(&root, &datum, &short));
+                }
+                assert!(!verify(&root, &datum, &Proof { index: size, ..proof }));
+            }
+            assert_eq!(tree.proof(size), None);
+
+            // multiproofs of random subsets
+            for _ in 0..4 {
+                if size == 0 {
+                    assert_eq!(tree.multiproof(&[0]), None);
+                    break;
+                }
+                let mut indices: Vec<usize> = (0..rng.gen_range(1, size + 1)).map(|_| rng.gen_range(0, size)).collect();
+                let proof = tree.multiproof(&indices).unwrap();
+                indices.sort();
+                indices.dedup();
+                assert_eq!(proof.indices, indices);
+                let data: Vec<H256> = indices.iter().map(|i| leaves[*i].hash()).collect();
+                assert!(verify_multi(&root, &data, &proof));
+                // no more nodes than the single proofs together
+                let single: usize = indices.iter().map(|i| tree.proof(*i).unwrap().siblings.len()).sum();
+                assert!(proof.hashes.len() <= single);
+                let mut wrong = data.clone();
+                wrong[0] = (&rng.gen::<[u8; 32]>()).into();
+                assert!(!verify_multi(&root, &wrong, &proof));
+                assert!(!verify_multi(&root, &data[1..], &proof));
+                if !proof.hashes.is_empty() {
+                    let mut extra = proof.clone();
+                    extra.hashes.push(root);
+                    assert!(!verify_multi(&root, &data, &extra));
+                }
+            }
+        }
     }
 }
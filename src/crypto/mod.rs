@@ -1,4 +1,6 @@
 pub mod hash;
 pub mod merkle;
+pub mod sparse_merkle;
 pub mod key_pair;
 pub mod address;
+pub mod hd;
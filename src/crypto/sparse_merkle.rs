@@ -0,0 +1,280 @@
+//! Sparse Merkle trees over 160-bit keys, the commitment to the account state.
+//!
+//! Layout: every key has a path from the root, the bits of the key, most significant first,
+//! going left on 0 and right on 1. A subtree without keys is `EMPTY_ROOT`. A subtree with a
+//! single key is its leaf, the SHA256 of `0x00`, the key and the value hash, wherever that
+//! subtree starts; paths are only as long as needed to tell the keys apart. Any other subtree is
+//! the SHA256 of `0x01`, the left subtree and the right subtree. The root thus only depends on
+//! the set of (key, value hash) pairs, not on the order they were inserted in.
+
+use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+use super::address::H160;
+use super::hash::H256;
+use ring::digest;
+
+/// The root of a tree without keys, and of every empty subtree.
+pub const EMPTY_ROOT: [u8; 32] = [0u8; 32];
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+
+/// A sparse Merkle tree that keeps the hash of every subtree of two keys or more. A clone costs
+/// O(1) and shares its nodes with the original; inserting a key copies and hashes again only the
+/// nodes on its path.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(into = "Vec<(H160, H256)>", from = "Vec<(H160, H256)>")]
+pub struct SparseMerkleTree {
+    root: Option<Arc<Node>>,
+    len: usize,
+}
+
+#[derive(Debug)]
+enum Node {
+    Leaf(H160, H256),
+    /// The hash of the subtree and its left and right subtrees
+    Branch(H256, Option<Arc<Node>>, Option<Arc<Node>>),
+}
+
+/// Proves the value hash of a key under a root, or that the key has none. The siblings go from
+/// the root down to where the path of the key ends: at the leaf of the key, at the leaf of
+/// another key or at an empty subtree. `leaf` is the key and value hash of the leaf there.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SparseProof {
+    pub siblings: Vec<H256>,
+    pub leaf: Option<(H160, H256)>,
+}
+
+impl SparseMerkleTree {
+    /// The tree of the given (key, value hash) pairs, the last value of a key counts.
+    pub fn new<I>(leaves: I) -> Self where I: IntoIterator<Item = (H160, H256)>, {
+        let mut tree = SparseMerkleTree::default();
+        for (key, value) in leaves {
+            tree.insert(key, value);
+        }
+        tree
+    }
+
+    /// Set the value hash of `key`.
+    pub fn insert(&mut self, key: H160, value: H256) {
+        let (root, added) = insert(self.root.as_ref(), key, value, 0);
+        self.root = Some(root);
+        if added {
+            self.len += 1;
+        }
+    }
+
+    pub fn root(&self) -> H256 {
+        hash(self.root.as_ref())
+    }
+
+    /// The number of keys.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the proof of the value of `key`, or of its absence.
+    pub fn proof(&self, key: &H160) -> SparseProof {
+        let mut node = self.root.as_ref();
+        let mut siblings = vec![];
+        let mut depth = 0;
+        while let Some(n) = node {
+            match &**n {
+                Node::Leaf(k, v) => return SparseProof { siblings, leaf: Some((*k, *v)) },
+                Node::Branch(_, left, right) => {
+                    if bit(key, depth) {
+                        siblings.push(hash(left.as_ref()));
+                        node = right.as_ref();
+                    } else {
+                        siblings.push(hash(right.as_ref()));
+                        node = left.as_ref();
+                    }
+                }
+            }
+            depth += 1;
+        }
+        SparseProof { siblings, leaf: None }
+    }
+
+    /// The (key, value hash) pairs, sorted by key.
+    pub fn leaves(&self) -> Vec<(H160, H256)> {
+        let mut leaves = Vec::with_capacity(self.len);
+        collect(self.root.as_ref(), &mut leaves);
+        leaves
+    }
+}
+
+/// Verify that `key` has the value hash `value` under `root`, or no value if `value` is None.
+pub fn verify(root: &H256, key: &H160, value: Option<&H256>, proof: &SparseProof) -> bool {
+    let depth = proof.siblings.len();
+    if depth > 160 {
+        return false;
+    }
+    let mut node = match (&proof.leaf, value) {
+        (Some((k, v)), Some(value)) if k == key && v == value => leaf(k, v),
+        // another key ends the path, so it must follow the path of `key` that far
+        (Some((k, v)), None) if k != key && (0..depth).all(|i| bit(k, i) == bit(key, i)) => leaf(k, v),
+        (None, None) => (&EMPTY_ROOT).into(),
+        _ => return false,
+    };
+    for (i, sibling) in proof.siblings.iter().enumerate().rev() {
+        node = if bit(key, i) { parent(sibling, &node) } else { parent(&node, sibling) };
+    }
+    node == *root
+}
+
+/// The subtree `node` at `depth` with the value hash of `key` set, and whether `key` is new.
+fn insert(node: Option<&Arc<Node>>, key: H160, value: H256, depth: usize) -> (Arc<Node>, bool) {
+    let node = match node {
+        None => return (Arc::new(Node::Leaf(key, value)), true),
+        Some(node) => node,
+    };
+    let (mut left, mut right) = match &**node {
+        Node::Leaf(other, _) if *other == key => return (Arc::new(Node::Leaf(key, value)), false),
+        // the leaf moves one level down, next to `key` if they share the bit
+        Node::Leaf(other, _) if bit(other, depth) => (None, Some(Arc::clone(node))),
+        Node::Leaf(_, _) => (Some(Arc::clone(node)), None),
+        Node::Branch(_, left, right) => (left.clone(), right.clone()),
+    };
+    let side = if bit(&key, depth) { &mut right } else { &mut left };
+    let (child, added) = insert(side.as_ref(), key, value, depth + 1);
+    *side = Some(child);
+    let hash = parent(&hash(left.as_ref()), &hash(right.as_ref()));
+    (Arc::new(Node::Branch(hash, left, right)), added)
+}
+
+/// The hash of the subtree `node`.
+fn hash(node: Option<&Arc<Node>>) -> H256 {
+    match node.map(|n| &**n) {
+        None => (&EMPTY_ROOT).into(),
+        Some(Node::Leaf(key, value)) => leaf(key, value),
+        Some(Node::Branch(hash, _, _)) => *hash,
+    }
+}
+
+fn collect(node: Option<&Arc<Node>>, leaves: &mut Vec<(H160, H256)>) {
+    match node.map(|n| &**n) {
+        None => {}
+        Some(Node::Leaf(key, value)) => leaves.push((*key, *value)),
+        Some(Node::Branch(_, left, right)) => {
+            collect(left.as_ref(), leaves);
+            collect(right.as_ref(), leaves);
+        }
+    }
+}
+
+/// Bit `i` of `key`, from the most significant one.
+fn bit(key: &H160, i: usize) -> bool {
+    key.as_ref()[i / 8] & (0x80 >> (i % 8)) != 0
+}
+
+impl From<SparseMerkleTree> for Vec<(H160, H256)> {
+    fn from(tree: SparseMerkleTree) -> Self {
+        tree.leaves()
+    }
+}
+
+impl From<Vec<(H160, H256)>> for SparseMerkleTree {
+    fn from(leaves: Vec<(H160, H256)>) -> Self {
+        SparseMerkleTree::new(leaves)
+    }
+}
+
+fn leaf(key: &H160, value: &H256) -> H256 {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&[LEAF]);
+    ctx.update(key.as_ref());
+    ctx.update(value.as_ref());
+    ctx.finish().into()
+}
+
+fn parent(left: &H256, right: &H256) -> H256 {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&[NODE]);
+    ctx.update(left.as_ref());
+    ctx.update(right.as_ref());
+    ctx.finish().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn key(bytes: [u8; 20]) -> H160 {
+        hex::encode(bytes).parse().unwrap()
+    }
+
+    #[test]
+    fn layout() {
+        let empty = SparseMerkleTree::new(vec![]);
+        assert_eq!(empty.root(), (&EMPTY_ROOT).into());
+        // a single key is a leaf at the root, two keys split at their first differing bit
+        let a = key([0x00; 20]);
+        let b = key([0x40; 20]);
+        let value: H256 = (&[7u8; 32]).into();
+        assert_eq!(SparseMerkleTree::new(vec![(a, value)]).root(), leaf(&a, &value));
+        let tree = SparseMerkleTree::new(vec![(b, value), (a, value)]);
+        let empty: H256 = (&EMPTY_ROOT).into();
+        assert_eq!(tree.root(), parent(&parent(&leaf(&a, &value), &leaf(&b, &value)), &empty));
+        assert_eq!(tree.proof(&a).siblings, vec![empty, leaf(&b, &value)]);
+    }
+
+    #[test]
+    fn every_proof_verifies() {
+        let mut rng = rand::thread_rng();
+        for size in vec![0, 1, 2, 3, 17, 200] {
+            let leaves: Vec<(H160, H256)> = (0..size).map(|_| (key(rng.gen()), (&rng.gen::<[u8; 32]>()).into())).collect();
+            let tree = SparseMerkleTree::new(leaves.clone());
+            let root = tree.root();
+            // the root does not depend on the order of the keys
+            let mut reversed = leaves.clone();
+            reversed.reverse();
+            assert_eq!(SparseMerkleTree::new(reversed).root(), root);
+            let other: H256 = (&rng.gen::<[u8; 32]>()).into();
+            let mut sorted = leaves.clone();
+            sorted.sort_by_key(|(k, _)| *k);
+            assert_eq!((tree.len(), tree.leaves()), (size, sorted));
+            let decoded: SparseMerkleTree = bincode::deserialize(&bincode::serialize(&tree).unwrap()).unwrap();
+            assert_eq!(decoded.root(), root);
+            if let Some((k, _)) = leaves.first() {
+                // updating a key of a clone leaves the original alone and gives the root of the
+                // updated pairs
+                let mut updated = tree.clone();
+                updated.insert(*k, other);
+                let mut changed = leaves.clone();
+                changed[0].1 = other;
+                assert_eq!(updated.root(), SparseMerkleTree::new(changed).root());
+                assert_eq!(updated.len(), size);
+                assert_eq!(tree.root(), root);
+            }
+            for (k, v) in leaves.iter() {
+                let proof = tree.proof(k);
+                assert!(verify(&root, k, Some(v), &proof));
+                assert!(!verify(&root, k, Some(&other), &proof));
+                assert!(!verify(&root, k, None, &proof));
+                if !proof.siblings.is_empty() {
+                    let mut tampered = proof.clone();
+                    tampered.siblings.pop();
+                    assert!(!verify(&root, k, Some(v), &tampered));
+                }
+            }
+            // absent keys, ending at an empty subtree or at another leaf
+            for _ in 0..20 {
+                let absent = key(rng.gen());
+                let proof = tree.proof(&absent);
+                assert!(verify(&root, &absent, None, &proof));
+                assert!(!verify(&root, &absent, Some(&other), &proof));
+                if let Some((k, v)) = proof.leaf {
+                    // the proof of absence does not prove the other key absent
+                    assert!(!verify(&root, &k, None, &proof));
+                    assert!(verify(&root, &k, Some(&v), &tree.proof(&k)));
+                }
+            }
+        }
+    }
+}
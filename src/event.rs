@@ -0,0 +1,113 @@
+use serde::Serialize;
+use crossbeam::channel::{self, Receiver, Sender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// How many undelivered events a subscriber may lag behind before new events are dropped for it.
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+/// A node event, serialized as a JSON object tagged by `type`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    NewPrBlock {
+        hash: String,
+        parent: String,
+        height: u32,
+        mined: bool,
+    },
+    NewTxBlock {
+        hash: String,
+        transactions: usize,
+        mined: bool,
+    },
+    NewVoterBlock {
+        hash: String,
+        votes: usize,
+        mined: bool,
+    },
+    Reorg {
+        old_tip: String,
+        new_tip: String,
+        depth: u32,
+    },
+    TransactionAccepted {
+        hash: String,
+    },
+    TransactionRejected {
+        hash: String,
+        reason: String,
+    },
+    PeerConnected {
+        addr: String,
+    },
+    PeerDisconnected {
+        addr: String,
+    },
+    MinerStateChanged {
+        state: String,
+        lambda: Option<u64>,
+    },
+}
+
+/// An event together with the time (ms since UNIX epoch) it was published.
+#[derive(Serialize, Debug, Clone)]
+pub struct Envelope {
+    pub timestamp: u128,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Fan-out bus that the miner, the workers and the P2P server publish node events to.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Sender<Envelope>>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus {
+            subscribers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Register a new subscriber. Events published before this call are not delivered.
+    pub fn subscribe(&self) -> Receiver<Envelope> {
+        let (sender, receiver) = channel::bounded(SUBSCRIBER_BUFFER);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Deliver an event to every live subscriber. Subscribers that went away are forgotten, and
+    /// subscribers that fell too far behind miss the event instead of blocking the publisher.
+    pub fn publish(&self, event: Event) {
+        let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+        let envelope = Envelope { timestamp, event };
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| match s.try_send(envelope.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_subscribe() {
+        let bus = EventBus::new();
+        let early = bus.subscribe();
+        bus.publish(Event::PeerConnected { addr: "127.0.0.1:6000".to_string() });
+        let late = bus.subscribe();
+        bus.publish(Event::PeerDisconnected { addr: "127.0.0.1:6000".to_string() });
+        assert_eq!(early.len(), 2);
+        assert_eq!(late.len(), 1);
+        let json = serde_json::to_string(&late.recv().unwrap()).unwrap();
+        assert!(json.contains("\"type\":\"peer_disconnected\""));
+        drop(early);
+        bus.publish(Event::PeerConnected { addr: "127.0.0.1:6001".to_string() });
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+    }
+}
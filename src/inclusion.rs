@@ -0,0 +1,263 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fmt;
+use crate::block::{Block, BlockKind, Header};
+use crate::blockchain::Blockchain;
+use crate::crypto::hash::{Hashable, H256};
+use crate::crypto::merkle::{self, MerkleTree, Proof};
+use crate::pow::PowBackend;
+use crate::transaction::{SignedTransaction, StatePerBlock};
+
+/// Everything a light client needs to check that a transaction was confirmed, given only the
+/// hash of a proposer block it trusts, the checkpoint. It shows that the transaction is in a
+/// transaction block and that a proposer block on the same chain as the checkpoint points to
+/// that block. Whether the transaction was applied or skipped is not part of it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InclusionProof {
+    pub transaction: SignedTransaction,
+    /// The header of the transaction block holding the transaction, and the path of the
+    /// transaction under its `tx_root`.
+    pub tx_block: Header,
+    pub tx_proof: Proof,
+    /// Consecutive proposer headers, newest first, each the parent of the one before. One end is
+    /// the checkpoint, the other end is the proposer block whose `TxPointer` confirmed the
+    /// transaction block.
+    pub headers: Vec<Header>,
+    /// The path of the transaction block hash under the `pointer_root` of the confirming proposer
+    /// block.
+    pub pointer_proof: Proof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofError {
+    /// No transaction block this node has holds the transaction.
+    NotFound,
+    /// The transaction blocks holding the transaction are not confirmed on the longest chain.
+    NotConfirmed,
+    /// The checkpoint is neither an ancestor nor a descendant of the confirming proposer block.
+    UnknownCheckpoint,
+    /// The transaction is not under the `tx_root` of the transaction block.
+    NotInTxBlock,
+    /// The transaction block is not under the `pointer_root` of the confirming proposer block.
+    NotConfirmedByProposer,
+    /// A header fails its proof of work or has other difficulties than the checkpoint.
+    InvalidHeader,
+    /// The headers are not linked by their parents, or no end of them is the checkpoint.
+    BrokenChain,
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProofError::NotFound => write!(f, "transaction not found in any transaction block"),
+            ProofError::NotConfirmed => write!(f, "transaction not confirmed on the longest chain"),
+            ProofError::UnknownCheckpoint => write!(f, "checkpoint not on the chain of the confirming proposer block"),
+            ProofError::NotInTxBlock => write!(f, "transaction not in the transaction block"),
+            ProofError::NotConfirmedByProposer => write!(f, "transaction block not pointed to by the proposer block"),
+            ProofError::InvalidHeader => write!(f, "invalid block header"),
+            ProofError::BrokenChain => write!(f, "headers do not link to the checkpoint"),
+        }
+    }
+}
+
+/// Build the inclusion proof of the transaction `hash` against `checkpoint`, using the
+/// transaction block that was confirmed first on the longest chain.
+pub fn prove(hash: &H256, checkpoint: &H256, blockchain: &Blockchain, txBlocks: &HashMap<H256, Block>, spb: &StatePerBlock) -> Result<InclusionProof, ProofError> {
+    let mut found = false;
+    let mut best: Option<(u32, H256, H256)> = None;
+    for (block_hash, block) in txBlocks.iter() {
+        if !block.content.data.iter().any(|t| t.hash() == *hash) {
+            continue;
+        }
+        found = true;
+        if let Some(pr) = spb.confirmed_by(blockchain, block_hash) {
+            let height = blockchain.height[&pr];
+            if best.map_or(true, |(h, p, b)| (height, pr, *block_hash) < (h, p, b)) {
+                best = Some((height, pr, *block_hash));
+            }
+        }
+    }
+    let (_, pr, tx_block) = match best {
+        Some(best) => best,
+        None if found => return Err(ProofError::NotConfirmed),
+        None => return Err(ProofError::NotFound),
+    };
+
+    let block = &txBlocks[&tx_block];
+    let index = block.content.data.iter().position(|t| t.hash() == *hash).unwrap();
+    let tx_proof = MerkleTree::new(&block.content.data).proof(index).unwrap();
+    let tp = &blockchain.blocks[&pr].txPointer.tp;
+    let position = tp.iter().position(|h| *h == tx_block).unwrap();
+    let pointer_proof = MerkleTree::new(tp).proof(position).unwrap();
+
+    let headers = match header_chain(blockchain, &pr, checkpoint) {
+        Some(headers) => headers,
+        None => header_chain(blockchain, checkpoint, &pr).ok_or(ProofError::UnknownCheckpoint)?,
+    };
+    Ok(InclusionProof {
+        transaction: block.content.data[index].clone(),
+        tx_block: block.header.clone(),
+        tx_proof,
+        headers,
+        pointer_proof,
+    })
+}
+
+/// The headers from `from` back to its ancestor `to`, both included.
+fn header_chain(blockchain: &Blockchain, from: &H256, to: &H256) -> Option<Vec<Header>> {
+    let mut headers = vec![];
+    let mut current = *from;
+    loop {
+        let block = blockchain.blocks.get(&current)?;
+        headers.push(block.header.clone());
+        if current == *to {
+            return Some(headers);
+        }
+        if current == blockchain.genesis {
+            return None;
+        }
+        current = block.header.parent;
+    }
+}
+
+/// Check `proof` against the trusted proposer block `checkpoint`, without any chain state. The
+/// headers other than the checkpoint must pass `pow`. Returns the hash of the confirming proposer
+/// block.
+///
+/// A confirming block that is an ancestor of the checkpoint is as trustworthy as the checkpoint;
+/// one that descends from it is only backed by the proof of work of the headers, so the caller
+/// may want several of them.
+pub fn verify(proof: &InclusionProof, checkpoint: &H256, pow: &dyn PowBackend) -> Result<H256, ProofError> {
+    let (first, last) = match (proof.headers.first(), proof.headers.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err(ProofError::BrokenChain),
+    };
+    let (proposer, anchor) = if last.hash() == *checkpoint {
+        (first, last)
+    } else if first.hash() == *checkpoint {
+        (last, first)
+    } else {
+        return Err(ProofError::BrokenChain);
+    };
+    if proof.headers.windows(2).any(|w| w[0].parent != w[1].hash()) {
+        return Err(ProofError::BrokenChain);
+    }
+    for header in proof.headers.iter() {
+        if header.hash() != *checkpoint && !pow.verify(header, BlockKind::Proposer) {
+            return Err(ProofError::InvalidHeader);
+        }
+        if !header.same_difficulty(anchor) {
+            return Err(ProofError::InvalidHeader);
+        }
+    }
+    if !pow.verify(&proof.tx_block, BlockKind::Transaction) || !proof.tx_block.same_difficulty(anchor) {
+        return Err(ProofError::InvalidHeader);
+    }
+    if !merkle::verify(&proof.tx_block.tx_root, &proof.transaction.hash(), &proof.tx_proof) {
+        return Err(ProofError::NotInTxBlock);
+    }
+    // the pointers are hashes, and merkle leaves are the hashes of the leaves
+    if !merkle::verify(&proposer.pointer_root, &proof.tx_block.hash().hash(), &proof.pointer_proof) {
+        return Err(ProofError::NotConfirmedByProposer);
+    }
+    Ok(proposer.hash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Content, TxPointer, Votes};
+    use crate::crypto::key_pair;
+    use crate::pow::Simulated;
+    use crate::transaction::{sign, AccountTransaction, ChainId, State, Transaction};
+    use ring::signature::KeyPair;
+
+    fn block(parent: &H256, kind: BlockKind, txPointer: Vec<H256>, data: Vec<SignedTransaction>, blockchain: &Blockchain) -> Block {
+        let genesis = &blockchain.blocks[&blockchain.genesis].header;
+        let content = Content { data };
+        let txPointer = TxPointer { tp: txPointer };
+        let mut header = Header {
+            parent: *parent,
+            nonce: rand::random(),
+            difficultyForPr: genesis.difficultyForPr,
+            difficultyForTx: genesis.difficultyForTx,
+            difficultyForVoter: genesis.difficultyForVoter,
+            timestamp: 0,
+            tx_root: content.root(),
+            pointer_root: txPointer.root(),
+            vote_root: Votes::default().root(),
+            state_root: H256::default(),
+        };
+        Simulated::grind(&mut header, kind);
+        Block { header, txPointer, content, votes: Votes::default() }
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        let key = key_pair::random();
+        let payments: Vec<SignedTransaction> = (1..4).map(|value| {
+            let transaction = Transaction { recipientAddr: key.public_key().as_ref().into(), value, accountNonce: 1, lock_height: None, lock_time: None };
+            let signature = sign(&transaction, &key, &ChainId::new(&H256::default(), None));
+            SignedTransaction::Account(AccountTransaction { signature: signature.as_ref().to_vec(), public_key: key.public_key().as_ref().to_vec(), transaction })
+        }).collect();
+
+        // genesis <- a <- b <- c, where b points to the transaction block
+        let mut blockchain = Blockchain::new();
+        let genesis = blockchain.genesis;
+        let mut spb = StatePerBlock::new(genesis, State::new());
+        let mut txBlocks = HashMap::new();
+        let txBlock = block(&genesis, BlockKind::Transaction, vec![], payments.clone(), &blockchain);
+        txBlocks.insert(txBlock.hash(), txBlock.clone());
+        let mut parent = genesis;
+        let mut chain = vec![];
+        for tp in vec![vec![], vec![H256::default(), txBlock.hash()], vec![]] {
+            let pr = block(&parent, BlockKind::Proposer, tp.clone(), vec![], &blockchain);
+            let mut state = spb.spb[&parent].clone();
+            for h in tp {
+                state.txBlockOrderedList.confirm(h, blockchain.height[&parent] + 1);
+            }
+            spb.insert(pr.hash(), &state);
+            blockchain.insert(&pr);
+            chain.push(pr.hash());
+            parent = pr.hash();
+        }
+
+        // the test blocks are ground to their type under simulated proof of work
+        let pow = Simulated::new(1.0);
+        let target = payments[2].hash();
+        let proof = prove(&target, &genesis, &blockchain, &txBlocks, &spb).unwrap();
+        assert_eq!(proof.headers.len(), 3);
+        assert_eq!(verify(&proof, &genesis, &pow), Ok(chain[1]));
+        // as served by the API
+        let served: InclusionProof = serde_json::from_str(&serde_json::to_string(&proof).unwrap()).unwrap();
+        assert_eq!(verify(&served, &genesis, &pow), Ok(chain[1]));
+        // a later checkpoint vouches for its ancestors
+        let later = prove(&target, &chain[2], &blockchain, &txBlocks, &spb).unwrap();
+        assert_eq!(later.headers.len(), 2);
+        assert_eq!(verify(&later, &chain[2], &pow), Ok(chain[1]));
+        assert_eq!(verify(&later, &genesis, &pow), Err(ProofError::BrokenChain));
+
+        let mut tampered = proof.clone();
+        tampered.transaction = payments[0].clone();
+        assert_eq!(verify(&tampered, &genesis, &pow), Err(ProofError::NotInTxBlock));
+        let mut tampered = proof.clone();
+        tampered.pointer_proof.index = 0;
+        assert_eq!(verify(&tampered, &genesis, &pow), Err(ProofError::NotConfirmedByProposer));
+        let mut tampered = proof.clone();
+        tampered.headers.remove(1);
+        assert_eq!(verify(&tampered, &genesis, &pow), Err(ProofError::BrokenChain));
+        let mut tampered = proof.clone();
+        tampered.tx_block.difficultyForTx = H256::default();
+        assert_eq!(verify(&tampered, &genesis, &pow), Err(ProofError::InvalidHeader));
+        // the test blocks carry no real proof of work
+        assert_eq!(verify(&proof, &genesis, &crate::pow::Sha256), Err(ProofError::InvalidHeader));
+
+        let unconfirmed = payments[0].hash().hash();
+        assert_eq!(prove(&unconfirmed, &genesis, &blockchain, &txBlocks, &spb).err(), Some(ProofError::NotFound));
+        let pending = block(&genesis, BlockKind::Transaction, vec![], vec![payments[0].clone()], &blockchain);
+        txBlocks.insert(pending.hash(), pending);
+        let mut later = txBlocks.clone();
+        later.retain(|h, _| *h != txBlock.hash());
+        assert_eq!(prove(&payments[0].hash(), &genesis, &blockchain, &later, &spb).err(), Some(ProofError::NotConfirmed));
+    }
+}
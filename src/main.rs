@@ -5,16 +5,29 @@ extern crate hex_literal;
 pub mod api;
 pub mod block;
 pub mod blockchain;
+pub mod confirmations;
 pub mod crypto;
+pub mod event;
+pub mod inclusion;
+pub mod metrics;
 pub mod miner;
+pub mod multisig;
 pub mod network;
+pub mod pow;
+pub mod pruning;
+pub mod snapshot;
 pub mod transaction;
+pub mod utxo;
+pub mod verifier;
+pub mod wallet;
 
 use clap::clap_app;
 use crossbeam::channel;
 use log::{error, info};
 use api::Server as ApiServer;
 use network::{server, worker, generator};
+use std::env;
+use std::io;
 use std::net;
 use std::process;
 use std::thread;
@@ -22,13 +35,22 @@ use std::time;
 
 use std::sync::{Arc, Mutex};
 use crate::blockchain::Blockchain;
-use crate::transaction::{Mempool, TxBlockMempool, State, StatePerBlock};
+use crate::transaction::{ChainId, LedgerMode, Mempool, TxBlockMempool, State, StatePerBlock, UnconfirmedTxBlocks};
 use std::collections::HashMap;
 use crate::crypto::hash::{Hashable,H256};
 use crate::block::Block;
-use crate::crypto::key_pair;
+use crate::event::EventBus;
+use crate::metrics::Metrics;
+use crate::pow::PowBackend;
+use crate::pruning::Pruning;
+use crate::snapshot::Snapshot;
+use crate::verifier::Verifier;
+use crate::wallet::Wallet;
+use std::path::{Path, PathBuf};
 use ring::signature::{Ed25519KeyPair};
 
+/// Environment variable the wallet passphrase is read from, it is prompted for otherwise.
+const PASSPHRASE_VAR: &str = "PRISM_WALLET_PASSPHRASE";
 
 fn main() {
     // parse command line arguments
@@ -41,7 +63,18 @@ fn main() {
      (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
      (@arg p2p_workers: --("p2p-workers") [INT] default_value("4") "Sets the number of worker threads for P2P server")
      (@arg generate: -g --("generator") [INT] default_value("0") "Sets generator status")
+     (@arg miner_threads: --("miner-threads") [INT] default_value("1") "Sets the number of nonce search threads of the miner")
+     (@arg pow: --pow [BACKEND] default_value("sha256") possible_value[sha256 simulated] "Sets the proof-of-work backend")
+     (@arg pow_rate: --("pow-rate") [RATE] default_value("1") "Sets the blocks per second per miner thread of the simulated backend")
+     (@arg ledger: --ledger [MODEL] default_value("account") possible_value[account utxo] "Sets the ledger model transactions are built and accepted for")
+     (@arg chain_id: --("chain-id") [NAME] "Sets a name that separates the chain from others sharing its genesis block, transactions are only valid on the chain they are signed for")
+     (@arg verify_cache: --("verify-cache") [SIZE] default_value("100000") "Sets how many verified transactions the signature cache remembers")
      (@arg attack: -a --("attacker") [INT] default_value("0") "Sets attacker status, 0: no attack, 1: spamming attack, 2: cencorship attack, 3: both attacks")
+     (@arg prune: --prune [DEPTH] "Keeps the states of the last DEPTH proposer levels and of the checkpoints only, and drops the transaction blocks confirmed deeper")
+     (@arg checkpoint_interval: --("checkpoint-interval") [INT] default_value("1000") "Sets the interval in proposer levels between the checkpoints a pruning node keeps the states of")
+     (@arg snapshot: --snapshot [FILE] "Starts from the ledger state of a snapshot instead of the genesis block")
+     (@arg snapshot_dir: --("snapshot-dir") [DIR] "Sets the directory snapshots are exported to, defaults to snapshots_<p2p port>")
+     (@arg wallet: --wallet [FILE] "Sets the keystore file of the wallet, defaults to wallet_<p2p port>.json")
     )
     .get_matches();
 
@@ -72,28 +105,130 @@ fn main() {
     // create channels between server and worker
     let (msg_tx, msg_rx) = channel::unbounded();
 
+    // create the bus that node events are published to
+    let events = EventBus::new();
+    let metrics = Arc::new(Metrics::new());
+
     let temp_blockchain = Blockchain::new();
     let mut blockchain = Arc::new(Mutex::new(temp_blockchain.clone()));
     let mut mempool = Arc::new(Mutex::new(Mempool::new()));
     let mut txBlockmempool = Arc::new(Mutex::new(TxBlockMempool::new()));
-    let mut txBlockOrderedList = Arc::new(Mutex::new(Vec::new()));
-    // let mut keys = vec![];
+    let mut unconfirmedTxBlocks = Arc::new(Mutex::new(UnconfirmedTxBlocks::new()));
+
+    // open the wallet, the transaction generator signs with its first five keys
+    let wallet_path = match matches.value_of("wallet") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(format!("wallet_{}.json", p2p_addr.port())),
+    };
+    // the passphrase is not taken from the command line, which other users of the machine can read
+    let passphrase = match env::var(PASSPHRASE_VAR) {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            eprint!("Passphrase of wallet {}: ", wallet_path.display());
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).unwrap_or_else(|e| {
+                error!("Error reading wallet passphrase: {}", e);
+                process::exit(1);
+            });
+            line.trim_end_matches(&['\n', '\r'][..]).to_string()
+        }
+    };
+    if passphrase.is_empty() {
+        error!("Error opening wallet {}: the passphrase is empty", wallet_path.display());
+        process::exit(1);
+    }
+    let mut wallet = Wallet::open(&wallet_path, &passphrase)
+        .unwrap_or_else(|e| {
+            error!("Error opening wallet {}: {}", wallet_path.display(), e);
+            process::exit(1);
+        });
+    while wallet.keys().len() < 5 {
+        let label = format!("generator-{}", wallet.keys().len());
+        wallet.generate(&label).unwrap_or_else(|e| {
+            error!("Error saving wallet {}: {}", wallet_path.display(), e);
+            process::exit(1);
+        });
+    }
     let mut key_hashtable: HashMap<u32, Ed25519KeyPair>=HashMap::new();
     for i in 0..5 {
-        let key = key_pair::random();
-        key_hashtable.insert(i, key);
-        // keys.push(key);
+        let key = wallet.key_pair(&wallet.keys()[i].address).unwrap();
+        key_hashtable.insert(i as u32, key);
     }
+    let wallet = Arc::new(Mutex::new(wallet));
 
     let temp_state = State::new();
-    let mut state = Arc::new(Mutex::new(temp_state.clone()));
     let mut spb = Arc::new(Mutex::new(StatePerBlock::new(temp_blockchain.genesis, temp_state)));
     let mut key_set = Arc::new(Mutex::new(key_hashtable));
 
     // start the p2p server
-    let (server_ctx, server) = server::new(p2p_addr, msg_tx).unwrap();
+    let (server_ctx, server) = server::new(p2p_addr, msg_tx, &events, &metrics).unwrap();
     server_ctx.start().unwrap();
 
+    // choose how blocks are mined and verified
+    let pow: Arc<dyn PowBackend> = match matches.value_of("pow").unwrap() {
+        "simulated" => {
+            let rate = matches
+                .value_of("pow_rate")
+                .unwrap()
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| *rate > 0.0)
+                .unwrap_or_else(|| {
+                    error!("Error parsing PoW rate: expected a positive number");
+                    process::exit(1);
+                });
+            Arc::new(pow::Simulated::new(rate))
+        }
+        _ => Arc::new(pow::Sha256),
+    };
+
+    // start from a snapshot of this chain and sync forward from its block
+    if let Some(path) = matches.value_of("snapshot") {
+        let snapshot = Snapshot::load(Path::new(path))
+            .and_then(|s| s.verify(&temp_blockchain.genesis, pow.as_ref()).map(|_| s))
+            .unwrap_or_else(|e| {
+                error!("Error loading snapshot {}: {}", path, e);
+                process::exit(1);
+            });
+        let (restored, states) = snapshot.restore();
+        *blockchain.lock().unwrap() = restored;
+        *spb.lock().unwrap() = states;
+        Metrics::set(&metrics.chain_height, snapshot.height() as usize);
+        info!("Starting from the snapshot of block {} at height {}", snapshot.block(), snapshot.height());
+    }
+
+    // choose the ledger model transactions are built and accepted for
+    let ledger = match matches.value_of("ledger").unwrap() {
+        "utxo" => LedgerMode::Utxo,
+        _ => LedgerMode::Account,
+    };
+
+    // transactions are signed for one chain
+    let chain_id = ChainId::new(&temp_blockchain.genesis, matches.value_of("chain_id"));
+    info!("Chain ID {}", chain_id);
+    // the worker and the miner share the signatures they already checked
+    let verify_cache = matches
+        .value_of("verify_cache")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing verification cache size: {}", e);
+            process::exit(1);
+        });
+    let verifier = Verifier::new(chain_id, verify_cache, &metrics);
+
+    // keep every state and transaction block unless asked to prune
+    let parse_positive = |name: &str, value: &str| {
+        value.parse::<u32>().ok().filter(|n| *n > 0).unwrap_or_else(|| {
+            error!("Error parsing {}: expected a positive integer", name);
+            process::exit(1);
+        })
+    };
+    let pruning = Pruning {
+        depth: matches.value_of("prune").map(|d| parse_positive("pruning depth", d)),
+        checkpoint_interval: parse_positive("checkpoint interval", matches.value_of("checkpoint_interval").unwrap()),
+    };
+
     // start the worker
     let p2p_workers = matches
         .value_of("p2p_workers")
@@ -114,31 +249,50 @@ fn main() {
         &new_Hashmap,
         &mempool,
         &txBlockmempool, 
-        &txBlockOrderedList,
+        &unconfirmedTxBlocks,
         // &state,
         &spb,
+        &events,
+        &metrics,
+        &pow,
+        ledger,
+        &verifier,
+        pruning,
     );
     worker_ctx.start();
 
     let attack = matches.value_of("attack").unwrap().parse::<usize>().unwrap();
+    // start the transaction generator, it only produces transactions while switched on
+    let (generator_ctx, generator) = generator::new(
+        1,
+        &server,
+        &blockchain,
+        &mempool,
+        &txBlockmempool,
+        &spb,
+        &key_set,
+        attack,
+        ledger,
+        chain_id,
+        &events,
+    );
+    generator_ctx.start();
     if Some("1") == matches.value_of("generate") {
-        
-        // start the transaction generator
-        let generator = generator::new(
-            1,
-            &server,
-            &mempool,
-            &state,
-            &key_set,
-            attack,
-        );
         generator.start();
     }
 
 
     // start the miner
+    let miner_threads = matches
+        .value_of("miner_threads")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing miner threads: {}", e);
+            process::exit(1);
+        });
     let (miner_ctx, miner) = miner::new(
-        &server, &blockchain, &mempool, &txBlockmempool, &txBlockOrderedList, &spb, attack,
+        &server, &blockchain, &mempool, &txBlockmempool, &unconfirmedTxBlocks, &spb, &events, &metrics, &pow, &verifier, pruning, miner_threads, attack,
     );
     miner_ctx.start();
 
@@ -176,11 +330,28 @@ fn main() {
     }
 
 
+    // snapshots are only written to their own directory
+    let snapshot_dir = match matches.value_of("snapshot_dir") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(format!("snapshots_{}", p2p_addr.port())),
+    };
+
     // start the API server
     ApiServer::start(
         api_addr,
         &miner,
+        &generator,
         &server,
+        &events,
+        &metrics,
+        &blockchain,
+        &mempool,
+        &txBlockmempool,
+        &spb,
+        &wallet,
+        ledger,
+        chain_id,
+        &snapshot_dir,
     );
 
     loop {
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Upper bounds (ms) of the block propagation delay histogram buckets.
+const DELAY_BUCKETS: [u64; 10] = [10, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000];
+
+/// A family of counters that differ only by the value of one label.
+#[derive(Default)]
+pub struct LabeledCounter {
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl LabeledCounter {
+    pub fn inc(&self, label: &str) {
+        *self.values.lock().unwrap().entry(label.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn get(&self, label: &str) -> u64 {
+        *self.values.lock().unwrap().get(label).unwrap_or(&0)
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; DELAY_BUCKETS.len()],
+    sum: u64,
+    count: u64,
+}
+
+/// Node-wide counters and gauges, shared between the miner, the P2P workers and the P2P server,
+/// and rendered in Prometheus text format by the API server.
+#[derive(Default)]
+pub struct Metrics {
+    /// Proposer blocks added to the blockchain, by `source` (mined or received).
+    pub pr_blocks: LabeledCounter,
+    /// Transaction blocks added to the transaction block mempool, by `source`.
+    pub tx_blocks: LabeledCounter,
+    /// Voter blocks added to the blockchain, by `source`.
+    pub voter_blocks: LabeledCounter,
+    /// Messages handed to the P2P layer, by message type.
+    pub inbound_messages: LabeledCounter,
+    pub outbound_messages: LabeledCounter,
+    /// Rejected blocks and transactions, by reason.
+    pub validation_failures: LabeledCounter,
+    /// Transactions of confirmed transaction blocks that were not applied, by reason.
+    pub skipped_transactions: LabeledCounter,
+    pub orphans: AtomicU64,
+    pub mempool_size: AtomicU64,
+    pub chain_height: AtomicU64,
+    pub peers: AtomicU64,
+    pub hash_attempts: AtomicU64,
+    /// Hashes per second, as measured by the miner over its last reporting interval.
+    pub hash_rate: AtomicU64,
+    /// Signature checks answered by the verification cache, and those that were not.
+    pub verify_cache_hits: AtomicU64,
+    pub verify_cache_misses: AtomicU64,
+    /// Proposer block states and transaction blocks dropped by pruning.
+    pub pruned_states: AtomicU64,
+    pub pruned_tx_blocks: AtomicU64,
+    propagation_delay: Mutex<Histogram>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn set(gauge: &AtomicU64, value: usize) {
+        gauge.store(value as u64, Ordering::Relaxed);
+    }
+
+    /// Record the delay between a block's `Header.timestamp` and its arrival at this node.
+    pub fn observe_propagation_delay(&self, delay_ms: u64) {
+        let mut histogram = self.propagation_delay.lock().unwrap();
+        for (i, bound) in DELAY_BUCKETS.iter().enumerate() {
+            if delay_ms <= *bound {
+                histogram.buckets[i] += 1;
+            }
+        }
+        histogram.sum += delay_ms;
+        histogram.count += 1;
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        render_labeled(&mut out, "prism_proposer_blocks_total", "Proposer blocks added to the blockchain.", "source", &self.pr_blocks);
+        render_labeled(&mut out, "prism_transaction_blocks_total", "Transaction blocks added to the transaction block mempool.", "source", &self.tx_blocks);
+        render_labeled(&mut out, "prism_voter_blocks_total", "Voter blocks added to the blockchain.", "source", &self.voter_blocks);
+        render_labeled(&mut out, "prism_messages_inbound_total", "P2P messages received.", "type", &self.inbound_messages);
+        render_labeled(&mut out, "prism_messages_outbound_total", "P2P messages sent.", "type", &self.outbound_messages);
+        render_labeled(&mut out, "prism_validation_failures_total", "Rejected blocks and transactions.", "reason", &self.validation_failures);
+        render_labeled(&mut out, "prism_skipped_transactions_total", "Confirmed transactions that were not applied.", "reason", &self.skipped_transactions);
+        render_gauge(&mut out, "prism_orphan_blocks", "Proposer blocks waiting for their parent.", &self.orphans);
+        render_gauge(&mut out, "prism_mempool_transactions", "Transactions in the mempool.", &self.mempool_size);
+        render_gauge(&mut out, "prism_chain_height", "Height of the longest proposer chain.", &self.chain_height);
+        render_gauge(&mut out, "prism_peers", "Connected P2P peers.", &self.peers);
+        render_counter(&mut out, "prism_hash_attempts_total", "Proof-of-work attempts made by the miner.", &self.hash_attempts);
+        render_gauge(&mut out, "prism_hash_rate", "Miner hashes per second.", &self.hash_rate);
+        render_counter(&mut out, "prism_verify_cache_hits_total", "Signature checks answered by the verification cache.", &self.verify_cache_hits);
+        render_counter(&mut out, "prism_verify_cache_misses_total", "Signature checks not answered by the verification cache.", &self.verify_cache_misses);
+        render_counter(&mut out, "prism_pruned_states_total", "Proposer block states dropped by pruning.", &self.pruned_states);
+        render_counter(&mut out, "prism_pruned_transaction_blocks_total", "Confirmed transaction blocks dropped by pruning.", &self.pruned_tx_blocks);
+
+        let histogram = self.propagation_delay.lock().unwrap();
+        let name = "prism_block_propagation_delay_ms";
+        writeln!(out, "# HELP {} Delay between a proposer block's timestamp and its arrival.", name).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        for (i, bound) in DELAY_BUCKETS.iter().enumerate() {
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, histogram.buckets[i]).unwrap();
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count).unwrap();
+        writeln!(out, "{}_sum {}", name, histogram.sum).unwrap();
+        writeln!(out, "{}_count {}", name, histogram.count).unwrap();
+        out
+    }
+}
+
+fn render_gauge(out: &mut String, name: &str, help: &str, gauge: &AtomicU64) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+    writeln!(out, "{} {}", name, gauge.load(Ordering::Relaxed)).unwrap();
+}
+
+fn render_counter(out: &mut String, name: &str, help: &str, counter: &AtomicU64) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} counter", name).unwrap();
+    writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed)).unwrap();
+}
+
+fn render_labeled(out: &mut String, name: &str, help: &str, label: &str, counter: &LabeledCounter) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} counter", name).unwrap();
+    for (value, count) in counter.values.lock().unwrap().iter() {
+        writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value, count).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        metrics.pr_blocks.inc("mined");
+        metrics.pr_blocks.inc("mined");
+        metrics.inbound_messages.inc("Ping");
+        Metrics::set(&metrics.peers, 3);
+        metrics.verify_cache_hits.fetch_add(2, Ordering::Relaxed);
+        metrics.pruned_states.fetch_add(5, Ordering::Relaxed);
+        metrics.observe_propagation_delay(70);
+        let text = metrics.render();
+        assert!(text.contains("prism_proposer_blocks_total{source=\"mined\"} 2\n"));
+        assert!(text.contains("prism_messages_inbound_total{type=\"Ping\"} 1\n"));
+        assert!(text.contains("prism_peers 3\n"));
+        assert!(text.contains("# TYPE prism_verify_cache_hits_total counter\nprism_verify_cache_hits_total 2\n"));
+        assert!(text.contains("prism_pruned_states_total 5\n"));
+        assert!(text.contains("prism_block_propagation_delay_ms_bucket{le=\"50\"} 0\n"));
+        assert!(text.contains("prism_block_propagation_delay_ms_bucket{le=\"100\"} 1\n"));
+        assert!(text.contains("prism_block_propagation_delay_ms_sum 70\n"));
+    }
+}
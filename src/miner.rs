@@ -1,28 +1,33 @@
 use crate::network::server::Handle as ServerHandle;
 
-use log::info;
+use log::{info, warn};
 
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use crossbeam::channel::{self, select, unbounded, Receiver, Sender, TryRecvError};
 use std::time;
 
 use std::thread;
 
 use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
 use crate::blockchain::Blockchain;
-use crate::transaction::{verify,Mempool,TxBlockMempool,State,StatePerBlock};
+use crate::transaction::{Mempool,PendingNonces,TxBlockMempool,StatePerBlock,UnconfirmedTxBlocks};
+use crate::crypto::address::H160;
 use std::time::SystemTime;
-use crate::crypto::merkle::MerkleTree;
 use crate::crypto::hash::H256;
-use crate::crypto::address::H160;
-use rand::Rng;
 use crate::transaction::SignedTransaction;
-use crate::block::{Block,Header,Content,TxPointer};
+use crate::verifier::Verifier;
+use crate::block::{Block,BlockKind,Content,TxPointer,Votes};
 use crate::crypto::hash::Hashable;
 use crate::network::message::Message;
-use serde::Serialize;
+use crate::event::{Event, EventBus};
+use crate::metrics::Metrics;
+use crate::pow::{NonceIter, PowBackend};
+use crate::pruning::Pruning;
+use std::sync::atomic::Ordering;
 
 enum ControlSignal {
     Start(u64), // the number controls the lambda of interval between block generation
+    Pause,
     Exit,
 }
 
@@ -32,6 +37,49 @@ enum OperatingState {
     ShutDown,
 }
 
+/// How often the miner thread rebuilds the block template while time-locked transactions wait
+/// in the mempool, they mature without any event.
+const TIME_LOCK_REFRESH: time::Duration = time::Duration::from_millis(1000);
+
+/// Attempts a search thread makes between checks for a new job when mining without a sleep.
+const SEARCH_BATCH: u64 = 256;
+
+/// The block being mined. It is rebuilt only when its inputs change: the tip, the transactions
+/// picked from the mempool or the transaction blocks not yet referenced by the tip.
+struct Template {
+    id: u64,
+    block: Block,
+    content_hash: Vec<H256>,
+    /// Whether time-locked transactions waited in the mempool when it was built
+    time_locked: bool,
+}
+
+/// Instructions from the miner thread to the nonce search threads.
+enum Job {
+    Mine(Arc<Template>, u64),
+    Pause,
+    Exit,
+}
+
+/// A block a search thread found, reported back to the miner thread to be committed.
+struct Found {
+    template: u64,
+    block: Block,
+    kind: BlockKind,
+}
+
+/// A nonce search thread. It only ever sees its own copy of the template and never touches the
+/// shared chain state.
+struct Searcher {
+    index: usize,
+    threads: usize,
+    pow: Arc<dyn PowBackend>,
+    jobs: Receiver<Job>,
+    found: Sender<Found>,
+    metrics: Arc<Metrics>,
+    attack: usize,
+}
+
 pub struct Context {
     /// Channel for receiving control signal
     control_chan: Receiver<ControlSignal>,
@@ -40,8 +88,14 @@ pub struct Context {
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
     txBlockmempool: Arc<Mutex<TxBlockMempool>>,
-    txBlockOrderedList: Arc<Mutex<Vec<H256>>>,
+    unconfirmedTxBlocks: Arc<Mutex<UnconfirmedTxBlocks>>,
     spb: Arc<Mutex<StatePerBlock>>,
+    events: EventBus,
+    metrics: Arc<Metrics>,
+    pow: Arc<dyn PowBackend>,
+    verifier: Verifier,
+    pruning: Pruning,
+    threads: usize,
     attack: usize,
 }
 
@@ -49,6 +103,8 @@ pub struct Context {
 pub struct Handle {
     /// Channel for sending signal to the miner thread
     control_chan: Sender<ControlSignal>,
+    metrics: Arc<Metrics>,
+    threads: usize,
 }
 
 pub fn new(
@@ -56,8 +112,14 @@ pub fn new(
     blockchain: &Arc<Mutex<Blockchain>>, 
     mempool: &Arc<Mutex<Mempool>>, 
     txBlockmempool: &Arc<Mutex<TxBlockMempool>>, 
-    txBlockOrderedList: &Arc<Mutex<Vec<H256>>>, 
+    unconfirmedTxBlocks: &Arc<Mutex<UnconfirmedTxBlocks>>, 
     spb: &Arc<Mutex<StatePerBlock>>,
+    events: &EventBus,
+    metrics: &Arc<Metrics>,
+    pow: &Arc<dyn PowBackend>,
+    verifier: &Verifier,
+    pruning: Pruning,
+    threads: usize,
     attack: usize,
 ) -> (Context, Handle) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
@@ -69,13 +131,21 @@ pub fn new(
         blockchain: Arc::clone(blockchain),
         mempool: Arc::clone(mempool),
         txBlockmempool: Arc::clone(txBlockmempool),
-        txBlockOrderedList: Arc::clone(txBlockOrderedList),
+        unconfirmedTxBlocks: Arc::clone(unconfirmedTxBlocks),
         spb: Arc::clone(spb),
+        events: events.clone(),
+        metrics: Arc::clone(metrics),
+        pow: Arc::clone(pow),
+        verifier: verifier.clone(),
+        pruning,
+        threads: threads.max(1),
         attack,
     };
 
     let handle = Handle {
         control_chan: signal_chan_sender,
+        metrics: Arc::clone(metrics),
+        threads: ctx.threads,
     };
 
     (ctx, handle)
@@ -92,6 +162,25 @@ impl Handle {
             .unwrap();
     }
 
+    pub fn pause(&self) {
+        self.control_chan.send(ControlSignal::Pause).unwrap();
+    }
+
+    /// Number of nonce search threads.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Hashes per second over the last second, 0 while paused.
+    pub fn hash_rate(&self) -> u64 {
+        self.metrics.hash_rate.load(Ordering::Relaxed)
+    }
+
+    /// Hashes tried since the node started.
+    pub fn hash_attempts(&self) -> u64 {
+        self.metrics.hash_attempts.load(Ordering::Relaxed)
+    }
+
 }
 
 impl Context {
@@ -110,325 +199,444 @@ impl Context {
             ControlSignal::Exit => {
                 info!("Miner shutting down");
                 self.operating_state = OperatingState::ShutDown;
+                self.events.publish(Event::MinerStateChanged { state: "shutdown".to_string(), lambda: None });
+            }
+            ControlSignal::Pause => {
+                info!("Miner paused");
+                self.operating_state = OperatingState::Paused;
+                self.events.publish(Event::MinerStateChanged { state: "paused".to_string(), lambda: None });
             }
             ControlSignal::Start(i) => {
                 info!("Miner starting in continuous mode with lambda {}", i);
                 self.operating_state = OperatingState::Run(i);
+                self.events.publish(Event::MinerStateChanged { state: "running".to_string(), lambda: Some(i) });
             }
         }
     }
 
     fn miner_loop(&mut self) {
-        // main mining loop
+        // spawn the nonce search threads, each with its own job channel
+        let (found_sender, found_receiver) = unbounded();
+        let mut jobs: Vec<Sender<Job>> = vec![];
+        for i in 0..self.threads {
+            let (job_sender, job_receiver) = unbounded();
+            let searcher = Searcher {
+                index: i,
+                threads: self.threads,
+                pow: Arc::clone(&self.pow),
+                jobs: job_receiver,
+                found: found_sender.clone(),
+                metrics: Arc::clone(&self.metrics),
+                attack: self.attack,
+            };
+            thread::Builder::new()
+                .name(format!("miner-{}", i))
+                .spawn(move || {
+                    searcher.search_loop();
+                })
+                .unwrap();
+            jobs.push(job_sender);
+        }
 
-        let mut counter = 0;
+        let mut template: Option<Arc<Template>> = None;
+        let mut next_template_id: u64 = 0;
+        // template id and lambda the search threads are currently working with
+        let mut dispatched: Option<(u64, u64)> = None;
+        let mut rate_window_start = SystemTime::now();
+        let mut rate_window_attempts = self.metrics.hash_attempts.load(Ordering::Relaxed);
+        // the worker, the API and the generator publish an event for every change of the
+        // template inputs, the template is only rebuilt after one
+        let changes = self.events.subscribe();
+        let mut changed = true;
 
         loop {
-            // check and react to control signals
-            
+            // wait for a control signal, a found block, a change or the hash rate window
             match self.operating_state {
                 OperatingState::Paused => {
                     let signal = self.control_chan.recv().unwrap();
                     self.handle_control_signal(signal);
-                    continue;
+                    rate_window_start = SystemTime::now();
+                    rate_window_attempts = self.metrics.hash_attempts.load(Ordering::Relaxed);
+                    changed = true;
                 }
+                OperatingState::ShutDown => {}
+                OperatingState::Run(_) => {
+                    let time_locks = match template.as_ref() {
+                        Some(current) if current.time_locked => channel::after(TIME_LOCK_REFRESH),
+                        _ => channel::never(),
+                    };
+                    select! {
+                        recv(self.control_chan) -> signal => match signal {
+                            Ok(signal) => self.handle_control_signal(signal),
+                            Err(_) => panic!("Miner control channel detached"),
+                        },
+                        recv(found_receiver) -> found => {
+                            let found = found.unwrap();
+                            // a block found on an outdated template may repeat transactions that
+                            // are no longer in the mempool, so it is dropped
+                            if let Some(current) = template.take() {
+                                if current.id == found.template {
+                                    self.commit(&found.block, found.kind, &current.content_hash);
+                                    changed = true;
+                                } else {
+                                    template = Some(current);
+                                }
+                            }
+                        },
+                        recv(changes) -> event => {
+                            changed |= event.map_or(false, |e| changes_template(&e.event));
+                            // a burst of changes rebuilds the template once
+                            for e in changes.try_iter() {
+                                changed |= changes_template(&e.event);
+                            }
+                        },
+                        recv(time_locks) -> _ => changed = true,
+                        default(time::Duration::from_secs(1)) => {},
+                    }
+                }
+            }
+
+            // hand the current job to the search threads
+            match self.operating_state {
                 OperatingState::ShutDown => {
+                    for job in jobs.iter() {
+                        job.send(Job::Exit).unwrap();
+                    }
+                    self.metrics.hash_rate.store(0, Ordering::Relaxed);
                     return;
                 }
-                _ => match self.control_chan.try_recv() {
-                    Ok(signal) => {
-                        self.handle_control_signal(signal);
+                OperatingState::Paused => {
+                    if dispatched.take().is_some() {
+                        for job in jobs.iter() {
+                            job.send(Job::Pause).unwrap();
+                        }
+                    }
+                    self.metrics.hash_rate.store(0, Ordering::Relaxed);
+                    continue;
+                }
+                OperatingState::Run(lambda) => {
+                    if changed {
+                        changed = false;
+                        if let Some(next) = self.build_template(template.as_ref(), next_template_id) {
+                            next_template_id += 1;
+                            template = Some(next);
+                        }
+                    }
+                    let current = template.as_ref().unwrap();
+                    if dispatched != Some((current.id, lambda)) {
+                        for job in jobs.iter() {
+                            job.send(Job::Mine(Arc::clone(current), lambda)).unwrap();
+                        }
+                        dispatched = Some((current.id, lambda));
                     }
-                    Err(TryRecvError::Empty) => {}
-                    Err(TryRecvError::Disconnected) => panic!("Miner control channel detached"),
-                },
+                }
             }
-            if let OperatingState::ShutDown = self.operating_state {
-                return;
+
+            if let Ok(elapsed) = rate_window_start.elapsed() {
+                if elapsed >= time::Duration::from_secs(1) {
+                    let attempts = self.metrics.hash_attempts.load(Ordering::Relaxed);
+                    let rate = (attempts - rate_window_attempts) * 1000 / (elapsed.as_millis() as u64);
+                    self.metrics.hash_rate.store(rate, Ordering::Relaxed);
+                    rate_window_start = SystemTime::now();
+                    rate_window_attempts = attempts;
+                }
             }
-            counter = counter % 100000;
-            counter += 1;
-            // println!("{:?}", counter);
-            // TODO: actual mining
+        }
+    }
+
+    /// Build a new template if the inputs differ from those of `current`. The shared state is
+    /// only locked while the inputs are read, the state the block commits to is computed after.
+    fn build_template(&self, current: Option<&Arc<Template>>, id: u64) -> Option<Arc<Template>> {
+        let timestamp:u128 = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+        let (parent, height, mut next_state, txBlocks, content, content_hash, tp, votes, time_locked) = {
             let temp = Arc::clone(&self.blockchain);
-            let mut blockchain = temp.lock().unwrap();
+            let blockchain = temp.lock().unwrap();
 
             let temp_mempool = Arc::clone(&self.mempool);
             let mut mempool = temp_mempool.lock().unwrap();
 
             let temp_txBlockmempool = Arc::clone(&self.txBlockmempool);
-            let mut txBlockmempool = temp_txBlockmempool.lock().unwrap();
+            let txBlockmempool = temp_txBlockmempool.lock().unwrap();
 
-            let temp_txBlockOrderedList = Arc::clone(&self.txBlockOrderedList);
-            let mut txBlockOrderedList = temp_txBlockOrderedList.lock().unwrap();
+            let temp_unconfirmedTxBlocks = Arc::clone(&self.unconfirmedTxBlocks);
+            let unconfirmedTxBlocks = temp_unconfirmedTxBlocks.lock().unwrap();
 
             let temp_spb = Arc::clone(&self.spb);
-            let mut spb = temp_spb.lock().unwrap();
-            let parent = blockchain.tip();
-            let timestamp:u128 = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
-            let difficultyForPr = blockchain.blocks[&parent].header.difficultyForPr;
-            let difficultyForTx = blockchain.blocks[&parent].header.difficultyForTx;
+            let spb = temp_spb.lock().unwrap();
 
-            let mut content:Vec<SignedTransaction> = vec![];
-            let mut content_hash: Vec<H256> = vec![];
+            let parent = blockchain.tip();
             let mempool_capacity = 8;
+            let vote_capacity = 8;
+
+            // Transactions whose nonce the tip state already used can never be applied, they are
+            // dropped from the mempool instead of being mined. Signatures were checked before the
+            // transactions entered the mempool.
+            let state = &spb.spb[&parent];
+            let stale: Vec<H256> = mempool.transactions.iter()
+                .filter(|(_, t)| state.staleCheck(t))
+                .map(|(h, _)| *h)
+                .collect();
+            for h in stale.iter() {
+                mempool.transactions.remove(h);
+            }
+            mempool.immature.retain(|_, t| !state.staleCheck(t));
+            // time-locked transactions join the candidates once the block can apply them
+            let promoted = mempool.promote(blockchain.height[&parent] + 1, timestamp);
+            if !stale.is_empty() || promoted > 0 {
+                Metrics::set(&self.metrics.mempool_size, mempool.transactions.len());
+            }
 
-            // Miner can put transactions into block content. CODE
-            if mempool.transactions.len() <= mempool_capacity {
-                for (h, t) in mempool.transactions.iter() {
-                    content.push((*t).clone());
-                    content_hash.push((*h).clone());
+            // Miner can put transactions into block content, one per sender and nonce or spent
+            // output. The account transactions of a sender go in nonce order, starting at the
+            // nonce after the ones waiting in transaction blocks, and stop at the first gap. CODE
+            let pending = PendingNonces::new(state, &txBlockmempool.txBlocks, None);
+            let mut candidates: Vec<(&H256, &SignedTransaction)> = mempool.transactions.iter().collect();
+            candidates.sort_by_key(|(_, t)| t.sender_nonce());
+            let mut content_hash: Vec<H256> = vec![];
+            let mut claims = HashSet::new();
+            let mut nextNonce: HashMap<H160, u32> = HashMap::new();
+            for (h, t) in candidates {
+                if content_hash.len() == mempool_capacity {
+                    break;
                 }
-            } else {
-                let mut num = 0;
-                for (h, t) in mempool.clone().transactions.iter() {
-                    content.push((*t).clone());
-                    content_hash.push((*h).clone());
-                    num += 1;
-                    if num == mempool_capacity {
-                        break;
+                if let Some((sender, accountNonce)) = t.sender_nonce() {
+                    let next = nextNonce.entry(sender).or_insert_with(|| pending.next(&sender));
+                    if accountNonce != *next {
+                        continue;
                     }
+                    *next += 1;
+                }
+                let txClaims = t.claims();
+                if txClaims.iter().all(|c| !claims.contains(c)) {
+                    claims.extend(txClaims);
+                    content_hash.push(*h);
                 }
             }
 
-            let mut tp: Vec<H256> = vec![];
-            let mut orderList = spb.spb[&parent].txBlockOrderedList.clone();
-            for txBlock in (*txBlockOrderedList).clone() {
-                if !orderList.contains(&txBlock) {
-                    tp.push(txBlock);
+            // point to every received transaction block the parent has not confirmed yet
+            let confirmed = &state.txBlockOrderedList;
+            let tp: Vec<H256> = unconfirmedTxBlocks.iter().filter(|h| !confirmed.contains(h)).copied().collect();
+
+            let votes = blockchain.unvoted(vote_capacity);
+
+            if let Some(current) = current {
+                if current.block.header.parent == parent
+                    && current.content_hash == content_hash
+                    && current.block.txPointer.tp == tp
+                    && current.block.votes.pr == votes
+                {
+                    return None;
                 }
             }
 
-            let txPointer = TxPointer{tp:tp.clone()};
-
-            // println!("{:?}!!!!!!!!!!!!!!!!!{:?}", content.len(), mempool.transactions.len());
-            let root = MerkleTree::new1(&content, &tp).root();
-            // println!("!!!!!!!yes!!!!!");
-            let mut rng = rand::thread_rng();
-            let nonce: u32 = rng.gen();
-            let header:Header = Header{parent:parent,
-                                       nonce:nonce,
-                                       difficultyForPr:difficultyForPr,
-                                       difficultyForTx:difficultyForTx,
-                                       timestamp:timestamp,
-                                       merkle_root:root
-                                    };
-            let content:Content = Content{data:content};
-            let block: Block = Block{header: header, txPointer: txPointer, content: content.clone()};
-            
-            // println!("Mempool length: {:?}", (*mempool).transactions.len());
-            if self.attack != 2 {
-                if block.hash()<= difficultyForPr {
-                    //Arc::make_mut(&mut self.blockchain).get_mut().unwrap().insert(&block);
-                    let mut state = spb.spb[&block.header.parent].clone();
-                    let mut validTransaction = vec![];
-                    // let contents = &(&block.clone()).content.data;
-                    for txpointer in tp.clone() {
-                        let txBlk = txBlockmempool.txBlocks[&txpointer].clone();
-                        let contents = txBlk.content.data;
-                        for signedTransaction in contents {
-                            let signature = &signedTransaction.signature;
-                            let public_key = &signedTransaction.public_key;
-                            let transaction = &signedTransaction.transaction;
-        
-                            // println!("2");
-                            let recipientAddr = transaction.recipientAddr;
-                            let value = transaction.value;
-                            let accountNonce = transaction.accountNonce;
-                            // println!("21");
-                            let senderAddr: H160 = public_key[..].into();
-                            // println!("22");
-                            if !state.addressCheck(&public_key[..]) {
-                                state.insert(public_key[..].into(), 1000, 0);
-                                info!("Offer 1000 coins to address {}", senderAddr);
-                            }
-                            if !state.states.contains_key(&recipientAddr){
-                                state.insert(recipientAddr, 1000, 0);
-                                info!("Offer 1000 coins to address {}", senderAddr);
-                            }
-                            // println!("23");
-                            if state.spendCheck(&public_key[..], value, accountNonce) {
-                                // println!("231");
-                                // let sender = state.states[&senderAddr];
-                                // // println!("2311");
-                                // let repient = state.states[&recipientAddr];
-                                // // println!("232");
-                                // state.insert(senderAddr,(sender.1)-value, (sender.0)+1);
-                                // state.insert(recipientAddr,(repient.1)+value, repient.0);
-                                validTransaction.push(signedTransaction);
-                            }
-                        }
-                    }
-                    
-                    
-                    for vt in validTransaction {
-                        let public_key = &vt.public_key;
-                        let transaction = &vt.transaction;
-                        let recipientAddr = transaction.recipientAddr;
-                        let senderAddr: H160 = public_key[..].into();
-                        let value = transaction.value;
-                        let sender = state.states[&senderAddr];
-                        let repient = state.states[&recipientAddr];
-                        state.insert(senderAddr,(sender.1)-value, (sender.0)+1);
-                        state.insert(recipientAddr,(repient.1)+value, repient.0);
-                        info!("{:} received {:?} coins from {:}", 
-                            recipientAddr,
-                            value,
-                            senderAddr,
-                        );
-                    }
-                    // for (key, val) in state.states.iter() {
-                    //     info!("Address: {} has balance: {}, nonce: {}", key, val.1, val.0);
-                    // }
-                    for txpointer in tp.clone() {
-                        state.txBlockOrderedList.push(txpointer);
-                    }
+            let txBlocks: HashMap<H256, Block> = tp.iter()
+                .filter_map(|h| txBlockmempool.txBlocks.get(h).map(|b| (*h, b.clone())))
+                .collect();
+            let content: Vec<SignedTransaction> = content_hash.iter().map(|h| mempool.transactions[h].clone()).collect();
+            (blockchain.blocks[&parent].clone(), blockchain.height[&parent] + 1, state.clone(), txBlocks, content, content_hash, tp, votes, !mempool.immature.is_empty())
+        };
+
+        // the state the block commits to, should it become a proposer block
+        if let Err(missing) = next_state.apply_tx_blocks(&tp, &txBlocks, height, timestamp, &self.verifier) {
+            warn!("Transaction blocks {:?} are gone, template not built", missing);
+            return None;
+        }
 
-                    (*spb).insert(block.hash(),&state);
-                    (*blockchain).insert(&block);
-                    // for key in content_hash.clone(){
-                    //     (*mempool).transactions.remove(&key);
-                    // }
-
-                    // for (key, value) in (*mempool).transactions.clone().iter() {
-                    //     for t in contents {
-                    //         if (t.public_key == value.public_key) && (t.transaction.accountNonce == value.transaction.accountNonce) {
-                    //             (*mempool).transactions.remove(&key);
-                    //         }
-                    //     }
-                    // }
-                    // let transactions_num = content_hash.len();
-                    // let currentTime = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
-                    // let durationSinceMined = currentTime - block.header.timestamp;
-                    let mut v = vec![];
-                    v.push(block.hash());
-                    self.server.broadcast(Message::NewPrBlockHashes(v));
-                    // counter += 1;
-                    // let encoded_block: Vec<u8> = bincode::serialize(&block).unwrap();
-                    // println!("!!!!!!!!!!!!!!!I did it! Counter: {:?}, Block size is: {:?}, Block contains {:?} transactions", counter, encoded_block.len(), transactions_num);
-                    println!("!!!!!!!!");
-                    info!("Pr block succeed !! Blockchain length: {:?}, Block tip: {:?}", blockchain.blocks.len(), (*blockchain).tip());
-                    // println!("!!!!!!!!");
-                    //self.blockchain = Arc::new(Mutex::new(blockchain));
-                }
-                else if block.hash()<= difficultyForTx {
-                    (*txBlockmempool).insert(&block);
-                    (*txBlockOrderedList).push(block.hash());
+        let block = self.pow.build_template(
+            &parent,
+            Content{data: content},
+            TxPointer{tp},
+            Votes{pr: votes},
+            next_state.state_root(),
+            timestamp,
+        );
+        return Some(Arc::new(Template { id, block, content_hash, time_locked }));
+    }
 
-                    for key in content_hash.clone(){
-                        (*mempool).transactions.remove(&key);
-                    }
+    /// Insert a block found by a search thread, taking the shared locks only for the insertion.
+    /// Only the content of the block's own type is kept.
+    fn commit(&self, block: &Block, kind: BlockKind, content_hash: &[H256]) {
+        let block = &block.for_kind(kind);
 
-                    for (key, value) in (*mempool).transactions.clone().iter() {
-                        for t in &content.data {
-                            if (t.public_key == value.public_key) && (t.transaction.accountNonce == value.transaction.accountNonce) {
-                                (*mempool).transactions.remove(&key);
-                            }
-                        }
+        let temp = Arc::clone(&self.blockchain);
+        let mut blockchain = temp.lock().unwrap();
+
+        let temp_mempool = Arc::clone(&self.mempool);
+        let mut mempool = temp_mempool.lock().unwrap();
+
+        let temp_txBlockmempool = Arc::clone(&self.txBlockmempool);
+        let mut txBlockmempool = temp_txBlockmempool.lock().unwrap();
+
+        let temp_unconfirmedTxBlocks = Arc::clone(&self.unconfirmedTxBlocks);
+        let mut unconfirmedTxBlocks = temp_unconfirmedTxBlocks.lock().unwrap();
+
+        let temp_spb = Arc::clone(&self.spb);
+        let mut spb = temp_spb.lock().unwrap();
+
+        let parent = block.header.parent;
+        let tp = &block.txPointer.tp;
+
+        match kind {
+            BlockKind::Proposer => {
+                // the worker may have pruned the state of the parent since the template was built
+                let mut state = match spb.spb.get(&parent) {
+                    Some(state) => state.clone(),
+                    None => {
+                        warn!("Pr block {} found after the state of its parent was pruned, dropped", block.hash());
+                        return;
                     }
-                    // let transactions_num = content_hash.len();
-                    // let currentTime = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
-                    // let durationSinceMined = currentTime - block.header.timestamp;
-                    let mut v = vec![];
-                    v.push(block.hash());
-                    self.server.broadcast(Message::NewTxBlockHashes(v));
-                    // counter += 1;
-                    // let encoded_block: Vec<u8> = bincode::serialize(&block).unwrap();
-                    // println!("!!!!!!!!!!!!!!!I did it! Counter: {:?}, Block size is: {:?}, Block contains {:?} transactions", counter, encoded_block.len(), transactions_num);
-                    println!("????????");
-                    // info!("Tx block succeed !! Blockchain length: {:?}, Block tip: {:?}", blockchain.blocks.len(), (*blockchain).tip());
-                    info!("Tx block succeed !! TxBlock contains {:?} transactions", block.content.data.len());
-                    // println!("????????");
+                };
+                if state.apply_tx_blocks(tp, &txBlockmempool.txBlocks, blockchain.height[&parent] + 1, block.header.timestamp, &self.verifier).is_err() {
+                    warn!("Pr block {} found after a transaction block it points to was pruned, dropped", block.hash());
+                    return;
+                }
+                // the search may have moved the timestamp past a time lock since the template
+                // was built, the block would then commit to the wrong state
+                if state.state_root() != block.header.state_root {
+                    warn!("Pr block {} found after its state changed, dropped", block.hash());
+                    return;
+                }
+                for skipped in state.skipped.iter() {
+                    self.metrics.skipped_transactions.inc(skipped.reason.label());
                 }
-            }
-            else{
-                if counter % 1000 == 0 {
-                    let mut state = spb.spb[&block.header.parent].clone();
-                    let mut validTransaction = vec![];
-                    // let contents = &(&block.clone()).content.data;
-                    for txpointer in tp.clone() {
-                        let txBlk = txBlockmempool.txBlocks[&txpointer].clone();
-                        let contents = txBlk.content.data;
-                        for signedTransaction in contents {
-                            let signature = &signedTransaction.signature;
-                            let public_key = &signedTransaction.public_key;
-                            let transaction = &signedTransaction.transaction;
-        
-                            // println!("2");
-                            let recipientAddr = transaction.recipientAddr;
-                            let value = transaction.value;
-                            let accountNonce = transaction.accountNonce;
-                            // println!("21");
-                            let senderAddr: H160 = public_key[..].into();
-                            // println!("22");
-                            if !state.addressCheck(&public_key[..]) {
-                                state.insert(public_key[..].into(), 1000, 0);
-                                info!("Offer 1000 coins to address {}", senderAddr);
-                            }
-                            if !state.states.contains_key(&recipientAddr){
-                                state.insert(recipientAddr, 1000, 0);
-                                info!("Offer 1000 coins to address {}", senderAddr);
-                            }
-                            // println!("23");
-                            if state.spendCheck(&public_key[..], value, accountNonce) {
-                                // println!("231");
-                                // let sender = state.states[&senderAddr];
-                                // // println!("2311");
-                                // let repient = state.states[&recipientAddr];
-                                // // println!("232");
-                                // state.insert(senderAddr,(sender.1)-value, (sender.0)+1);
-                                // state.insert(recipientAddr,(repient.1)+value, repient.0);
-                                validTransaction.push(signedTransaction);
-                            }
-                        }
-                    }
-                    
-                    
-                    for vt in validTransaction {
-                        let public_key = &vt.public_key;
-                        let transaction = &vt.transaction;
-                        let recipientAddr = transaction.recipientAddr;
-                        let senderAddr: H160 = public_key[..].into();
-                        let value = transaction.value;
-                        let sender = state.states[&senderAddr];
-                        let repient = state.states[&recipientAddr];
-                        state.insert(senderAddr,(sender.1)-value, (sender.0)+1);
-                        state.insert(recipientAddr,(repient.1)+value, repient.0);
-                        info!("{:} received {:?} coins from {:}", 
-                            recipientAddr,
-                            value,
-                            senderAddr,
-                        );
-                    }
-                    // for (key, val) in state.states.iter() {
-                    //     info!("Address: {} has balance: {}, nonce: {}", key, val.1, val.0);
-                    // }
-                    for txpointer in tp.clone() {
-                        state.txBlockOrderedList.push(txpointer);
-                    }
 
-                    (*spb).insert(block.hash(),&state);
-                    (*blockchain).insert(&block);
-                    
-                    let mut v = vec![];
-                    v.push(block.hash());
-                    self.server.broadcast(Message::NewPrBlockHashes(v));
-                    // counter += 1;
-                    // let encoded_block: Vec<u8> = bincode::serialize(&block).unwrap();
-                    // println!("!!!!!!!!!!!!!!!I did it! Counter: {:?}, Block size is: {:?}, Block contains {:?} transactions", counter, encoded_block.len(), transactions_num);
+                let old_tip = blockchain.tip();
+                (*spb).insert(block.hash(),&state);
+                (*blockchain).insert(&block);
+                unconfirmedTxBlocks.follow(&blockchain, &old_tip, &spb, &txBlockmempool.txBlocks);
+                let pruned = self.pruning.prune(&blockchain, &mut spb, &mut txBlockmempool);
+                self.metrics.pruned_states.fetch_add(pruned.states as u64, Ordering::Relaxed);
+                self.metrics.pruned_tx_blocks.fetch_add(pruned.tx_blocks as u64, Ordering::Relaxed);
+
+                let mut v = vec![];
+                v.push(block.hash());
+                self.server.broadcast(Message::NewPrBlockHashes(v));
+                if self.attack == 2 {
                     println!("Attacking!!!!!!!!");
                     info!("Fake Pr block succeed !!");
+                } else {
+                    println!("!!!!!!!!");
+                    info!("Pr block succeed !! Blockchain length: {:?}, Block tip: {:?}", blockchain.blocks.len(), (*blockchain).tip());
+                }
+                self.metrics.pr_blocks.inc("mined");
+                Metrics::set(&self.metrics.chain_height, blockchain.height[&blockchain.tip()] as usize);
+                self.events.publish(Event::NewPrBlock {
+                    hash: block.hash().to_string(),
+                    parent: parent.to_string(),
+                    height: blockchain.height[&block.hash()],
+                    mined: true,
+                });
+            }
+            BlockKind::Transaction => {
+                (*txBlockmempool).insert(&block);
+                (*unconfirmedTxBlocks).insert(block.hash());
+
+                for key in content_hash {
+                    (*mempool).transactions.remove(key);
                 }
 
+                (*mempool).remove_conflicts(&block.content.data);
+                let mut v = vec![];
+                v.push(block.hash());
+                self.server.broadcast(Message::NewTxBlockHashes(v));
+                println!("????????");
+                info!("Tx block succeed !! TxBlock contains {:?} transactions", block.content.data.len());
+                self.metrics.tx_blocks.inc("mined");
+                Metrics::set(&self.metrics.mempool_size, mempool.transactions.len());
+                self.events.publish(Event::NewTxBlock {
+                    hash: block.hash().to_string(),
+                    transactions: block.content.data.len(),
+                    mined: true,
+                });
             }
-            
+            BlockKind::Voter => {
+                (*blockchain).insert_voter(&block);
+                let mut v = vec![];
+                v.push(block.hash());
+                self.server.broadcast(Message::NewVoterBlockHashes(v));
+                info!("Voter block succeed !! VoterBlock votes for {:?} proposer blocks", block.votes.pr.len());
+                self.metrics.voter_blocks.inc("mined");
+                self.events.publish(Event::NewVoterBlock {
+                    hash: block.hash().to_string(),
+                    votes: block.votes.pr.len(),
+                    mined: true,
+                });
+            }
+        }
+    }
+}
 
-            if let OperatingState::Run(i) = self.operating_state {
-                if i != 0 {
-                    let interval = time::Duration::from_micros(i as u64);
-                    thread::sleep(interval);
+impl Searcher {
+    fn search_loop(&self) {
+        let mut job = match self.jobs.recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        loop {
+            let (template, lambda) = match job {
+                Job::Mine(template, lambda) => (template, lambda),
+                Job::Pause => {
+                    job = match self.jobs.recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    continue;
                 }
+                Job::Exit => return,
+            };
+            job = match self.search(&template, lambda) {
+                Some(job) => job,
+                None => return,
+            };
+        }
+    }
+
+    /// Try nonces on `template` until the miner thread sends the next job.
+    fn search(&self, template: &Template, lambda: u64) -> Option<Job> {
+        let mut header = template.block.header.clone();
+        let mut nonces = NonceIter::new(self.index, self.threads, header.timestamp);
+        // with a sleep between attempts they are made one at a time
+        let batch = if lambda == 0 { SEARCH_BATCH } else { 1 };
+        let mut counter: u64 = 0;
+        loop {
+            match self.jobs.try_recv() {
+                Ok(job) => return Some(job),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return None,
+            }
+            let search = self.pow.search(&mut header, &mut nonces, batch);
+            self.metrics.hash_attempts.fetch_add(search.attempts, Ordering::Relaxed);
+
+            // the censorship attacker publishes a proposer block every 1000 attempts, valid or not
+            let kind = if self.attack == 2 {
+                counter += search.attempts;
+                if counter >= 1000 {
+                    counter -= 1000;
+                    Some(BlockKind::Proposer)
+                } else {
+                    None
+                }
+            } else {
+                search.found
+            };
+            if let Some(kind) = kind {
+                let mut block = template.block.clone();
+                block.header = header.clone();
+                self.found.send(Found { template: template.id, block, kind }).unwrap();
+            }
+
+            if lambda != 0 {
+                let interval = time::Duration::from_micros(lambda);
+                thread::sleep(interval);
             }
         }
     }
 }
+
+/// Whether `event` changes the inputs of the block template.
+fn changes_template(event: &Event) -> bool {
+    match event {
+        Event::NewPrBlock { .. } | Event::NewTxBlock { .. } | Event::NewVoterBlock { .. } | Event::Reorg { .. } | Event::TransactionAccepted { .. } => true,
+        _ => false,
+    }
+}
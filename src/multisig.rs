@@ -0,0 +1,145 @@
+use serde::{Serialize, Deserialize};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::collections::HashSet;
+use std::fmt;
+use crate::crypto::address::H160;
+
+/// Most keys a multisig address can have.
+pub const MAX_KEYS: usize = 16;
+/// Prefixed to the encoded policy before hashing it into an address, so that a multisig address
+/// never equals the address of a single key.
+const ADDRESS_TAG: &[u8] = b"prism-multisig";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultisigError {
+    /// The threshold is zero or above the number of keys.
+    InvalidThreshold,
+    /// A key is not a 32 byte Ed25519 public key, or there are more than `MAX_KEYS`.
+    InvalidKey,
+    DuplicateKey,
+    /// Fewer keys than the threshold signed.
+    NotEnoughSignatures,
+}
+
+impl fmt::Display for MultisigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultisigError::InvalidThreshold => write!(f, "threshold must be between 1 and the number of keys"),
+            MultisigError::InvalidKey => write!(f, "keys must be at most {} Ed25519 public keys", MAX_KEYS),
+            MultisigError::DuplicateKey => write!(f, "duplicate key"),
+            MultisigError::NotEnoughSignatures => write!(f, "fewer signatures than the threshold"),
+        }
+    }
+}
+
+/// An M-of-N policy: `threshold` of the sorted `public_keys` must sign. The address is derived
+/// from the policy, so every signer computes the same address from the same keys in any order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Multisig {
+    pub threshold: u32,
+    pub public_keys: Vec<Vec<u8>>,
+}
+
+/// A signature by the key at `index` in the sorted keys of a policy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeySignature {
+    pub index: u32,
+    pub signature: Vec<u8>,
+}
+
+impl Multisig {
+    pub fn new(threshold: u32, mut public_keys: Vec<Vec<u8>>) -> Result<Multisig, MultisigError> {
+        public_keys.sort();
+        let multisig = Multisig { threshold, public_keys };
+        multisig.check()?;
+        return Ok(multisig);
+    }
+
+    /// Whether the keys are sorted, distinct and well formed and the threshold can be met. A
+    /// policy received from the network is not trusted to be.
+    pub fn check(&self) -> Result<(), MultisigError> {
+        if self.public_keys.len() > MAX_KEYS || self.public_keys.iter().any(|k| k.len() != 32) {
+            return Err(MultisigError::InvalidKey);
+        }
+        if self.public_keys.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(MultisigError::DuplicateKey);
+        }
+        if self.threshold == 0 || self.threshold as usize > self.public_keys.len() {
+            return Err(MultisigError::InvalidThreshold);
+        }
+        return Ok(());
+    }
+
+    pub fn address(&self) -> H160 {
+        let encoded: Vec<u8> = bincode::serialize(&(ADDRESS_TAG, self)).unwrap();
+        encoded[..].into()
+    }
+
+    /// Sign `message` with those of `keys` that belong to the policy.
+    pub fn sign(&self, message: &[u8], keys: &[&Ed25519KeyPair]) -> Vec<KeySignature> {
+        let mut signatures = vec![];
+        for (index, public_key) in self.public_keys.iter().enumerate() {
+            if let Some(key) = keys.iter().find(|k| k.public_key().as_ref() == &public_key[..]) {
+                signatures.push(KeySignature { index: index as u32, signature: key.sign(message).as_ref().to_vec() });
+            }
+        }
+        signatures
+    }
+
+    /// Whether the policy is valid and at least `threshold` distinct keys signed `message`.
+    pub fn verify(&self, message: &[u8], signatures: &[KeySignature]) -> bool {
+        if self.check().is_err() {
+            return false;
+        }
+        let mut signers = HashSet::new();
+        for s in signatures {
+            let public_key = match self.public_keys.get(s.index as usize) {
+                Some(k) => k,
+                None => return false,
+            };
+            let public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key);
+            if public_key.verify(message, &s.signature).is_err() {
+                return false;
+            }
+            signers.insert(s.index);
+        }
+        signers.len() >= self.threshold as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key_pair;
+
+    #[test]
+    fn threshold() {
+        let keys: Vec<Ed25519KeyPair> = (0..3).map(|_| key_pair::random()).collect();
+        let public_keys: Vec<Vec<u8>> = keys.iter().map(|k| k.public_key().as_ref().to_vec()).collect();
+        let multisig = Multisig::new(2, public_keys.clone()).unwrap();
+        let mut reversed = public_keys.clone();
+        reversed.reverse();
+        assert_eq!(Multisig::new(2, reversed).unwrap().address(), multisig.address());
+        assert_ne!(Multisig::new(1, public_keys.clone()).unwrap().address(), multisig.address());
+        let single: H160 = keys[0].public_key().as_ref().into();
+        assert_ne!(Multisig::new(1, vec![public_keys[0].clone()]).unwrap().address(), single);
+
+        let message = b"pay bob";
+        let one = multisig.sign(message, &[&keys[0]]);
+        assert!(!multisig.verify(message, &one));
+        // the same key signing twice does not meet the threshold
+        let twice: Vec<KeySignature> = one.iter().chain(one.iter()).cloned().collect();
+        assert!(!multisig.verify(message, &twice));
+        let two = multisig.sign(message, &[&keys[2], &keys[0]]);
+        assert!(multisig.verify(message, &two));
+        assert!(!multisig.verify(b"pay carol", &two));
+        let outsider = key_pair::random();
+        assert!(multisig.sign(message, &[&outsider]).is_empty());
+
+        assert_eq!(Multisig::new(0, public_keys.clone()), Err(MultisigError::InvalidThreshold));
+        assert_eq!(Multisig::new(4, public_keys.clone()), Err(MultisigError::InvalidThreshold));
+        let duplicate = vec![public_keys[0].clone(), public_keys[0].clone()];
+        assert_eq!(Multisig::new(1, duplicate), Err(MultisigError::DuplicateKey));
+        assert_eq!(Multisig::new(1, vec![vec![0u8; 31]]), Err(MultisigError::InvalidKey));
+    }
+}
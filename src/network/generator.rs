@@ -4,6 +4,7 @@ use crate::network::server::Handle as ServerHandle;
 use crossbeam::channel;
 use log::{debug, warn, info};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use crate::blockchain::Blockchain;
 use crate::block::Block;
 use crate::crypto::hash::{Hashable,H256};
@@ -11,7 +12,9 @@ use std::collections::HashMap;
 use std::time::SystemTime;
 use std::thread;
 use ring::signature::{Signature, KeyPair, Ed25519KeyPair};
-use crate::transaction::{verify,sign,Mempool,Transaction,SignedTransaction,State};
+use crate::transaction::{verify,sign,AccountTransaction,ChainId,LedgerMode,Mempool,PendingNonces,Transaction,TxBlockMempool,SignedTransaction,StatePerBlock,spendable,COIN,INITIAL_BALANCE};
+use crate::utxo::{OutPoint, Output, UtxoTransaction};
+use crate::event::{Event, EventBus};
 use rand::Rng;
 use crate::crypto::key_pair;
 use std::time;
@@ -25,37 +28,74 @@ pub struct Context {
     // msg_chan: channel::Receiver<(Vec<u8>, peer::Handle)>,
     num_worker: usize,
     server: ServerHandle,
-    // blockchain: Arc<Mutex<Blockchain>>,
+    blockchain: Arc<Mutex<Blockchain>>,
     // buffer: HashMap<H256, Block>,
     mempool: Arc<Mutex<Mempool>>,
+    txBlockmempool: Arc<Mutex<TxBlockMempool>>,
     // keyPairs: Vec<Ed25519KeyPair>,
-    state: Arc<Mutex<State>>,
+    spb: Arc<Mutex<StatePerBlock>>,
     key_set: Arc<Mutex<HashMap<u32, Ed25519KeyPair>>>,
     attacker: usize,
+    ledger: LedgerMode,
+    chain_id: ChainId,
+    events: EventBus,
+    running: Arc<AtomicBool>,
+}
+
+#[derive(Clone)]
+pub struct Handle {
+    /// Whether the generator threads should produce transactions
+    running: Arc<AtomicBool>,
 }
 
 pub fn new(
     num_worker: usize,
     // msg_src: channel::Receiver<(Vec<u8>, peer::Handle)>,
     server: &ServerHandle,
-    // blockchain: &Arc<Mutex<Blockchain>>,
+    blockchain: &Arc<Mutex<Blockchain>>,
     mempool: &Arc<Mutex<Mempool>>,
+    txBlockmempool: &Arc<Mutex<TxBlockMempool>>,
     // keyPairs: Vec<Ed25519KeyPair>,
-    state: &Arc<Mutex<State>>,
+    spb: &Arc<Mutex<StatePerBlock>>,
     key_set: &Arc<Mutex<HashMap<u32, Ed25519KeyPair>>>,
     attacker: usize,
-) -> Context {
-    Context {
+    ledger: LedgerMode,
+    chain_id: ChainId,
+    events: &EventBus,
+) -> (Context, Handle) {
+    let running = Arc::new(AtomicBool::new(false));
+    let ctx = Context {
         // msg_chan: msg_src,
         num_worker,
         server: server.clone(),
-        // blockchain: Arc::clone(blockchain),
+        blockchain: Arc::clone(blockchain),
         // buffer: HashMap::new(),
         mempool: Arc::clone(mempool),
+        txBlockmempool: Arc::clone(txBlockmempool),
         // keyPairs: keyPairs.clone(),
-        state: Arc::clone(state),
+        spb: Arc::clone(spb),
         key_set: Arc::clone(key_set),
         attacker,
+        ledger,
+        chain_id,
+        events: events.clone(),
+        running: Arc::clone(&running),
+    };
+    let handle = Handle { running };
+    (ctx, handle)
+}
+
+impl Handle {
+    pub fn start(&self) {
+        self.running.store(true, Ordering::SeqCst);
+    }
+
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
     }
 }
 
@@ -81,16 +121,15 @@ impl Context {
         else {
             println!("No attack");
         }
-        let mut record: HashMap<u32, (u32, u32)> = HashMap::new();
-        for i in 0..5 {
-            record.insert(i,(0,1000));
-        }
         let duration = time::Duration::from_millis(5000);
         thread::sleep(duration);
         println!("Start Generator");
         loop {
             let duration = time::Duration::from_millis(500);
             thread::sleep(duration);
+            if !self.running.load(Ordering::SeqCst) {
+                continue;
+            }
             // println!("I'm running!");
             
             let accountNum = 5;
@@ -109,16 +148,37 @@ impl Context {
             let senderPublicKey_hash_h160:H160 = senderPublicKey.into();
 
             let recipientKeyPair = &key_set[&recipientIdx];
-            let recipientNonce = record[&recipientIdx].0;
-            let recipientBalance = record[&recipientIdx].1;
             let recipientPublicKey = (key_set[&recipientIdx]).public_key().as_ref();
             let recipientPublicKey_hash_h160:H160 = recipientPublicKey.into();
 
-            let mut currentBalance:u32 = 0;
+            // the account of the sender at the tip, and the nonce after its pending transactions
+            let temp_blockchain = Arc::clone(&self.blockchain);
+            let blockchain = temp_blockchain.lock().unwrap();
+            let temp_mempool = Arc::clone(&self.mempool);
+            let mut mempool = temp_mempool.lock().unwrap();
+            let temp_txBlockmempool = Arc::clone(&self.txBlockmempool);
+            let txBlockmempool = temp_txBlockmempool.lock().unwrap();
+            let temp_spb = Arc::clone(&self.spb);
+            let spb = temp_spb.lock().unwrap();
+            let state = &spb.spb[&blockchain.tip()];
+
+            let mut currentBalance:u64 = 0;
             let mut nonce:u32 = 0;
 
-            currentBalance = record[&senderIdx].1;
-            nonce = &record[&senderIdx].0+1;
+            // in the UTXO model, the outputs of the sender at the tip that no pending transaction
+            // spends
+            let outputs = match self.ledger {
+                LedgerMode::Account => vec![],
+                LedgerMode::Utxo => spendable(state, &txBlockmempool.txBlocks, &mempool, &senderPublicKey_hash_h160),
+            };
+            currentBalance = match self.ledger {
+                LedgerMode::Account => state.account(&senderPublicKey_hash_h160).1,
+                LedgerMode::Utxo => outputs.iter().fold(0, |sum: u64, (_, o)| sum.saturating_add(o.value)),
+            };
+            nonce = PendingNonces::new(state, &txBlockmempool.txBlocks, Some(&mempool)).next(&senderPublicKey_hash_h160);
+            if currentBalance == 0 {
+                continue;
+            }
             
 
             // let temp_state = Arc::clone(&self.state);
@@ -137,12 +197,10 @@ impl Context {
             // }
             let mut value = 0;
             if self.attacker == 1{
-                value = 2000;
+                value = 2 * INITIAL_BALANCE;
             }
-            else{
-                value = rng.gen_range(0,cmp::min(10,currentBalance)) as u32;
-                record.insert(senderIdx,(nonce, currentBalance-value));
-                record.insert(recipientIdx, (recipientNonce,recipientBalance+value));
+            else {
+                value = rng.gen_range(0,cmp::min(10 * COIN,currentBalance));
             }
             
             
@@ -154,17 +212,28 @@ impl Context {
             );
 
 
-            let transaction = Transaction{recipientAddr: recipientPublicKey.into(), value: value, accountNonce: nonce};
-            let sig = sign(&transaction, &senderKeyPair);
-            let signedT = SignedTransaction{signature: sig.as_ref().to_vec(), public_key: senderPublicKey.to_vec(), transaction: transaction};
+            let signedT = match self.ledger {
+                LedgerMode::Account => {
+                    let transaction = Transaction{recipientAddr: recipientPublicKey.into(), value: value, accountNonce: nonce, lock_height: None, lock_time: None};
+                    let sig = sign(&transaction, &senderKeyPair, &self.chain_id);
+                    SignedTransaction::Account(AccountTransaction{signature: sig.as_ref().to_vec(), public_key: senderPublicKey.to_vec(), transaction: transaction})
+                }
+                LedgerMode::Utxo => {
+                    // spend every output of the sender, paying the change back to it
+                    let spends: Vec<(OutPoint, &Ed25519KeyPair)> = outputs.iter().map(|(o, _)| (*o, senderKeyPair)).collect();
+                    let mut payments = vec![Output{recipientAddr: recipientPublicKey_hash_h160, value: value}];
+                    if currentBalance > value {
+                        payments.push(Output{recipientAddr: senderPublicKey_hash_h160, value: currentBalance - value});
+                    }
+                    SignedTransaction::Utxo(UtxoTransaction::sign(&spends, payments, &self.chain_id))
+                }
+            };
 
-            let temp_mempool = Arc::clone(&self.mempool);
-            let mut mempool = temp_mempool.lock().unwrap();
             (*mempool).insert(&signedT);
+            self.events.publish(Event::TransactionAccepted { hash: signedT.hash().to_string() });
             let mut broadcast_transactions_hashes = vec![];
             broadcast_transactions_hashes.push(signedT.clone().hash());
             self.server.broadcast(Message::NewTransactionHashes(broadcast_transactions_hashes));
-            // println!("{:?}", record);
 
             
         }
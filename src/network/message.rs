@@ -16,4 +16,29 @@ pub enum Message {
     NewTransactionHashes(Vec<H256>),
     GetTransactions(Vec<H256>),
     Transactions(Vec<SignedTransaction>),
+    NewVoterBlockHashes(Vec<H256>),
+    GetVoterBlocks(Vec<H256>),
+    VoterBlocks(Vec<Block>),
+}
+
+impl Message {
+    /// Name of the message type, used to label per-type metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Ping(_) => "Ping",
+            Message::Pong(_) => "Pong",
+            Message::NewPrBlockHashes(_) => "NewPrBlockHashes",
+            Message::GetPrBlocks(_) => "GetPrBlocks",
+            Message::PrBlocks(_) => "PrBlocks",
+            Message::NewTxBlockHashes(_) => "NewTxBlockHashes",
+            Message::GetTxBlocks(_) => "GetTxBlocks",
+            Message::TxBlocks(_) => "TxBlocks",
+            Message::NewTransactionHashes(_) => "NewTransactionHashes",
+            Message::GetTransactions(_) => "GetTransactions",
+            Message::Transactions(_) => "Transactions",
+            Message::NewVoterBlockHashes(_) => "NewVoterBlockHashes",
+            Message::GetVoterBlocks(_) => "GetVoterBlocks",
+            Message::VoterBlocks(_) => "VoterBlocks",
+        }
+    }
 }
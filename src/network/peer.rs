@@ -196,7 +196,7 @@ pub fn new(
     Ok((ctx, handle))
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub enum Direction {
     Incoming,
     Outgoing,
@@ -1,10 +1,13 @@
 use super::message;
 use super::peer::{self, ReadResult, WriteResult};
+use crate::event::{Event, EventBus};
+use crate::metrics::Metrics;
 use crossbeam::channel as cbchannel;
 use log::{debug, error, info, trace, warn};
 use mio::{self, net};
 use mio_extras::channel;
 use std::sync::mpsc;
+use std::sync::Arc;
 use std::thread;
 
 const MAX_INCOMING_CLIENT: usize = 256;
@@ -13,6 +16,8 @@ const MAX_EVENT: usize = 1024;
 pub fn new(
     addr: std::net::SocketAddr,
     msg_sink: cbchannel::Sender<(Vec<u8>, peer::Handle)>,
+    events: &EventBus,
+    metrics: &Arc<Metrics>,
 ) -> std::io::Result<(Context, Handle)> {
     let (control_signal_sender, control_signal_receiver) = channel::channel();
     let handle = Handle {
@@ -25,6 +30,8 @@ pub fn new(
         poll: mio::Poll::new()?,
         control_chan: control_signal_receiver,
         new_msg_chan: msg_sink,
+        events: events.clone(),
+        metrics: Arc::clone(metrics),
         _handle: handle.clone(),
     };
     Ok((ctx, handle))
@@ -37,6 +44,8 @@ pub struct Context {
     poll: mio::Poll,
     control_chan: channel::Receiver<ControlSignal>,
     new_msg_chan: cbchannel::Sender<(Vec<u8>, peer::Handle)>,
+    events: EventBus,
+    metrics: Arc<Metrics>,
     _handle: Handle,
 }
 
@@ -90,9 +99,11 @@ impl Context {
         )?;
 
         // insert the context and return the handle
+        self.events.publish(Event::PeerConnected { addr: ctx.addr.to_string() });
         vacant.insert(ctx);
         // record the key of this peer
         self.peer_list.push(key);
+        Metrics::set(&self.metrics.peers, self.peer_list.len());
         trace!("Registering peer with event token={}", key);
         Ok(handle)
     }
@@ -131,10 +142,26 @@ impl Context {
                 let handle = self.connect(&req.addr);
                 req.result_chan.send(handle).unwrap();
             }
+            ControlSignal::ListPeers(result_chan) => {
+                trace!("Processing ListPeers command");
+                let peers = self
+                    .peer_list
+                    .iter()
+                    .map(|peer_id| {
+                        let peer = &self.peers[*peer_id];
+                        PeerInfo {
+                            addr: peer.addr,
+                            direction: peer.direction,
+                        }
+                    })
+                    .collect();
+                result_chan.send(peers).unwrap();
+            }
             ControlSignal::BroadcastMessage(msg) => {
                 trace!("Processing BroadcastMessage command");
                 for peer_id in &self.peer_list {
                     self.peers[*peer_id].handle.write(msg.clone());
+                    self.metrics.outbound_messages.inc(msg.kind());
                 }
             }
         }
@@ -156,6 +183,15 @@ impl Context {
         Ok(())
     }
 
+    /// Forget a peer whose connection is gone.
+    fn drop_peer(&mut self, peer_id: usize) {
+        let peer = self.peers.remove(peer_id);
+        let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
+        self.peer_list.swap_remove(index);
+        Metrics::set(&self.metrics.peers, self.peer_list.len());
+        self.events.publish(Event::PeerDisconnected { addr: peer.addr.to_string() });
+    }
+
     fn process_readable(&mut self, peer_id: usize) -> std::io::Result<()> {
         // we are using edge-triggered events, loop until block
         let peer = &mut self.peers[peer_id];
@@ -164,9 +200,7 @@ impl Context {
                 Ok(ReadResult::EOF) => {
                     // EOF, remove it from the connections set
                     info!("Peer {} dropped connection", peer.addr);
-                    self.peers.remove(peer_id);
-                    let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                    self.peer_list.swap_remove(index);
+                    self.drop_peer(peer_id);
                     break;
                 }
                 Ok(ReadResult::Continue) => {
@@ -187,9 +221,7 @@ impl Context {
                         break;
                     } else {
                         warn!("Error reading peer {}, disconnecting: {}", peer.addr, e);
-                        self.peers.remove(peer_id);
-                        let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                        self.peer_list.swap_remove(index);
+                        self.drop_peer(peer_id);
                         break;
                     }
                 }
@@ -224,9 +256,7 @@ impl Context {
             Ok(WriteResult::EOF) => {
                 // EOF, remove it from the connections set
                 info!("Peer {} dropped connection", peer.addr);
-                self.peers.remove(peer_id);
-                let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                self.peer_list.swap_remove(index);
+                self.drop_peer(peer_id);
             }
             Ok(WriteResult::ChanClosed) => {
                 // the channel is closed. no more writes.
@@ -246,9 +276,7 @@ impl Context {
                 // socket is not ready anymore, stop reading
                 } else {
                     warn!("Error writing peer {}, disconnecting: {}", peer.addr, e);
-                    self.peers.remove(peer_id);
-                    let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                    self.peer_list.swap_remove(index);
+                    self.drop_peer(peer_id);
                 }
             }
         }
@@ -382,6 +410,15 @@ impl Handle {
         receiver.recv().unwrap()
     }
 
+    /// List the currently connected peers.
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let (sender, receiver) = cbchannel::unbounded();
+        self.control_chan
+            .send(ControlSignal::ListPeers(sender))
+            .unwrap();
+        receiver.recv().unwrap()
+    }
+
     pub fn broadcast(&self, msg: message::Message) {
         self.control_chan
             .send(ControlSignal::BroadcastMessage(msg))
@@ -392,6 +429,12 @@ impl Handle {
 enum ControlSignal {
     ConnectNewPeer(ConnectRequest),
     BroadcastMessage(message::Message),
+    ListPeers(cbchannel::Sender<Vec<PeerInfo>>),
+}
+
+pub struct PeerInfo {
+    pub addr: std::net::SocketAddr,
+    pub direction: peer::Direction,
 }
 
 struct ConnectRequest {
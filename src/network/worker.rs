@@ -5,14 +5,20 @@ use crossbeam::channel;
 use log::{debug, warn};
 use std::sync::{Arc, Mutex};
 use crate::blockchain::Blockchain;
-use crate::block::Block;
+use crate::block::{Block, BlockKind};
 use crate::crypto::hash::{Hashable,H256};
-use std::collections::HashMap;
+use crate::crypto::address::H160;
+use std::collections::{HashMap, HashSet};
 use std::time::SystemTime;
 use std::thread;
 use ring::signature::{Signature, KeyPair, Ed25519KeyPair};
-use crate::transaction::{verify,Mempool,TxBlockMempool,State,StatePerBlock};
-use crate::crypto::address::H160;
+use crate::transaction::{LedgerMode,Mempool,PendingNonces,SignedTransaction,SkipReason,TxBlockMempool,StatePerBlock,UnconfirmedTxBlocks};
+use crate::event::{Event, EventBus};
+use crate::metrics::Metrics;
+use crate::pow::PowBackend;
+use crate::pruning::Pruning;
+use crate::verifier::Verifier;
+use std::sync::atomic::Ordering;
 use log::info;
 
 #[derive(Clone)]
@@ -24,9 +30,56 @@ pub struct Context {
     buffer: Arc<Mutex<HashMap<H256, Block>>>,
     mempool: Arc<Mutex<Mempool>>,
     txBlockmempool: Arc<Mutex<TxBlockMempool>>,
-    txBlockOrderedList: Arc<Mutex<Vec<H256>>>,
+    unconfirmedTxBlocks: Arc<Mutex<UnconfirmedTxBlocks>>,
     // state: Arc<Mutex<State>>,
     spb: Arc<Mutex<StatePerBlock>>,
+    events: EventBus,
+    metrics: Arc<Metrics>,
+    pow: Arc<dyn PowBackend>,
+    ledger: LedgerMode,
+    verifier: Verifier,
+    pruning: Pruning,
+    /// Locked after the other locks, and only briefly
+    waiting: Arc<Mutex<Waiting>>,
+}
+
+/// Proposer blocks the node could apply but for transaction blocks it has not received yet, kept
+/// until the last of those arrives.
+#[derive(Default)]
+struct Waiting {
+    /// Each block and the transaction blocks it still misses
+    blocks: HashMap<H256, (Block, HashSet<H256>)>,
+    /// The proposer blocks waiting for each transaction block
+    for_tx_block: HashMap<H256, Vec<H256>>,
+}
+
+impl Waiting {
+    /// Keep `block` until the transaction blocks `missing` arrive.
+    fn wait(&mut self, block: &Block, missing: Vec<H256>) {
+        let hash = block.hash();
+        for tx_block in missing.iter() {
+            let blocks = self.for_tx_block.entry(*tx_block).or_default();
+            if !blocks.contains(&hash) {
+                blocks.push(hash);
+            }
+        }
+        self.blocks.insert(hash, (block.clone(), missing.into_iter().collect()));
+    }
+
+    /// The proposer blocks `tx_block` was the last missing transaction block of.
+    fn arrived(&mut self, tx_block: &H256) -> Vec<Block> {
+        let mut ready = vec![];
+        for hash in self.for_tx_block.remove(tx_block).unwrap_or_default() {
+            let complete = match self.blocks.get_mut(&hash) {
+                Some((_, missing)) => missing.remove(tx_block) && missing.is_empty(),
+                None => false,
+            };
+            if complete {
+                ready.push(self.blocks.remove(&hash).unwrap().0);
+            }
+        }
+        ready
+    }
 }
 
 pub fn new(
@@ -37,9 +90,15 @@ pub fn new(
     hashMap: &Arc<Mutex<HashMap<H256, Block>>>,
     mempool: &Arc<Mutex<Mempool>>,
     txBlockmempool: &Arc<Mutex<TxBlockMempool>>, 
-    txBlockOrderedList: &Arc<Mutex<Vec<H256>>>, 
+    unconfirmedTxBlocks: &Arc<Mutex<UnconfirmedTxBlocks>>, 
     // state: &Arc<Mutex<State>>,
     spb: &Arc<Mutex<StatePerBlock>>,
+    events: &EventBus,
+    metrics: &Arc<Metrics>,
+    pow: &Arc<dyn PowBackend>,
+    ledger: LedgerMode,
+    verifier: &Verifier,
+    pruning: Pruning,
 ) -> Context {
     Context {
         msg_chan: msg_src,
@@ -49,9 +108,16 @@ pub fn new(
         buffer: Arc::clone(hashMap),
         mempool: Arc::clone(mempool),
         txBlockmempool: Arc::clone(txBlockmempool),
-        txBlockOrderedList: Arc::clone(txBlockOrderedList),
+        unconfirmedTxBlocks: Arc::clone(unconfirmedTxBlocks),
         // state: Arc::clone(state),
         spb: Arc::clone(spb),
+        events: events.clone(),
+        metrics: Arc::clone(metrics),
+        pow: Arc::clone(pow),
+        ledger,
+        verifier: verifier.clone(),
+        pruning,
+        waiting: Arc::new(Mutex::new(Waiting::default())),
     }
 }
 
@@ -67,10 +133,118 @@ impl Context {
         }
     }
 
+    /// Reply to a single peer.
+    fn send(&self, peer: &peer::Handle, msg: Message) {
+        self.metrics.outbound_messages.inc(msg.kind());
+        peer.write(msg);
+    }
+
+    /// Drop what fell out of the pruning window after new proposer blocks.
+    fn prune(&self, blockchain: &Blockchain, spb: &mut StatePerBlock, txBlockmempool: &mut TxBlockMempool) {
+        let pruned = self.pruning.prune(blockchain, spb, txBlockmempool);
+        self.metrics.pruned_states.fetch_add(pruned.states as u64, Ordering::Relaxed);
+        self.metrics.pruned_tx_blocks.fetch_add(pruned.tx_blocks as u64, Ordering::Relaxed);
+    }
+
+    /// Add the proposer block `block`, whose parent is in `blockchain`, with the state it commits
+    /// to. A block pointing to transaction blocks the node has not received waits in `waiting`
+    /// while they are requested from `peer`. Returns whether the block was added.
+    fn connect(&self, block: &Block, peer: &peer::Handle, blockchain: &mut Blockchain, spb: &mut StatePerBlock, txBlockmempool: &TxBlockMempool) -> bool {
+        let parent = block.header.parent;
+        if blockchain.blocks.contains_key(&block.hash()) {
+            return false;
+        }
+        // a node started from a snapshot has no state before it, a pruning node none deeper than
+        // its window
+        // The state is reverted when a fork becomes the new longest chain. CODE
+        let mut state = match spb.spb.get(&parent) {
+            Some(state) => state.clone(),
+            None => {
+                debug!("Parent state unknown, Pr block forks off before the snapshot or below the pruned depth");
+                return false;
+            }
+        };
+        if let Err(missing) = state.apply_tx_blocks(&block.txPointer.tp, &txBlockmempool.txBlocks, blockchain.height[&parent] + 1, block.header.timestamp, &self.verifier) {
+            debug!("Transaction blocks of Pr block not received yet");
+            self.send(peer, Message::GetTxBlocks(missing.clone()));
+            self.waiting.lock().unwrap().wait(block, missing);
+            return false;
+        }
+        if state.state_root() != block.header.state_root {
+            warn!("Pr block {} commits to another state, rejected", block.hash());
+            self.metrics.validation_failures.inc("state_root_mismatch");
+            return false;
+        }
+        for skipped in state.skipped.iter() {
+            self.metrics.skipped_transactions.inc(skipped.reason.label());
+        }
+
+        spb.insert(block.hash(), &state);
+        blockchain.insert(block);
+        self.metrics.pr_blocks.inc("received");
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+        self.metrics.observe_propagation_delay(now.saturating_sub(block.header.timestamp) as u64);
+        self.events.publish(Event::NewPrBlock {
+            hash: block.hash().to_string(),
+            parent: parent.to_string(),
+            height: blockchain.height[&block.hash()],
+            mined: false,
+        });
+        true
+    }
+
+    /// Add the buffered descendants of the proposer block `parent`, as far as they go. Returns
+    /// the ones added.
+    fn connect_buffered(&self, parent: H256, peer: &peer::Handle, blockchain: &mut Blockchain, spb: &mut StatePerBlock, txBlockmempool: &TxBlockMempool, buffer: &mut HashMap<H256, Block>) -> Vec<H256> {
+        let mut connected = vec![];
+        let mut parent = parent;
+        while let Some(child) = buffer.remove(&parent) {
+            println!("stucked! TAT");
+            if !child.header.same_difficulty(&blockchain.blocks[&parent].header) {
+                break;
+            }
+            if !self.connect(&child, peer, blockchain, spb, txBlockmempool) {
+                break;
+            }
+            parent = child.hash();
+            connected.push(parent);
+        }
+        connected
+    }
+
+    /// Follow the tip, prune and announce after the proposer blocks `connected` were added,
+    /// `old_tip` being the tip before.
+    fn extended(&self, old_tip: H256, connected: Vec<H256>, blockchain: &Blockchain, spb: &mut StatePerBlock, txBlockmempool: &mut TxBlockMempool, unconfirmedTxBlocks: &mut UnconfirmedTxBlocks) {
+        if !connected.is_empty() {
+            unconfirmedTxBlocks.follow(blockchain, &old_tip, spb, &txBlockmempool.txBlocks);
+            self.prune(blockchain, spb, txBlockmempool);
+            self.server.broadcast(Message::NewPrBlockHashes(connected));
+        }
+        let new_tip = blockchain.tip();
+        let depth = blockchain.reorg_depth(&old_tip, &new_tip);
+        if depth > 0 {
+            self.events.publish(Event::Reorg {
+                old_tip: old_tip.to_string(),
+                new_tip: new_tip.to_string(),
+                depth,
+            });
+        }
+        Metrics::set(&self.metrics.chain_height, blockchain.height[&blockchain.tip()] as usize);
+    }
+
     fn worker_loop(&mut self) {
         loop {
             // println!("0");
             let msg = self.msg_chan.recv().unwrap();
+            let (msg, peer) = msg;
+            let msg: Message = bincode::deserialize(&msg).unwrap();
+            // signatures are checked before any lock is taken, in parallel, and the results are
+            // kept for the checks below and for applying the transaction blocks later
+            match &msg {
+                Message::Transactions(transactions) => self.verifier.verify_batch(transactions),
+                Message::TxBlocks(blocks) => self.verifier.verify_batch(blocks.iter().flat_map(|b| b.content.data.iter())),
+                _ => {}
+            }
             // println!("1");
             let temp = Arc::clone(&self.blockchain);
             // println!("2");
@@ -82,8 +256,8 @@ impl Context {
             let temp_txBlockmempool = Arc::clone(&self.txBlockmempool);
             let mut txBlockmempool = temp_txBlockmempool.lock().unwrap();
 
-            let temp_txBlockOrderedList = Arc::clone(&self.txBlockOrderedList);
-            let mut txBlockOrderedList = temp_txBlockOrderedList.lock().unwrap();
+            let temp_unconfirmedTxBlocks = Arc::clone(&self.unconfirmedTxBlocks);
+            let mut unconfirmedTxBlocks = temp_unconfirmedTxBlocks.lock().unwrap();
             // let temp_state = Arc::clone(&self.state);
             // let mut state = temp_state.lock().unwrap();
 
@@ -92,12 +266,11 @@ impl Context {
 
             let temp_buffer = Arc::clone(&self.buffer);
             let mut buffer = temp_buffer.lock().unwrap();
-            let (msg, peer) = msg;
-            let msg: Message = bincode::deserialize(&msg).unwrap();
+            self.metrics.inbound_messages.inc(msg.kind());
             match msg {
                 Message::Ping(nonce) => {
                     debug!("Ping: {}", nonce);
-                    peer.write(Message::Pong(nonce.to_string()));
+                    self.send(&peer, Message::Pong(nonce.to_string()));
                 }
                 Message::Pong(nonce) => {
                     debug!("Pong: {}", nonce);
@@ -106,24 +279,24 @@ impl Context {
                     debug!("NewTransactionHashes");
                     let mut h = vec![];
                     for hash in hashes {
-                        if !mempool.transactions.contains_key(&hash) {
+                        if !mempool.contains(&hash) {
                             h.push(hash);
                         }
                     }
                     if h.len()>0{
-                        peer.write(Message::GetTransactions(h));
+                        self.send(&peer, Message::GetTransactions(h));
                     }
                 }
                 Message::GetTransactions(hashes) => {
                     debug!("GetTransactions");
                     let mut b = vec![];
                     for hash in hashes {
-                        if mempool.transactions.contains_key(&hash) {
-                            b.push(mempool.transactions[&hash].clone());
+                        if let Some(t) = mempool.get(&hash) {
+                            b.push(t.clone());
                         }
                     }
                     if b.len()>0{
-                        peer.write(Message::Transactions(b));
+                        self.send(&peer, Message::Transactions(b));
                     }                   
                 }
                 Message::Transactions(transactions) => {
@@ -133,58 +306,88 @@ impl Context {
                     // println!("1");
                     // println!("{:?}", blockchain.tip());
                     let state = &spb.spb[&blockchain.tip()];
+                    // time-locked transactions wait in the mempool until the next proposer block
+                    // can apply them
+                    let nextHeight = blockchain.height[&blockchain.tip()] + 1;
+                    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+                    // the nonces waiting in transaction blocks and the mempool, counted once per
+                    // message and kept up to date as transactions are accepted
+                    let mut pending = PendingNonces::new(state, &txBlockmempool.txBlocks, Some(&mempool));
                     // println!("2");
                     for transaction in transactions {
-                        if !mempool.transactions.contains_key(&transaction.hash()) {                           
-                            let signature = &transaction.signature;
-                            let public_key = &transaction.public_key;
-                            let transaction_content = &transaction.transaction;
-                            if verify(transaction_content, public_key, signature) {
-                                if !state.addressCheck(public_key) {
-                                    // (*state).insert(public_key[..].into(), 1000, 0);
-                                    // if (transaction_content.value <= 1000) && (transaction_content.accountNonce == 1){
-                                    if (transaction_content.value <= 1000){
-                                        (*mempool).insert(&transaction);
-                                        broadcast_transactions_hashes.push(transaction.clone().hash());
-                                    }
-                                    else{
-                                        println!("not add 1");
-                                    }
-                                } else {
-                                    if state.spendCheck(public_key, transaction_content.value, transaction_content.accountNonce) {
-                                        (*mempool).insert(&transaction);
-                                        broadcast_transactions_hashes.push(transaction.clone().hash());
-                                    } 
-                                    else{
-                                        println!("not add 2");
+                        if mempool.contains(&transaction.hash()) {
+                            continue;
+                        }
+                        // Err holds the reason and the metric label of a rejection
+                        let verdict: Result<(), (String, &str)> = if transaction.ledger() != self.ledger {
+                            Err(("transaction of the other ledger model".to_string(), "wrong_ledger"))
+                        } else if !self.verifier.verify(&transaction) {
+                            Err(("invalid signature".to_string(), "invalid_signature"))
+                        } else {
+                            match &transaction {
+                                SignedTransaction::Account(_) | SignedTransaction::Batch(_) | SignedTransaction::Multisig(_) => {
+                                    // the nonce may wait for transactions still pending, but not
+                                    // leave a gap after them; a batch is checked as a whole
+                                    let (sender, accountNonce) = transaction.sender_nonce().unwrap();
+                                    if state.staleCheck(&transaction) {
+                                        Err(("stale nonce".to_string(), "stale_nonce"))
+                                    } else if accountNonce > pending.next(&sender) {
+                                        Err(("nonce gap".to_string(), "nonce_gap"))
+                                    } else {
+                                        match transaction.value() {
+                                            Err(e) => Err((e.to_string(), SkipReason::from(e).label())),
+                                            Ok(value) if state.account(&sender).1 < value => {
+                                                Err(("insufficient balance".to_string(), "insufficient_balance"))
+                                            }
+                                            Ok(_) => Ok(()),
+                                        }
                                     }
                                 }
-                            }                                                        
-                        }                                              
+                                SignedTransaction::Utxo(t) => {
+                                    state.utxos.check(t).map_err(|reason| (reason.to_string(), reason.label()))
+                                }
+                            }
+                        };
+                        match verdict {
+                            Ok(()) => {
+                                (*mempool).insert_or_hold(&transaction, nextHeight, now);
+                                pending.add(&transaction);
+                                broadcast_transactions_hashes.push(transaction.hash());
+                                self.events.publish(Event::TransactionAccepted { hash: transaction.hash().to_string() });
+                            }
+                            Err((reason, label)) => {
+                                self.events.publish(Event::TransactionRejected {
+                                    hash: transaction.hash().to_string(),
+                                    reason,
+                                });
+                                self.metrics.validation_failures.inc(label);
+                            }
+                        }
                     }
                     if broadcast_transactions_hashes.len() > 0 {
                         self.server.broadcast(Message::NewTransactionHashes(broadcast_transactions_hashes));
                     }
+                    Metrics::set(&self.metrics.mempool_size, mempool.transactions.len());
                 }
 
                 Message::NewTxBlockHashes(hashes) => {
                     debug!("NewTxBlockHashes");
                     let mut h = vec![];
                     for hash in hashes {
-                        if !txBlockmempool.txBlocks.contains_key(&hash) {
+                        if !txBlockmempool.txBlocks.contains_key(&hash) && !self.pruning.dropped(&blockchain, &spb, &hash) {
                             h.push(hash);
                         }
                     }
                     // let ttt = h.clone();
                     if h.len()>0{
                         // self.server.broadcast(Message::NewBlockHashes(ttt));
-                        peer.write(Message::GetTxBlocks(h));
+                        self.send(&peer, Message::GetTxBlocks(h));
                     }
                 }
 
                 Message::GetTxBlocks(hashes) => {
                     // let ttt = hashes.clone();
-                    // peer.write(Message::GetBlocks(ttt));
+                    // self.send(&peer, Message::GetBlocks(ttt));
                     debug!("GetTxBlocks");
                     let mut b = vec![];
                     for hash in hashes {
@@ -193,7 +396,7 @@ impl Context {
                         }
                     }
                     if b.len()>0{
-                        peer.write(Message::TxBlocks(b));
+                        self.send(&peer, Message::TxBlocks(b));
                         // println!("Blockchain length: {:?}", blockchain.blocks.len());
                         // println!("Buffer length: {:?}", (*buffer).len());
                         // println!("Tip: {:?}", (*blockchain).tip());
@@ -203,21 +406,30 @@ impl Context {
                 Message::TxBlocks(blocks) => {
                     debug!("TxBlocks");
                     let mut broadcast_blocks_hashes = vec![];
+                    let mut ready = vec![];
                     for block in blocks {
+                        // only the transactions are checked, a transaction block carries no other content
+                        if !block.verify_content(BlockKind::Transaction) {
+                            self.metrics.validation_failures.inc("content_root_mismatch");
+                            continue;
+                        }
                         // println!("1");
-                        if block.header.difficultyForTx >= block.hash() {
-                            if !txBlockmempool.txBlocks.contains_key(&block.hash()) {
-                                if (block.header.difficultyForTx == blockchain.blocks[&block.header.parent].header.difficultyForTx) && (block.header.difficultyForPr == blockchain.blocks[&block.header.parent].header.difficultyForPr) {
+                        if self.pow.verify(&block.header, BlockKind::Transaction) {
+                            if !txBlockmempool.txBlocks.contains_key(&block.hash()) && !self.pruning.dropped(&blockchain, &spb, &block.hash()) {
+                                if block.header.same_difficulty(&blockchain.blocks[&block.header.parent].header) {
                                     let contents = &(&block.clone()).content.data;
                                     let mut flag = false; 
 
                                     for signedTransaction in contents {
-                                        let signature = &signedTransaction.signature;
-                                        let public_key = &signedTransaction.public_key;
-                                        let transaction = &signedTransaction.transaction;
+                                        if signedTransaction.ledger() != self.ledger {
+                                            flag = true;
+                                            self.metrics.validation_failures.inc("wrong_ledger");
+                                            break;
+                                        }
                                         // Signature check CODE
-                                        if !verify(transaction, public_key, signature) {
+                                        if !self.verifier.verify(signedTransaction) {
                                             flag = true;    // invalid signature
+                                            self.metrics.validation_failures.inc("invalid_signature");
                                             break;
                                             println!("ooooooooops, something is not good!");
                                         }
@@ -228,7 +440,7 @@ impl Context {
                                     }
 
                                     (*txBlockmempool).insert(&block);
-                                    (*txBlockOrderedList).push(block.hash());
+                                    (*unconfirmedTxBlocks).insert(block.hash());
                                     
                                     for t in contents{
                                         let key = t.hash();
@@ -237,15 +449,16 @@ impl Context {
                                         }
                                     }
 
-                                    for (key, value) in (*mempool).transactions.clone().iter() {
-                                        for t in contents {
-                                            if (t.public_key == value.public_key) && (t.transaction.accountNonce == value.transaction.accountNonce) {
-                                                (*mempool).transactions.remove(&key);
-                                            }
-                                        }
-                                    }
+                                    (*mempool).remove_conflicts(contents);
 
                                     broadcast_blocks_hashes.push(block.clone().hash());
+                                    ready.extend(self.waiting.lock().unwrap().arrived(&block.hash()));
+                                    self.metrics.tx_blocks.inc("received");
+                                    self.events.publish(Event::NewTxBlock {
+                                        hash: block.hash().to_string(),
+                                        transactions: contents.len(),
+                                        mined: false,
+                                    });
                                 }                        
                             }
                         }                       
@@ -254,11 +467,25 @@ impl Context {
                     if broadcast_blocks_hashes.len() > 0 {
                         self.server.broadcast(Message::NewTxBlockHashes(broadcast_blocks_hashes));
                     }
+                    // the proposer blocks that waited for these transaction blocks, and their
+                    // buffered descendants
+                    if !ready.is_empty() {
+                        let old_tip = blockchain.tip();
+                        let mut connected = vec![];
+                        for block in ready {
+                            if self.connect(&block, &peer, &mut blockchain, &mut spb, &txBlockmempool) {
+                                connected.push(block.hash());
+                                connected.extend(self.connect_buffered(block.hash(), &peer, &mut blockchain, &mut spb, &txBlockmempool, &mut buffer));
+                            }
+                        }
+                        self.extended(old_tip, connected, &blockchain, &mut spb, &mut txBlockmempool, &mut unconfirmedTxBlocks);
+                    }
                     // println!("Blockchain length: {:?}", blockchain.blocks.len());
                     // println!("Buffer length: {:?}", (*buffer).len());
                     // println!("Tip: {:?}", (*blockchain).tip());
                     println!("???????");
                     info!("Tx block received !! Blockchain length: {:?}, Block tip: {:?}", blockchain.blocks.len(), (*blockchain).tip());
+                    Metrics::set(&self.metrics.mempool_size, mempool.transactions.len());
                     println!("???????");
                 }
 
@@ -273,21 +500,22 @@ impl Context {
                     // let ttt = h.clone();
                     if h.len()>0{
                         // self.server.broadcast(Message::NewBlockHashes(ttt));
-                        peer.write(Message::GetPrBlocks(h));
+                        self.send(&peer, Message::GetPrBlocks(h));
                     }
                 }
                 Message::GetPrBlocks(hashes) => {
                     // let ttt = hashes.clone();
-                    // peer.write(Message::GetBlocks(ttt));
+                    // self.send(&peer, Message::GetBlocks(ttt));
                     debug!("GetPrBlocks");
                     let mut b = vec![];
                     for hash in hashes {
-                        if blockchain.blocks.contains_key(&hash) {
+                        // a block restored from a snapshot would fail the content check of the peer
+                        if blockchain.blocks.contains_key(&hash) && !blockchain.headers_only.contains(&hash) {
                             b.push(blockchain.blocks[&hash].clone());
                         }
                     }
                     if b.len()>0{
-                        peer.write(Message::PrBlocks(b));
+                        self.send(&peer, Message::PrBlocks(b));
                         // println!("Blockchain length: {:?}", blockchain.blocks.len());
                         // println!("Buffer length: {:?}", (*buffer).len());
                         // println!("Tip: {:?}", (*blockchain).tip());
@@ -297,219 +525,141 @@ impl Context {
                 }
                 Message::PrBlocks(blocks) => {
                     // let ttt = blocks.clone();
-                    // peer.write(Message::Blocks(ttt));
+                    // self.send(&peer, Message::Blocks(ttt));
                     debug!("PrBlocks");
                     info!("Receive one block");
+                    let old_tip = blockchain.tip();
                     let mut p = vec![];
                     let mut broadcast_blocks_hashes = vec![];
                     for block in blocks {
+                        // only the transaction block pointers are checked
+                        if !block.verify_content(BlockKind::Proposer) {
+                            self.metrics.validation_failures.inc("content_root_mismatch");
+                            continue;
+                        }
                         // println!("1");
-                        if block.header.difficultyForPr >= block.hash() {
+                        if self.pow.verify(&block.header, BlockKind::Proposer) {
                             if !blockchain.blocks.contains_key(&block.hash()) {
                                 if !blockchain.blocks.contains_key(&block.header.parent){                                   
                                     (*buffer).insert(block.header.parent,block.clone());
                                     debug!("Parent not recieved yet");
                                     p.push(block.header.parent)                                                                     
-                                } else {
-                                    if (block.header.difficultyForTx == blockchain.blocks[&block.header.parent].header.difficultyForTx) && (block.header.difficultyForPr == blockchain.blocks[&block.header.parent].header.difficultyForPr) {
-                                        let mut flag = false; 
-                                        // The state is reverted when a fork becomes the new longest chain. CODE
-                                        let mut state = spb.spb[&block.header.parent].clone();
-                                        let mut orderList = spb.spb[&block.header.parent].txBlockOrderedList.clone();
-
-                                        let mut validTransaction = vec![];
-                                        
-                                        let tp = block.txPointer.tp.clone();
-
-                                        for txpointer in tp {
-                                            if !orderList.contains(&txpointer) {
-                                                state.txBlockOrderedList.push(txpointer);
-                                                if txBlockmempool.txBlocks.contains_key(&txpointer) {
-                                                    let txBlk = txBlockmempool.txBlocks[&txpointer].clone();
-                                                    let contents = txBlk.content.data;
-
-                                                    for signedTransaction in contents {
-                                                        let signature = &signedTransaction.signature;
-                                                        let public_key = &signedTransaction.public_key;
-                                                        let transaction = &signedTransaction.transaction;
-                                                        // Signature check CODE
-                                                        if !verify(transaction, public_key, signature) {
-                                                            flag = true;    // invalid signature
-                                                            break;
-                                                            println!("ooooooooops, something is not good!");
-                                                        }
-                                                        let recipientAddr = transaction.recipientAddr;
-                                                        let value = transaction.value;
-                                                        let accountNonce = transaction.accountNonce;
-
-                                                        let senderAddr: H160 = public_key[..].into();
-
-                                                        if !state.addressCheck(&public_key[..]) {
-                                                            state.insert(public_key[..].into(), 1000, 0);
-                                                            info!("Offer 1000 coins to address {}", senderAddr);
-                                                        }
-                                                        if !state.states.contains_key(&recipientAddr){
-                                                            state.insert(recipientAddr, 1000, 0);
-                                                            info!("Offer 1000 coins to address {}", senderAddr);
-                                                        }
-
-                                                        if state.spendCheck(&public_key[..], value, accountNonce) {
-                                                            validTransaction.push(signedTransaction);
-                                                        }
-                
-                                                    }
-                                                    if flag {
-                                                        break;
-                                                    }
-                                                }
-                                                else {
-                                                    println!("Fatal error!");
-                                                }                                               
-                                            }                      
-                                        }
-
-                                        if flag {
-                                            break;
-                                        }                                        
-                                      
-                                        for vt in validTransaction {
-                                            let public_key = &vt.public_key;
-                                            let transaction = &vt.transaction;
-                                            let recipientAddr = transaction.recipientAddr;
-                                            let senderAddr: H160 = public_key[..].into();
-                                            let value = transaction.value;
-                                            let sender = state.states[&senderAddr];
-                                            let repient = state.states[&recipientAddr];
-                                            state.insert(senderAddr,(sender.1)-value, (sender.0)+1);
-                                            state.insert(recipientAddr,(repient.1)+value, repient.0);
-                                            info!("{:} received {:?} coins from {:}", 
-                                                recipientAddr,
-                                                value,
-                                                senderAddr,
-                                            );
-                                        }
-
-                                        (*spb).insert(block.hash(),&state);
-                                        (*blockchain).insert(&block);                                      
-
-                                        // let currentTime = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
-                                        // let durationSinceMined = currentTime - block.header.timestamp;
-                                        // println!("!!!!!!!!!!!!!!!!!!!Latency: {:?}", durationSinceMined);
-                                        broadcast_blocks_hashes.push(block.clone().hash());
-                                        let mut parent = block.hash();
-                                        let difficultyForPr = block.header.difficultyForPr;
-                                        let difficultyForTx = block.header.difficultyForTx;
-                                        let mut temp;
-                                        while (*buffer).contains_key(&parent) {
-                                            println!("stucked! TAT");
-                                            if ((*buffer)[&parent].header.difficultyForPr != difficultyForPr) || ((*buffer)[&parent].header.difficultyForTx != difficultyForTx) {
-                                                break;
-                                            }
-                                            let mut flag = false; 
-                                            // The state is reverted when a fork becomes the new longest chain. CODE
-                                            let mut state = spb.spb[&parent].clone();
-                                            let mut orderList = spb.spb[&parent].txBlockOrderedList.clone();
-
-                                            let mut validTransaction = vec![];
-                                            
-                                            let tp = &((*buffer)[&parent].clone()).txPointer.tp.clone();
-
-                                            for txpointer in tp {
-                                                if !orderList.contains(&txpointer) {
-                                                    state.txBlockOrderedList.push(*txpointer);
-                                                    if txBlockmempool.txBlocks.contains_key(&txpointer) {
-                                                        let txBlk = txBlockmempool.txBlocks[&txpointer].clone();
-                                                        let contents = txBlk.content.data;
-
-                                                        for signedTransaction in contents {
-                                                            let signature = &signedTransaction.signature;
-                                                            let public_key = &signedTransaction.public_key;
-                                                            let transaction = &signedTransaction.transaction;
-                                                            // Signature check CODE
-                                                            if !verify(transaction, public_key, signature) {
-                                                                flag = true;    // invalid signature
-                                                                break;
-                                                                println!("ooooooooops, something is not good!");
-                                                            }
-                                                            let recipientAddr = transaction.recipientAddr;
-                                                            let value = transaction.value;
-                                                            let accountNonce = transaction.accountNonce;
-
-                                                            let senderAddr: H160 = public_key[..].into();
-
-                                                            if !state.addressCheck(&public_key[..]) {
-                                                                state.insert(public_key[..].into(), 1000, 0);
-                                                                info!("Offer 1000 coins to address {}", senderAddr);
-                                                            }
-                                                            if !state.states.contains_key(&recipientAddr){
-                                                                state.insert(recipientAddr, 1000, 0);
-                                                                info!("Offer 1000 coins to address {}", senderAddr);
-                                                            }
-
-                                                            if state.spendCheck(&public_key[..], value, accountNonce) {
-                                                                validTransaction.push(signedTransaction);
-                                                            }
-                    
-                                                        }
-                                                        if flag {
-                                                            break;
-                                                        }
-                                                    }
-                                                    else {
-                                                        println!("Fatal error!");
-                                                    }                                               
-                                                }                      
-                                            }
-
-                                            if flag {
-                                                break;
-                                            }                                        
-                                        
-                                            for vt in validTransaction {
-                                                let public_key = &vt.public_key;
-                                                let transaction = &vt.transaction;
-                                                let recipientAddr = transaction.recipientAddr;
-                                                let senderAddr: H160 = public_key[..].into();
-                                                let value = transaction.value;
-                                                let sender = state.states[&senderAddr];
-                                                let repient = state.states[&recipientAddr];
-                                                state.insert(senderAddr,(sender.1)-value, (sender.0)+1);
-                                                state.insert(recipientAddr,(repient.1)+value, repient.0);
-                                                info!("{:} received {:?} coins from {:}", 
-                                                    recipientAddr,
-                                                    value,
-                                                    senderAddr,
-                                                );
-                                            }
-          
-                                            (*spb).insert((*buffer)[&parent].hash(),&state);
-                                            (*blockchain).insert(&(*buffer)[&parent]); 
-                               
-                                            broadcast_blocks_hashes.push(((*buffer)[&parent]).clone().hash());                         
-                                            temp = (*buffer)[&parent].hash();
-                                            (*buffer).remove(&parent);
-                                            parent = temp;
-                                        }
+                                } else if block.header.same_difficulty(&blockchain.blocks[&block.header.parent].header) {
+                                    if self.connect(&block, &peer, &mut blockchain, &mut spb, &txBlockmempool) {
+                                        broadcast_blocks_hashes.push(block.hash());
+                                        broadcast_blocks_hashes.extend(self.connect_buffered(block.hash(), &peer, &mut blockchain, &mut spb, &txBlockmempool, &mut buffer));
                                     }
-                                }                           
+                                }
                             }
                         }
                         else{
                             println!("Detect attack block!!!!");
+                            self.metrics.validation_failures.inc("insufficient_pow");
                         }                       
                     }
 
-                    if broadcast_blocks_hashes.len() > 0 {
-                        self.server.broadcast(Message::NewPrBlockHashes(broadcast_blocks_hashes));
+                    // fetch the missing parents, down to a known block, e.g. the snapshot the node
+                    // started from
+                    if p.len() > 0 {
+                        self.send(&peer, Message::GetPrBlocks(p));
                     }
+                    self.extended(old_tip, broadcast_blocks_hashes, &blockchain, &mut spb, &mut txBlockmempool, &mut unconfirmedTxBlocks);
                     // println!("Blockchain length: {:?}", blockchain.blocks.len());
                     // println!("Buffer length: {:?}", (*buffer).len());
                     // println!("Tip: {:?}", (*blockchain).tip());
                     println!("!!!!!!!!");
                     info!("Pr block received !! Blockchain length: {:?}, Block tip: {:?}", blockchain.blocks.len(), (*blockchain).tip());
+                    Metrics::set(&self.metrics.orphans, buffer.len());
                     println!("!!!!!!!!");
                 }
+
+                Message::NewVoterBlockHashes(hashes) => {
+                    debug!("NewVoterBlockHashes");
+                    let mut h = vec![];
+                    for hash in hashes {
+                        if !blockchain.voter_blocks.contains_key(&hash) {
+                            h.push(hash);
+                        }
+                    }
+                    if h.len()>0{
+                        self.send(&peer, Message::GetVoterBlocks(h));
+                    }
+                }
+                Message::GetVoterBlocks(hashes) => {
+                    debug!("GetVoterBlocks");
+                    let mut b = vec![];
+                    for hash in hashes {
+                        if blockchain.voter_blocks.contains_key(&hash) {
+                            b.push(blockchain.voter_blocks[&hash].clone());
+                        }
+                    }
+                    if b.len()>0{
+                        self.send(&peer, Message::VoterBlocks(b));
+                    }
+                }
+                Message::VoterBlocks(blocks) => {
+                    debug!("VoterBlocks");
+                    let mut broadcast_blocks_hashes = vec![];
+                    for block in blocks {
+                        // only the votes are checked
+                        if !block.verify_content(BlockKind::Voter) {
+                            self.metrics.validation_failures.inc("content_root_mismatch");
+                            continue;
+                        }
+                        if !self.pow.verify(&block.header, BlockKind::Voter) {
+                            self.metrics.validation_failures.inc("insufficient_pow");
+                            continue;
+                        }
+                        if blockchain.voter_blocks.contains_key(&block.hash()) || !blockchain.blocks.contains_key(&block.header.parent) {
+                            continue;
+                        }
+                        if !block.header.same_difficulty(&blockchain.blocks[&block.header.parent].header) {
+                            continue;
+                        }
+                        if !block.votes.pr.iter().all(|pr| blockchain.blocks.contains_key(pr)) {
+                            self.metrics.validation_failures.inc("unknown_vote");
+                            continue;
+                        }
+                        (*blockchain).insert_voter(&block);
+                        broadcast_blocks_hashes.push(block.hash());
+                        self.metrics.voter_blocks.inc("received");
+                        self.events.publish(Event::NewVoterBlock {
+                            hash: block.hash().to_string(),
+                            votes: block.votes.pr.len(),
+                            mined: false,
+                        });
+                    }
+                    if broadcast_blocks_hashes.len() > 0 {
+                        self.server.broadcast(Message::NewVoterBlockHashes(broadcast_blocks_hashes));
+                    }
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test::generate_random_block;
+
+    #[test]
+    fn waiting_blocks_are_released_by_their_last_tx_block() {
+        let genesis: H256 = (&[0u8; 32]).into();
+        let (a, b, c): (H256, H256, H256) = ((&[1u8; 32]).into(), (&[2u8; 32]).into(), (&[3u8; 32]).into());
+        let first = generate_random_block(&genesis);
+        let second = generate_random_block(&genesis);
+        let mut waiting = Waiting::default();
+        waiting.wait(&first, vec![a, b]);
+        waiting.wait(&second, vec![b]);
+        // waiting again for the same block replaces what it misses
+        waiting.wait(&second, vec![b, c]);
+        assert!(waiting.arrived(&a).is_empty());
+        assert!(waiting.arrived(&a).is_empty());
+        let ready: Vec<H256> = waiting.arrived(&b).iter().map(|block| block.hash()).collect();
+        assert_eq!(ready, vec![first.hash()]);
+        let ready: Vec<H256> = waiting.arrived(&c).iter().map(|block| block.hash()).collect();
+        assert_eq!(ready, vec![second.hash()]);
+        assert!(waiting.blocks.is_empty() && waiting.for_tx_block.is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+use crate::block::{Block, BlockKind, Content, Header, TxPointer, Votes};
+use crate::crypto::hash::{Hashable, H256};
+use rand::distributions::Exp;
+use rand::Rng;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Outcome of one call to `PowBackend::search`.
+pub struct Search {
+    /// Headers tried.
+    pub attempts: u64,
+    /// Kind of block found, the header passed to `search` is the winning one.
+    pub found: Option<BlockKind>,
+}
+
+/// How blocks are mined and checked. The miner builds templates and searches through it, and the
+/// network workers verify received blocks with it.
+pub trait PowBackend: Send + Sync {
+    /// The block to search from, on `parent` and with the content of every block type and the
+    /// state root a proposer block would have. Blocks inherit the difficulties of their parent.
+    fn build_template(&self, parent: &Block, content: Content, txPointer: TxPointer, votes: Votes, state_root: H256, timestamp: u128) -> Block {
+        let header = Header {
+            parent: parent.hash(),
+            nonce: 0,
+            difficultyForPr: parent.header.difficultyForPr,
+            difficultyForTx: parent.header.difficultyForTx,
+            difficultyForVoter: parent.header.difficultyForVoter,
+            timestamp,
+            tx_root: content.root(),
+            pointer_root: txPointer.root(),
+            vote_root: votes.root(),
+            state_root,
+        };
+        Block { header, txPointer, content, votes }
+    }
+
+    /// Try up to `attempts` headers, taking timestamps and nonces from `nonces`.
+    fn search(&self, header: &mut Header, nonces: &mut NonceIter, attempts: u64) -> Search;
+
+    /// Whether `header` is a valid block of the given kind.
+    fn verify(&self, header: &Header, kind: BlockKind) -> bool;
+}
+
+/// Proof of work over the SHA-256 hash of the bincode encoded header, sortitioned into a block
+/// type by `Header::sortition`.
+pub struct Sha256;
+
+impl PowBackend for Sha256 {
+    fn search(&self, header: &mut Header, nonces: &mut NonceIter, attempts: u64) -> Search {
+        for attempt in 1..=attempts {
+            let (timestamp, nonce) = nonces.next().unwrap();
+            header.timestamp = timestamp;
+            header.nonce = nonce;
+            let found = header.sortition(&header.hash());
+            if found.is_some() {
+                return Search { attempts: attempt, found };
+            }
+        }
+        Search { attempts, found: None }
+    }
+
+    fn verify(&self, header: &Header, kind: BlockKind) -> bool {
+        header.sortition(&header.hash()) == Some(kind)
+    }
+}
+
+/// Longest a simulated search call sleeps before handing control back to the search thread.
+const SIMULATED_SLICE: Duration = Duration::from_millis(10);
+
+/// Simulated mining for experiments. Blocks arrive as a Poisson process with `rate` blocks per
+/// second per search thread, split between the block types in the same ratio as SHA-256 mining at
+/// the header's difficulties would give. No hashing is done while waiting; the type of a found
+/// block is then read off the hash of its header, so `verify` checks the type but no work.
+pub struct Simulated {
+    rate: f64,
+}
+
+impl Simulated {
+    pub fn new(rate: f64) -> Self {
+        Simulated { rate }
+    }
+
+    /// Bump the nonce of `header` until it is a simulated block of `kind`, for tests that build
+    /// blocks without mining them.
+    #[cfg(test)]
+    pub fn grind(header: &mut Header, kind: BlockKind) {
+        while simulated_kind(header) != kind {
+            header.nonce = header.nonce.wrapping_add(1);
+        }
+    }
+}
+
+impl PowBackend for Simulated {
+    fn search(&self, header: &mut Header, nonces: &mut NonceIter, _attempts: u64) -> Search {
+        let (timestamp, nonce) = nonces.next().unwrap();
+        header.timestamp = timestamp;
+        header.nonce = nonce;
+        // arrivals are memoryless, so the wait can be drawn afresh on every call
+        let mut rng = rand::thread_rng();
+        let wait: f64 = rng.sample(Exp::new(self.rate));
+        if wait >= SIMULATED_SLICE.as_secs_f64() {
+            thread::sleep(SIMULATED_SLICE);
+            return Search { attempts: 1, found: None };
+        }
+        thread::sleep(Duration::from_secs_f64(wait));
+        Search { attempts: 1, found: Some(simulated_kind(header)) }
+    }
+
+    fn verify(&self, header: &Header, kind: BlockKind) -> bool {
+        simulated_kind(header) == kind
+    }
+}
+
+/// The type of a simulated block, from the leading 8 bytes of its header hash taken as a fraction
+/// and split between the types in proportion to their targets.
+fn simulated_kind(header: &Header) -> BlockKind {
+    let proposer = target_fraction(&header.difficultyForPr);
+    let transaction = target_fraction(&header.difficultyForTx).max(proposer);
+    let any = target_fraction(&header.difficultyForVoter).max(transaction);
+    let draw = target_fraction(&header.hash()) * any;
+    if draw < proposer {
+        BlockKind::Proposer
+    } else if draw < transaction {
+        BlockKind::Transaction
+    } else {
+        BlockKind::Voter
+    }
+}
+
+/// Fraction of all hashes that are at or below `target`, from its leading 8 bytes.
+fn target_fraction(target: &H256) -> f64 {
+    let mut leading = [0u8; 8];
+    leading.copy_from_slice(&target.as_ref()[..8]);
+    (u64::from_be_bytes(leading) as f64 + 1.0) / 2f64.powi(64)
+}
+
+/// Attempts between two reads of the clock in a search thread.
+const CLOCK_CHECK: u32 = 1 << 16;
+
+/// Walks the (timestamp, nonce) space of one search thread. Nonces are tried in order within the
+/// thread's own slice of the 32-bit space. When the slice runs out, or when the clock has moved
+/// on, the timestamp is rolled forward and the slice starts over, so no header is hashed twice.
+pub struct NonceIter {
+    start: u32,
+    end: u32,
+    next: Option<u32>,
+    timestamp: u128,
+    since_clock_check: u32,
+}
+
+impl NonceIter {
+    pub fn new(thread: usize, threads: usize, timestamp: u128) -> Self {
+        let (start, end) = nonce_range(thread, threads);
+        NonceIter { start, end, next: Some(start), timestamp, since_clock_check: 0 }
+    }
+
+    /// Move to a later timestamp and start the nonce slice over. The clock is used if it has
+    /// passed the current timestamp, otherwise the timestamp is bumped by one millisecond.
+    fn roll(&mut self, now: u128) {
+        self.timestamp = now.max(self.timestamp + 1);
+        self.next = Some(self.start);
+        self.since_clock_check = 0;
+    }
+}
+
+impl Iterator for NonceIter {
+    type Item = (u128, u32);
+
+    fn next(&mut self) -> Option<(u128, u32)> {
+        self.since_clock_check += 1;
+        if self.since_clock_check == CLOCK_CHECK {
+            self.since_clock_check = 0;
+            let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+            if now > self.timestamp {
+                self.roll(now);
+            }
+        }
+        let nonce = match self.next {
+            Some(nonce) => nonce,
+            None => {
+                let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+                self.roll(now);
+                self.start
+            }
+        };
+        self.next = if nonce == self.end { None } else { Some(nonce + 1) };
+        Some((self.timestamp, nonce))
+    }
+}
+
+/// The inclusive slice of the 32-bit nonce space searched by thread `thread` of `threads`.
+pub fn nonce_range(thread: usize, threads: usize) -> (u32, u32) {
+    let size = (1u64 << 32) / threads as u64;
+    let start = size * thread as u64;
+    let end = if thread + 1 == threads { u32::max_value() as u64 } else { start + size - 1 };
+    (start as u32, end as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonce_ranges_cover_the_space() {
+        for threads in 1..6 {
+            let mut expected_start: u64 = 0;
+            for thread in 0..threads {
+                let (start, end) = nonce_range(thread, threads);
+                assert_eq!(start as u64, expected_start);
+                assert!(end >= start);
+                expected_start = end as u64 + 1;
+            }
+            assert_eq!(expected_start, 1u64 << 32);
+        }
+    }
+
+    #[test]
+    fn nonce_iter_rolls_timestamp() {
+        // a slice of three nonces at a timestamp far in the future, so the clock never wins
+        let timestamp = u128::max_value() / 2;
+        let mut iter = NonceIter { start: 7, end: 9, next: Some(7), timestamp, since_clock_check: 0 };
+        let tried: Vec<(u128, u32)> = iter.by_ref().take(7).collect();
+        assert_eq!(
+            tried,
+            vec![
+                (timestamp, 7), (timestamp, 8), (timestamp, 9),
+                (timestamp + 1, 7), (timestamp + 1, 8), (timestamp + 1, 9),
+                (timestamp + 2, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn sha256_search_and_verify() {
+        let mut header = Header {
+            parent: [0u8; 32].into(),
+            nonce: 0,
+            difficultyForPr: [0u8; 32].into(),
+            difficultyForTx: [255u8; 32].into(),
+            difficultyForVoter: [255u8; 32].into(),
+            timestamp: 0,
+            tx_root: [0u8; 32].into(),
+            pointer_root: [0u8; 32].into(),
+            vote_root: [0u8; 32].into(),
+            state_root: [0u8; 32].into(),
+        };
+        let mut nonces = NonceIter::new(0, 1, 0);
+        let search = Sha256.search(&mut header, &mut nonces, 10);
+        assert_eq!(search.attempts, 1);
+        assert_eq!(search.found, Some(BlockKind::Transaction));
+        assert!(Sha256.verify(&header, BlockKind::Transaction));
+        assert!(!Sha256.verify(&header, BlockKind::Proposer));
+
+        header.difficultyForTx = [0u8; 32].into();
+        header.difficultyForVoter = [0u8; 32].into();
+        let search = Sha256.search(&mut header, &mut nonces, 10);
+        assert_eq!(search.attempts, 10);
+        assert_eq!(search.found, None);
+        assert!(!Sha256.verify(&header, BlockKind::Transaction));
+    }
+
+    #[test]
+    fn simulated_kind_follows_the_header() {
+        let mut header = Header {
+            parent: [0u8; 32].into(),
+            nonce: 0,
+            difficultyForPr: [0x40u8; 32].into(),
+            difficultyForTx: [0x80u8; 32].into(),
+            difficultyForVoter: [0xffu8; 32].into(),
+            timestamp: 0,
+            tx_root: [0u8; 32].into(),
+            pointer_root: [0u8; 32].into(),
+            vote_root: [0u8; 32].into(),
+            state_root: [0u8; 32].into(),
+        };
+        let pow = Simulated::new(1000.0);
+        let mut nonces = NonceIter::new(0, 1, 0);
+        let mut kinds = vec![];
+        while kinds.len() < 50 {
+            if let Some(kind) = pow.search(&mut header, &mut nonces, 1).found {
+                // a header verifies as the type it was found as only
+                assert!(pow.verify(&header, kind));
+                let others = [BlockKind::Proposer, BlockKind::Transaction, BlockKind::Voter];
+                assert_eq!(others.iter().filter(|k| pow.verify(&header, **k)).count(), 1);
+                kinds.push(kind);
+            }
+        }
+        assert!(kinds.contains(&BlockKind::Proposer) && kinds.contains(&BlockKind::Voter));
+    }
+}
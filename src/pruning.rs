@@ -0,0 +1,145 @@
+use crate::blockchain::Blockchain;
+use crate::crypto::hash::H256;
+use crate::transaction::{StatePerBlock, TxBlockMempool};
+
+/// How much history a node keeps, see `--prune` and `--checkpoint-interval`. States are kept for
+/// the proposer blocks at most `depth` levels below the tip, on any fork, and for the
+/// checkpoints, the blocks at a multiple of `checkpoint_interval`. A block whose parent has no
+/// state left cannot be applied, so forks that start deeper than `depth` are not followed.
+/// Transaction blocks confirmed `depth` levels below the tip, or deeper, are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pruning {
+    /// None keeps every state and transaction block
+    pub depth: Option<u32>,
+    pub checkpoint_interval: u32,
+}
+
+/// What one call to `Pruning::prune` dropped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Pruned {
+    pub states: usize,
+    pub tx_blocks: usize,
+}
+
+impl Pruning {
+    pub fn keep_everything() -> Self {
+        Pruning { depth: None, checkpoint_interval: 1 }
+    }
+
+    /// Whether the state of a block at `height` is kept below a tip at `tip_height`.
+    pub fn keeps_state(&self, height: u32, tip_height: u32) -> bool {
+        match self.depth {
+            None => true,
+            Some(depth) => height + depth >= tip_height || height % self.checkpoint_interval == 0,
+        }
+    }
+
+    /// Whether `tx_block` is confirmed on the longest chain `depth` levels below the tip or deeper,
+    /// so it was dropped, or will be, and must not be fetched again.
+    pub fn dropped(&self, blockchain: &Blockchain, spb: &StatePerBlock, tx_block: &H256) -> bool {
+        let depth = match self.depth {
+            Some(depth) => depth,
+            None => return false,
+        };
+        let tip = blockchain.tip();
+        match spb.spb.get(&tip).and_then(|state| state.txBlockOrderedList.get(tx_block)) {
+            Some(confirmation) => confirmation.height + depth <= blockchain.height[&tip],
+            None => false,
+        }
+    }
+
+    /// Drop the states and transaction blocks that fell out of the window below the tip of
+    /// `blockchain`. The tip confirms those transaction blocks, so the miner no longer points to
+    /// them.
+    pub fn prune(&self, blockchain: &Blockchain, spb: &mut StatePerBlock, txBlockmempool: &mut TxBlockMempool) -> Pruned {
+        let depth = match self.depth {
+            Some(depth) => depth,
+            None => return Pruned::default(),
+        };
+        let tip = blockchain.tip();
+        let tip_height = blockchain.height[&tip];
+        let states = spb.spb.len();
+        spb.spb.retain(|hash, _| blockchain.height.get(hash).map_or(true, |h| self.keeps_state(*h, tip_height)));
+
+        // the ancestor at the bottom of the window lists the transaction blocks confirmed that
+        // deep, the ones dropped before come first
+        let mut deep = tip;
+        while blockchain.height[&deep] + depth > tip_height && deep != blockchain.genesis {
+            deep = blockchain.blocks[&deep].header.parent;
+        }
+        let mut tx_blocks = 0;
+        if let Some(state) = spb.spb.get(&deep) {
+            for hash in state.txBlockOrderedList.iter_rev() {
+                if txBlockmempool.txBlocks.remove(hash).is_none() {
+                    break;
+                }
+                tx_blocks += 1;
+            }
+        }
+        Pruned { states: states - spb.spb.len(), tx_blocks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test::generate_random_block;
+    use crate::crypto::hash::Hashable;
+    use crate::transaction::State;
+
+    #[test]
+    fn prune_window_and_checkpoints() {
+        // a chain of ten proposer blocks each confirming one transaction block, and a fork at
+        // height 2
+        let mut blockchain = Blockchain::new();
+        let genesis = blockchain.genesis;
+        let mut spb = StatePerBlock::new(genesis, State::new());
+        let mut txBlockmempool = TxBlockMempool::new();
+        let mut state = State::new();
+        let mut chain = vec![genesis];
+        for height in 1..11 {
+            let txBlock = generate_random_block(&genesis);
+            txBlockmempool.insert(&txBlock);
+            state.txBlockOrderedList.confirm(txBlock.hash(), height);
+            let block = generate_random_block(chain.last().unwrap());
+            spb.insert(block.hash(), &state);
+            blockchain.insert(&block);
+            chain.push(block.hash());
+        }
+        let fork = generate_random_block(&chain[1]);
+        spb.insert(fork.hash(), &State::new());
+        blockchain.insert(&fork);
+        let pending = generate_random_block(&genesis);
+        txBlockmempool.insert(&pending);
+
+        let mut unpruned = spb.clone();
+        assert_eq!(Pruning::keep_everything().prune(&blockchain, &mut unpruned, &mut txBlockmempool.clone()), Pruned::default());
+        assert_eq!(unpruned.spb.len(), 12);
+
+        let pruning = Pruning { depth: Some(3), checkpoint_interval: 4 };
+        let pruned = pruning.prune(&blockchain, &mut spb, &mut txBlockmempool);
+        // heights 7 to 10 and the checkpoints 0, 4 and 8 are kept
+        let mut kept: Vec<u32> = spb.spb.keys().map(|h| blockchain.height[h]).collect();
+        kept.sort();
+        assert_eq!(kept, vec![0, 4, 7, 8, 9, 10]);
+        // the transaction blocks confirmed at heights 1 to 7 are gone
+        assert_eq!(pruned, Pruned { states: 6, tx_blocks: 7 });
+        let confirmed = state.txBlockOrderedList.to_vec();
+        assert!(confirmed[7..].iter().chain(vec![&pending.hash()]).all(|h| txBlockmempool.txBlocks.contains_key(h)));
+        assert_eq!(txBlockmempool.txBlocks.len(), 4);
+        // a dropped transaction block announced again is not fetched
+        assert!(pruning.dropped(&blockchain, &spb, &confirmed[6]));
+        assert!(!pruning.dropped(&blockchain, &spb, &confirmed[7]));
+        assert!(!pruning.dropped(&blockchain, &spb, &pending.hash()));
+        assert!(!Pruning::keep_everything().dropped(&blockchain, &spb, &confirmed[0]));
+
+        // the next block moves the window by one
+        let block = generate_random_block(chain.last().unwrap());
+        spb.insert(block.hash(), &state);
+        blockchain.insert(&block);
+        let pruned = pruning.prune(&blockchain, &mut spb, &mut txBlockmempool);
+        assert_eq!(pruned, Pruned { states: 1, tx_blocks: 1 });
+        assert!(!spb.spb.contains_key(&chain[7]) && spb.spb.contains_key(&chain[8]));
+        assert_eq!(txBlockmempool.txBlocks.len(), 3);
+    }
+}
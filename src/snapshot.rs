@@ -0,0 +1,295 @@
+//! Snapshots of the ledger state at a proposer block, to start a node from that block instead of
+//! replaying the chain from the genesis block.
+//!
+//! File layout, version 1:
+//! - the 8 bytes `PRISMSNP`,
+//! - the format version, a big-endian u32,
+//! - the bincode encoding of `Snapshot`,
+//! - the SHA256 of everything before it.
+
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use ring::digest;
+use crate::block::{BlockKind, Header};
+use crate::blockchain::Blockchain;
+use crate::crypto::address::H160;
+use crate::crypto::hash::{Hashable, H256};
+use crate::pow::PowBackend;
+use crate::transaction::{State, StatePerBlock};
+use crate::utxo::UtxoSet;
+
+const MAGIC: &[u8; 8] = b"PRISMSNP";
+/// The version written, and the only one read.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    /// The file is not a snapshot, or is truncated.
+    Format(String),
+    UnsupportedVersion(u32),
+    /// The checksum does not match, the file is damaged.
+    Checksum,
+    /// The first header is not the genesis block of this node.
+    WrongGenesis,
+    /// The headers are not linked by their parents.
+    BrokenChain,
+    /// A header fails its proof of work or has other difficulties than the genesis block.
+    InvalidHeader,
+    /// The accounts are not the ones the last header commits to.
+    StateRootMismatch,
+    /// An export name that is not a plain file name.
+    InvalidName(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "snapshot i/o error: {}", e),
+            SnapshotError::Format(e) => write!(f, "malformed snapshot: {}", e),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Checksum => write!(f, "snapshot checksum mismatch"),
+            SnapshotError::WrongGenesis => write!(f, "snapshot of another genesis block"),
+            SnapshotError::BrokenChain => write!(f, "snapshot headers do not link"),
+            SnapshotError::InvalidHeader => write!(f, "invalid block header in snapshot"),
+            SnapshotError::StateRootMismatch => write!(f, "snapshot accounts do not match the state root"),
+            SnapshotError::InvalidName(name) => write!(f, "invalid snapshot name {:?}, expected a file name", name),
+        }
+    }
+}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+/// The state of a proposer block and the headers leading to it. The accounts are checked
+/// against the state root of the last header; the unspent outputs are only covered by the
+/// checksum, so a snapshot should come from a node one trusts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    /// Proposer headers from the genesis block to the block of the snapshot, oldest first
+    pub headers: Vec<Header>,
+    /// (nonce, balance) per address
+    pub accounts: BTreeMap<H160, (u32, u64)>,
+    /// The transaction blocks confirmed by the block of the snapshot and its ancestors, in order
+    pub txBlockOrderedList: Vec<H256>,
+    pub utxos: UtxoSet,
+}
+
+impl Snapshot {
+    /// The snapshot of the proposer block `block`, if it is known with its state.
+    pub fn new(block: &H256, blockchain: &Blockchain, spb: &StatePerBlock) -> Option<Snapshot> {
+        let state = spb.spb.get(block)?;
+        let mut headers = vec![];
+        let mut current = *block;
+        loop {
+            let header = &blockchain.blocks.get(&current)?.header;
+            headers.push(header.clone());
+            if current == blockchain.genesis {
+                break;
+            }
+            current = header.parent;
+        }
+        headers.reverse();
+        Some(Snapshot {
+            headers,
+            accounts: state.states.iter().map(|(address, account)| (*address, *account)).collect(),
+            txBlockOrderedList: state.txBlockOrderedList.to_vec(),
+            utxos: state.utxos.clone(),
+        })
+    }
+
+    /// The hash of the proposer block of the snapshot.
+    pub fn block(&self) -> H256 {
+        self.headers.last().map(|h| h.hash()).unwrap_or_default()
+    }
+
+    /// The height of the proposer block of the snapshot.
+    pub fn height(&self) -> u32 {
+        self.headers.len().saturating_sub(1) as u32
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+        bytes.extend(bincode::serialize(self).unwrap());
+        let checksum = digest::digest(&digest::SHA256, &bytes);
+        bytes.extend_from_slice(checksum.as_ref());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        if bytes.len() < MAGIC.len() + 4 + 32 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::Format("not a snapshot file".to_string()));
+        }
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&bytes[MAGIC.len()..MAGIC.len() + 4]);
+        let version = u32::from_be_bytes(version);
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let (contents, checksum) = bytes.split_at(bytes.len() - 32);
+        if digest::digest(&digest::SHA256, contents).as_ref() != checksum {
+            return Err(SnapshotError::Checksum);
+        }
+        bincode::deserialize(&contents[MAGIC.len() + 4..]).map_err(|e| SnapshotError::Format(e.to_string()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        // write to a temporary file first so that a crash never leaves a truncated snapshot
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.encode())?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Save as the file `name` of the directory `dir`, creating the directory if needed. The name
+    /// cannot be a path, so nothing is written outside `dir`.
+    pub fn export(&self, dir: &Path, name: &str) -> Result<PathBuf, SnapshotError> {
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(file)), None) if file == name => {}
+            _ => return Err(SnapshotError::InvalidName(name.to_string())),
+        }
+        fs::create_dir_all(dir)?;
+        let path = dir.join(name);
+        self.save(&path)?;
+        Ok(path)
+    }
+
+    pub fn load(path: &Path) -> Result<Snapshot, SnapshotError> {
+        Snapshot::decode(&fs::read(path)?)
+    }
+
+    /// Check that the headers lead from `genesis` to the block of the snapshot, with proof of
+    /// work, and that the accounts are the ones that block commits to.
+    pub fn verify(&self, genesis: &H256, pow: &dyn PowBackend) -> Result<(), SnapshotError> {
+        let first = match self.headers.first() {
+            Some(first) if first.hash() == *genesis => first,
+            _ => return Err(SnapshotError::WrongGenesis),
+        };
+        if self.headers.windows(2).any(|w| w[1].parent != w[0].hash()) {
+            return Err(SnapshotError::BrokenChain);
+        }
+        for header in self.headers[1..].iter() {
+            if !pow.verify(header, BlockKind::Proposer) || !header.same_difficulty(first) {
+                return Err(SnapshotError::InvalidHeader);
+            }
+        }
+        if self.state().state_root() != self.headers.last().unwrap().state_root {
+            return Err(SnapshotError::StateRootMismatch);
+        }
+        Ok(())
+    }
+
+    /// The state of the block of the snapshot.
+    pub fn state(&self) -> State {
+        let mut state = State::new();
+        for (address, (nonce, balance)) in self.accounts.iter() {
+            state.insert(*address, *balance, *nonce);
+        }
+        // the node cannot tell which ancestor confirmed a transaction block, the snapshot block
+        // stands for all of them
+        for tx_block in self.txBlockOrderedList.iter() {
+            state.txBlockOrderedList.confirm(*tx_block, self.height());
+        }
+        state.utxos = self.utxos.clone();
+        state.height = self.height();
+        state.timestamp = self.headers.last().map_or(0, |h| h.timestamp);
+        state
+    }
+
+    /// The blockchain and states a node starts from. The blocks of the snapshot are known by
+    /// their headers only, and only the last one has a state; the node syncs forward from it.
+    pub fn restore(&self) -> (Blockchain, StatePerBlock) {
+        let mut blockchain = Blockchain::new();
+        for header in self.headers[1..].iter() {
+            blockchain.insert_header(header);
+        }
+        let mut spb = StatePerBlock::default();
+        spb.insert(self.block(), &self.state());
+        (blockchain, spb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test::generate_random_block;
+    use crate::pow::Simulated;
+
+    #[test]
+    fn export_and_restore() {
+        // genesis <- a <- b, each with its own accounts
+        let mut blockchain = Blockchain::new();
+        let genesis = blockchain.genesis;
+        let genesis_header = blockchain.blocks[&genesis].header.clone();
+        let mut spb = StatePerBlock::new(genesis, State::new());
+        let mut state = State::new();
+        let mut parent = genesis;
+        for i in 1..3u8 {
+            state.insert(H160::default(), 1000 + i as u64, i as u32);
+            state.txBlockOrderedList.confirm((&[i; 32]).into(), i as u32);
+            let mut block = generate_random_block(&parent);
+            block.header = Header { parent, state_root: state.state_root(), ..genesis_header.clone() };
+            block.header.nonce = i as u32;
+            Simulated::grind(&mut block.header, BlockKind::Proposer);
+            spb.insert(block.hash(), &state);
+            blockchain.insert(&block);
+            parent = block.hash();
+        }
+        let tip = blockchain.tip();
+        let pow = Simulated::new(1.0);
+
+        let snapshot = Snapshot::new(&tip, &blockchain, &spb).unwrap();
+        assert_eq!((snapshot.block(), snapshot.height(), snapshot.headers.len()), (tip, 2, 3));
+        let bytes = snapshot.encode();
+        let decoded = Snapshot::decode(&bytes).unwrap();
+        assert!(decoded.verify(&genesis, &pow).is_ok());
+        let (restored, states) = decoded.restore();
+        assert_eq!((restored.tip(), restored.height[&tip]), (tip, 2));
+        // the restored blocks have no content to serve
+        assert!(restored.headers_only.contains(&tip) && !restored.headers_only.contains(&genesis));
+        assert_eq!(restored.headers_only.len(), 2);
+        assert_eq!(states.spb[&tip].state_root(), spb.spb[&tip].state_root());
+        assert_eq!(states.spb[&tip].txBlockOrderedList.to_vec(), spb.spb[&tip].txBlockOrderedList.to_vec());
+        assert_eq!(states.spb[&tip].height, 2);
+        // the restored node cannot tell which ancestor confirmed an old transaction block
+        assert_eq!(spb.confirmed_by(&blockchain, &[1u8; 32].into()), Some(blockchain.blocks[&tip].header.parent));
+        assert_eq!(states.confirmed_by(&restored, &[1u8; 32].into()), None);
+        assert_eq!(states.confirmed_by(&restored, &[2u8; 32].into()), None);
+
+        let mut damaged = bytes.clone();
+        damaged[20] ^= 1;
+        assert!(matches!(Snapshot::decode(&damaged), Err(SnapshotError::Checksum)));
+        let mut newer = bytes.clone();
+        newer[11] = 2;
+        assert!(matches!(Snapshot::decode(&newer), Err(SnapshotError::UnsupportedVersion(2))));
+        assert!(matches!(Snapshot::decode(&bytes[..30]), Err(SnapshotError::Format(_))));
+
+        // exports stay in their directory
+        let dir = std::env::temp_dir().join(format!("prism-snapshots-{}", std::process::id()));
+        let path = snapshot.export(&dir, "tip.snap").unwrap();
+        assert_eq!(path, dir.join("tip.snap"));
+        assert_eq!(Snapshot::load(&path).unwrap().block(), tip);
+        for name in &["", ".", "..", "../tip.snap", "a/tip.snap", "/tmp/tip.snap", "./tip.snap"] {
+            assert!(matches!(snapshot.export(&dir, name), Err(SnapshotError::InvalidName(_))));
+        }
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(matches!(snapshot.verify(&H256::default(), &pow), Err(SnapshotError::WrongGenesis)));
+        let mut forged = snapshot.clone();
+        forged.accounts.insert(H160::default(), (2, u64::max_value()));
+        assert!(matches!(forged.verify(&genesis, &pow), Err(SnapshotError::StateRootMismatch)));
+        let mut forged = snapshot.clone();
+        forged.headers.remove(1);
+        assert!(matches!(forged.verify(&genesis, &pow), Err(SnapshotError::BrokenChain)));
+        // the test blocks carry no real proof of work
+        assert!(matches!(snapshot.verify(&genesis, &crate::pow::Sha256), Err(SnapshotError::InvalidHeader)));
+    }
+}
@@ -4,8 +4,83 @@ use rand::Rng;
 use ring::digest;
 use crate::crypto::hash::{H256, Hashable};
 use crate::crypto::address::H160;
-use std::collections::HashMap;
-use crate::block::{Block};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use crate::block::{Block, Header};
+use crate::crypto::sparse_merkle::{self, SparseMerkleTree, SparseProof};
+use crate::blockchain::Blockchain;
+use crate::confirmations::Confirmations;
+use crate::multisig::{KeySignature, Multisig};
+use crate::utxo::{self, OutPoint, Output, UtxoSet, UtxoTransaction};
+use crate::verifier::Verifier;
+use log::{info, warn};
+
+/// Amounts are counted in the smallest unit, `COIN` of them make one coin.
+pub const COIN: u64 = 100_000_000;
+/// Balance credited to an address the first time the ledger sees it.
+pub const INITIAL_BALANCE: u64 = 1000 * COIN;
+
+/// Why a balance update was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerError {
+    /// The balance is lower than the amount taken from it
+    InsufficientBalance,
+    /// The balance or a sum of amounts does not fit in 64 bits
+    Overflow,
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerError::InsufficientBalance => write!(f, "insufficient balance"),
+            LedgerError::Overflow => write!(f, "amount overflow"),
+        }
+    }
+}
+
+/// Prefixed to every signed transaction payload, so that a transaction signature cannot be taken
+/// for a signature over anything else.
+const SIGNING_DOMAIN: &[u8] = b"prism-transaction";
+
+/// The network transactions are signed for: the genesis hash, mixed with the name given by
+/// `--chain-id` if any. A signature made for one chain does not verify on another.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChainId(pub H256);
+
+impl ChainId {
+    pub fn new(genesis: &H256, name: Option<&str>) -> ChainId {
+        match name {
+            None => ChainId(*genesis),
+            Some(name) => {
+                let mut ctx = digest::Context::new(&digest::SHA256);
+                ctx.update(genesis.as_ref());
+                ctx.update(name.as_bytes());
+                ChainId(ctx.finish().into())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ChainId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The bytes a signature over `body` covers: the signing domain, the chain and the kind of
+/// transaction, then the body.
+pub fn signing_payload<T: Serialize + ?Sized>(chain_id: &ChainId, kind: &str, body: &T) -> Vec<u8> {
+    bincode::serialize(&(SIGNING_DOMAIN, chain_id, kind, body)).unwrap()
+}
+
+/// The ledger model a node builds and accepts transactions for, see `--ledger`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerMode {
+    /// Balances and nonces per address
+    Account,
+    /// Unspent transaction outputs, as in Bitcoin
+    Utxo,
+}
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct StatePerBlock {
@@ -22,24 +97,166 @@ impl StatePerBlock {
     pub fn insert(&mut self, block_hash: H256, state: &State) {
         self.spb.insert(block_hash,state.clone());
     }
+
+    /// The proposer block of the longest chain that confirmed `tx_block`, the ancestor of the tip
+    /// at the height the tip's state records for it. None as well if the state before that block
+    /// is unknown: the block is older than the snapshot the node started from, or pruned.
+    pub fn confirmed_by(&self, blockchain: &Blockchain, tx_block: &H256) -> Option<H256> {
+        let mut curBlock = blockchain.tip();
+        let confirmation = self.spb.get(&curBlock)?.txBlockOrderedList.get(tx_block)?;
+        while blockchain.height[&curBlock] > confirmation.height {
+            curBlock = blockchain.blocks[&curBlock].header.parent;
+        }
+        if !self.spb.contains_key(&blockchain.blocks[&curBlock].header.parent) {
+            return None;
+        }
+        return Some(curBlock);
+    }
+}
+
+/// Why a transaction of a confirmed transaction block was not applied to the state.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The same transaction came earlier in the ordered list of the proposer block
+    DuplicateTransaction,
+    /// Another transaction of the same sender with the same nonce came earlier
+    DuplicateNonce,
+    /// The nonce is not above the sender's nonce, e.g. the transaction was applied by an earlier
+    /// proposer block
+    StaleNonce,
+    /// The nonce skips values after the sender's nonce
+    NonceGap,
+    InsufficientBalance,
+    /// A balance or the sum of the inputs or outputs would overflow
+    Overflow,
+    InvalidSignature,
+    /// An output the transaction spends is spent already or does not exist
+    MissingInput,
+    /// The transaction spends an output that an earlier one, or an earlier input, spends
+    DoubleSpend,
+    /// The proposer block is below the lock height or older than the lock time
+    Immature,
+}
+
+impl SkipReason {
+    /// Name used in metric labels, as serialized.
+    pub fn label(&self) -> &'static str {
+        match self {
+            SkipReason::DuplicateTransaction => "duplicate_transaction",
+            SkipReason::DuplicateNonce => "duplicate_nonce",
+            SkipReason::StaleNonce => "stale_nonce",
+            SkipReason::NonceGap => "nonce_gap",
+            SkipReason::InsufficientBalance => "insufficient_balance",
+            SkipReason::Overflow => "overflow",
+            SkipReason::InvalidSignature => "invalid_signature",
+            SkipReason::MissingInput => "missing_input",
+            SkipReason::DoubleSpend => "double_spend",
+            SkipReason::Immature => "immature",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            SkipReason::DuplicateTransaction => "duplicate transaction",
+            SkipReason::DuplicateNonce => "duplicate nonce",
+            SkipReason::StaleNonce => "stale nonce",
+            SkipReason::NonceGap => "nonce gap",
+            SkipReason::InsufficientBalance => "insufficient balance",
+            SkipReason::Overflow => "amount overflow",
+            SkipReason::InvalidSignature => "invalid signature",
+            SkipReason::MissingInput => "missing input",
+            SkipReason::DoubleSpend => "double spend",
+            SkipReason::Immature => "time lock not reached",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl From<LedgerError> for SkipReason {
+    fn from(e: LedgerError) -> Self {
+        match e {
+            LedgerError::InsufficientBalance => SkipReason::InsufficientBalance,
+            LedgerError::Overflow => SkipReason::Overflow,
+        }
+    }
+}
+
+/// A transaction of a confirmed transaction block that was not applied to the state.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Skipped {
+    pub tx_block: H256,
+    pub transaction: H256,
+    pub reason: SkipReason,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct State {
-    pub states: HashMap<H160,(u32,u32)>,
-    pub txBlockOrderedList: Vec<H256>,
+    /// (nonce, balance) per address, changed through `insert` only
+    pub states: HashMap<H160,(u32,u64)>,
+    /// The sparse Merkle tree of `states`, updated with it
+    accounts: SparseMerkleTree,
+    /// The transaction blocks confirmed by the proposer block of this state and its ancestors
+    pub txBlockOrderedList: Confirmations,
+    /// Transactions the proposer block of this state confirmed but did not apply
+    pub skipped: Vec<Skipped>,
+    /// Unspent outputs, used by UTXO transactions only
+    pub utxos: UtxoSet,
+    /// Height and timestamp of the proposer block of this state, time locks are checked
+    /// against them
+    pub height: u32,
+    pub timestamp: u128,
 }
 
 impl State {
     
 	pub fn new() -> Self {
-                let mut states: HashMap<H160,(u32,u32)> = HashMap::new();
-                let mut txBlockOrderedList: Vec<H256> = Vec::new();
-	            return State{states:states, txBlockOrderedList:txBlockOrderedList};
+                let mut states: HashMap<H160,(u32,u64)> = HashMap::new();
+                let mut txBlockOrderedList = Confirmations::new();
+	            return State{states:states, accounts:SparseMerkleTree::default(), txBlockOrderedList:txBlockOrderedList, skipped:vec![], utxos:UtxoSet::new(), height:0, timestamp:0};
 	}
 
-    pub fn insert(&mut self, address: H160, balance: u32, nonce: u32) {
+    pub fn insert(&mut self, address: H160, balance: u64, nonce: u32) {
         self.states.insert(address,(nonce,balance));
+        self.accounts.insert(address, account_hash(&(nonce,balance)));
+    }
+
+    /// (nonce, balance) of `address`, an address without an account has its initial balance.
+    pub fn account(&self, address: &H160) -> (u32, u64) {
+        match self.states.get(address) {
+            Some(account) => *account,
+            None => (0, INITIAL_BALANCE),
+        }
+    }
+
+    /// Sparse Merkle root of the accounts, committed to by the proposer block of this state. The
+    /// leaf of an address is its `account_hash`; addresses without an account have no leaf, nor
+    /// do unspent outputs.
+    pub fn state_root(&self) -> H256 {
+        self.accounts.root()
+    }
+
+    /// The proof of the account of `address`, or of its absence, under `state_root`.
+    pub fn account_proof(&self, address: &H160) -> SparseProof {
+        self.accounts.proof(address)
+    }
+
+    /// Take `value` from the balance of `address`.
+    pub fn debit(&mut self, address: &H160, value: u64) -> Result<(), LedgerError> {
+        let (nonce, balance) = self.account(address);
+        let balance = balance.checked_sub(value).ok_or(LedgerError::InsufficientBalance)?;
+        self.insert(*address, balance, nonce);
+        return Ok(());
+    }
+
+    /// Add `value` to the balance of `address`.
+    pub fn credit(&mut self, address: &H160, value: u64) -> Result<(), LedgerError> {
+        let (nonce, balance) = self.account(address);
+        let balance = balance.checked_add(value).ok_or(LedgerError::Overflow)?;
+        self.insert(*address, balance, nonce);
+        return Ok(());
     }
 
     pub fn addressCheck(&self, public_key: &[u8]) -> bool {
@@ -49,16 +266,284 @@ impl State {
             return false;
         }
     }
+    /// Whether `accountNonce` is the next nonce of `address`, one past the nonce of its last
+    /// applied transaction.
+    pub fn nonceCheck(&self, address: &H160, accountNonce: u32) -> Result<(), SkipReason> {
+        let nonce = self.account(address).0;
+        if accountNonce <= nonce {
+            return Err(SkipReason::StaleNonce);
+        }
+        if accountNonce - nonce > 1 {
+            return Err(SkipReason::NonceGap);
+        }
+        return Ok(());
+    }
+
     // CODE
-    pub fn spendCheck(&self, public_key: &[u8], value:u32, accountNonce:u32) -> bool {
-        let accountInfo = self.states[&public_key.into()];
-        if (accountInfo.0 < accountNonce) && (accountInfo.1 >= value) {
-        // if (accountInfo.1 >= value){
+    /// Whether a transaction can apply next: it uses the next nonce and the balance covers it.
+    pub fn spendCheck(&self, public_key: &[u8], value:u64, accountNonce:u32) -> bool {
+        let address: H160 = public_key.into();
+        let accountInfo = self.account(&address);
+        if self.nonceCheck(&address, accountNonce).is_ok() && (accountInfo.1 >= value) {
             return true;
         } else {
             return false;
         }
     }
+
+    /// Whether the address holds an account or received or spent an output.
+    pub fn knows(&self, address: &H160) -> bool {
+        self.states.contains_key(address) || self.utxos.knows(address)
+    }
+
+    /// Whether the transaction can never be applied: the sender already used its nonce, or an
+    /// output it spends is gone.
+    pub fn staleCheck(&self, signedTransaction: &SignedTransaction) -> bool {
+        if let Some((senderAddr, accountNonce)) = signedTransaction.sender_nonce() {
+            return match self.states.get(&senderAddr) {
+                Some(accountInfo) => accountInfo.0 >= accountNonce,
+                None => false,
+            };
+        }
+        match signedTransaction {
+            SignedTransaction::Utxo(t) => self.utxos.spent(t),
+            _ => false,
+        }
+    }
+
+    /// Confirm the transaction blocks `tp` this state has not confirmed yet and apply their
+    /// transactions, one at a time in the order they are listed. Transactions that cannot be
+    /// applied are skipped and recorded in `skipped`, so the outcome only depends on the ordered
+    /// list. A sender's nonce goes up by one with every applied transaction and a transaction must
+    /// use the next one, which keeps a transaction or a (sender, nonce) pair from being applied
+    /// twice across proposer blocks; a UTXO transaction cannot be applied twice as its inputs are
+    /// spent. `height` and `timestamp` are those of the proposer block, signatures are checked
+    /// by `verifier`. If a transaction block to confirm is not in `txBlocks`, nothing is applied
+    /// and the missing ones are returned.
+    pub fn apply_tx_blocks(&mut self, tp: &[H256], txBlocks: &HashMap<H256, Block>, height: u32, timestamp: u128, verifier: &Verifier) -> Result<(), Vec<H256>> {
+        let missing = self.missing_tx_blocks(tp, txBlocks);
+        if !missing.is_empty() {
+            return Err(missing);
+        }
+        self.skipped.clear();
+        self.height = height;
+        self.timestamp = timestamp;
+        let mut seen_transactions = HashSet::new();
+        let mut seen_claims = HashSet::new();
+        for txpointer in tp {
+            if !self.txBlockOrderedList.confirm(*txpointer, height) {
+                continue;
+            }
+            let txBlk = &txBlocks[txpointer];
+            for signedTransaction in txBlk.content.data.iter() {
+                let hash = signedTransaction.hash();
+                let claims = signedTransaction.claims();
+                let result = if !seen_transactions.insert(hash) {
+                    Err(SkipReason::DuplicateTransaction)
+                } else if let Some(claim) = claims.iter().find(|c| seen_claims.contains(*c)) {
+                    Err(claim.conflict())
+                } else {
+                    // only an applied transaction uses its nonce or outputs
+                    let result = self.apply(signedTransaction, verifier);
+                    if result.is_ok() {
+                        seen_claims.extend(claims);
+                    }
+                    result
+                };
+                if let Err(reason) = result {
+                    info!("Skip transaction {}: {}", hash, reason);
+                    self.skipped.push(Skipped { tx_block: *txpointer, transaction: hash, reason });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The transaction blocks of `tp` this state has not confirmed and `txBlocks` does not hold.
+    pub fn missing_tx_blocks(&self, tp: &[H256], txBlocks: &HashMap<H256, Block>) -> Vec<H256> {
+        tp.iter()
+            .filter(|h| !txBlocks.contains_key(h) && !self.txBlockOrderedList.contains(h))
+            .copied()
+            .collect()
+    }
+
+    /// Apply one transaction of either model.
+    fn apply(&mut self, signedTransaction: &SignedTransaction, verifier: &Verifier) -> Result<(), SkipReason> {
+        if !verifier.verify(signedTransaction) {
+            return Err(SkipReason::InvalidSignature);
+        }
+        if !signedTransaction.mature(self.height, self.timestamp) {
+            return Err(SkipReason::Immature);
+        }
+        match signedTransaction {
+            SignedTransaction::Account(t) => {
+                let payment = Output { recipientAddr: t.transaction.recipientAddr, value: t.transaction.value };
+                self.apply_account(t.public_key[..].into(), t.transaction.accountNonce, &[payment])
+            }
+            SignedTransaction::Batch(t) => self.apply_account(t.public_key[..].into(), t.transaction.accountNonce, &t.transaction.outputs),
+            SignedTransaction::Multisig(t) => {
+                let payment = Output { recipientAddr: t.transaction.recipientAddr, value: t.transaction.value };
+                self.apply_account(t.multisig.address(), t.transaction.accountNonce, &[payment])
+            }
+            SignedTransaction::Utxo(t) => self.utxos.apply(t),
+        }
+    }
+
+    /// Apply the payments of one account, batch or multisig transaction as a whole, giving
+    /// unknown addresses their initial balance.
+    fn apply_account(&mut self, senderAddr: H160, accountNonce: u32, payments: &[Output]) -> Result<(), SkipReason> {
+        // check the sender and every recipient before touching any, so a refused transfer
+        // changes nothing, not even the accounts that exist
+        self.nonceCheck(&senderAddr, accountNonce)?;
+        let value = utxo::total_value(payments)?;
+        if self.account(&senderAddr).1 < value {
+            return Err(SkipReason::InsufficientBalance);
+        }
+        let mut credits: HashMap<H160, u64> = HashMap::new();
+        for payment in payments.iter().filter(|p| p.recipientAddr != senderAddr) {
+            let credit = credits.entry(payment.recipientAddr).or_insert(0);
+            *credit = credit.checked_add(payment.value).ok_or(LedgerError::Overflow)?;
+        }
+        if credits.iter().any(|(recipientAddr, credit)| self.account(recipientAddr).1.checked_add(*credit).is_none()) {
+            return Err(SkipReason::Overflow);
+        }
+        for address in std::iter::once(&senderAddr).chain(payments.iter().map(|p| &p.recipientAddr)) {
+            if !self.states.contains_key(address) {
+                self.insert(*address, INITIAL_BALANCE, 0);
+                info!("Offer {} coins to address {}", INITIAL_BALANCE / COIN, address);
+            }
+        }
+        self.debit(&senderAddr, value)?;
+        for payment in payments {
+            self.credit(&payment.recipientAddr, payment.value)?;
+            info!("{:} received {:?} coins from {:}",
+                payment.recipientAddr,
+                payment.value,
+                senderAddr,
+            );
+        }
+        self.insert(senderAddr, self.states[&senderAddr].1, accountNonce);
+        return Ok(());
+    }
+}
+
+/// Nonces of account transactions not applied by a state yet, to pick the nonce of the next
+/// transaction of an address. Transactions are waiting in transaction blocks the state has not
+/// confirmed or, if given, in the mempool.
+pub struct PendingNonces<'a> {
+    state: &'a State,
+    waiting: HashMap<H160, HashSet<u32>>,
+}
+
+impl<'a> PendingNonces<'a> {
+    pub fn new(state: &'a State, txBlocks: &HashMap<H256, Block>, mempool: Option<&Mempool>) -> Self {
+        let mut waiting: HashMap<H160, HashSet<u32>> = HashMap::new();
+        let unconfirmed = txBlocks
+            .iter()
+            .filter(|(hash, _)| !state.txBlockOrderedList.contains(hash))
+            .flat_map(|(_, block)| block.content.data.iter());
+        let pooled = mempool.into_iter().flat_map(|m| m.transactions.values().chain(m.immature.values()));
+        for t in unconfirmed.chain(pooled) {
+            if let Some((sender, accountNonce)) = t.sender_nonce() {
+                waiting.entry(sender).or_default().insert(accountNonce);
+            }
+        }
+        PendingNonces { state, waiting }
+    }
+
+    /// The nonce after the state's nonce of `address` and the waiting nonces that follow it
+    /// without a gap.
+    pub fn next(&self, address: &H160) -> u32 {
+        let mut next = self.state.account(address).0.saturating_add(1);
+        if let Some(nonces) = self.waiting.get(address) {
+            while nonces.contains(&next) {
+                next = next.saturating_add(1);
+            }
+        }
+        next
+    }
+
+    /// Count `transaction` as waiting, e.g. once it entered the mempool.
+    pub fn add(&mut self, transaction: &SignedTransaction) {
+        if let Some((sender, accountNonce)) = transaction.sender_nonce() {
+            self.waiting.entry(sender).or_default().insert(accountNonce);
+        }
+    }
+}
+
+/// Unspent outputs of `address` in `state` that no transaction waiting in a transaction block the
+/// state has not confirmed, nor in `mempool`, spends yet.
+pub fn spendable(state: &State, txBlocks: &HashMap<H256, Block>, mempool: &Mempool, address: &H160) -> Vec<(OutPoint, Output)> {
+    let unconfirmed = txBlocks
+        .iter()
+        .filter(|(hash, _)| !state.txBlockOrderedList.contains(hash))
+        .flat_map(|(_, block)| block.content.data.iter());
+    let pooled = mempool.transactions.values().chain(mempool.immature.values());
+    let spent: HashSet<Claim> = unconfirmed.chain(pooled).flat_map(|t| t.claims()).collect();
+    state.utxos.unspent(address).into_iter().filter(|(outpoint, _)| !spent.contains(&Claim::Output(*outpoint))).collect()
+}
+
+/// Balance and nonces of an address, as seen from the tip of the longest chain.
+#[derive(Serialize, Debug, Clone)]
+pub struct AccountInfo {
+    pub balance: u64,
+    /// Nonce of the last transaction of this address included in the chain.
+    pub nonce: u32,
+    /// Nonce the next transaction from this address should use, counting the transactions
+    /// waiting in unconfirmed transaction blocks and in the mempool.
+    pub pending_nonce: u32,
+}
+
+/// The value hash of a (nonce, balance) account in the state tree: the SHA256 of the nonce as
+/// 4 big-endian bytes followed by the balance as 8 big-endian bytes.
+pub fn account_hash(account: &(u32, u64)) -> H256 {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&account.0.to_be_bytes());
+    ctx.update(&account.1.to_be_bytes());
+    ctx.finish().into()
+}
+
+/// The account of an address in the state of a proposer block, for light clients. `account` is
+/// None for an address without an account, which has its initial balance if it holds no
+/// outputs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountProof {
+    pub address: H160,
+    /// The header of the proposer block, whose `state_root` the proof is against
+    pub header: Header,
+    /// (nonce, balance)
+    pub account: Option<(u32, u64)>,
+    pub proof: SparseProof,
+}
+
+impl AccountProof {
+    pub fn new(address: &H160, header: &Header, state: &State) -> Self {
+        AccountProof {
+            address: *address,
+            header: header.clone(),
+            account: state.states.get(address).copied(),
+            proof: state.account_proof(address),
+        }
+    }
+
+    /// Whether `account` is the account of `address` under the state root of `header`. The
+    /// header itself has to be trusted by other means, e.g. by its hash.
+    pub fn verify(&self) -> bool {
+        let value = self.account.as_ref().map(account_hash);
+        sparse_merkle::verify(&self.header.state_root, &self.address, value.as_ref(), &self.proof)
+    }
+}
+
+/// Balance and nonces of `address` in `state`. An address without an account gets the value of
+/// its unspent outputs as balance, which also is the initial balance for an address the ledger
+/// has not seen.
+pub fn account_info(address: &H160, state: &State, txBlocks: &HashMap<H256, Block>, mempool: &Mempool) -> AccountInfo {
+    let (nonce, balance) = match state.states.get(address) {
+        Some(account) => *account,
+        None => (0, state.utxos.balance(address)),
+    };
+    let pending_nonce = PendingNonces::new(state, txBlocks, Some(mempool)).next(address);
+    AccountInfo { balance, nonce, pending_nonce }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -77,24 +562,237 @@ impl TxBlockMempool {
     }
 }
 
+/// The received transaction blocks the state of the tip has not confirmed, in the order they were
+/// received. The miner points to all of them.
+#[derive(Debug, Default, Clone)]
+pub struct UnconfirmedTxBlocks {
+    next: u64,
+    order: BTreeMap<u64, H256>,
+    received: HashMap<H256, u64>,
+}
+
+impl UnconfirmedTxBlocks {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn insert(&mut self, txBlock: H256) {
+        if self.received.contains_key(&txBlock) {
+            return;
+        }
+        self.next += 1;
+        self.order.insert(self.next, txBlock);
+        self.received.insert(txBlock, self.next);
+    }
+
+    pub fn remove(&mut self, txBlock: &H256) -> bool {
+        match self.received.remove(txBlock) {
+            Some(n) => self.order.remove(&n).is_some(),
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, txBlock: &H256) -> bool {
+        self.received.contains_key(txBlock)
+    }
+
+    pub fn len(&self) -> usize {
+        self.received.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.received.is_empty()
+    }
+
+    /// The transaction blocks, the first received first.
+    pub fn iter(&self) -> impl Iterator<Item = &H256> {
+        self.order.values()
+    }
+
+    /// Follow the tip of `blockchain` from `old_tip`: drop the transaction blocks the blocks the
+    /// longest chain gained point to, and take back the ones in `txBlocks` that only the blocks
+    /// it lost confirmed.
+    pub fn follow(&mut self, blockchain: &Blockchain, old_tip: &H256, spb: &StatePerBlock, txBlocks: &HashMap<H256, Block>) {
+        let tip = blockchain.tip();
+        let mut old = *old_tip;
+        let mut new = tip;
+        let mut lost = vec![];
+        while old != new {
+            if blockchain.height[&new] >= blockchain.height[&old] {
+                for txBlock in blockchain.blocks[&new].txPointer.tp.iter() {
+                    self.remove(txBlock);
+                }
+                new = blockchain.blocks[&new].header.parent;
+            } else {
+                lost.push(old);
+                old = blockchain.blocks[&old].header.parent;
+            }
+        }
+        let confirmed = match spb.spb.get(&tip) {
+            Some(state) => &state.txBlockOrderedList,
+            None => return,
+        };
+        for block in lost.iter().rev() {
+            for txBlock in blockchain.blocks[block].txPointer.tp.iter() {
+                if txBlocks.contains_key(txBlock) && !confirmed.contains(txBlock) {
+                    self.insert(*txBlock);
+                }
+            }
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Mempool {
     pub transactions: HashMap<H256,SignedTransaction>,
+    /// Time-locked transactions that cannot apply in the next proposer block yet. They are not
+    /// mined until `promote` moves them to `transactions`.
+    pub immature: HashMap<H256,SignedTransaction>,
 }
 
 impl Mempool {
     pub fn new() -> Self {
         let mut transactions: HashMap<H256,SignedTransaction> = HashMap::new();
-        return Mempool{transactions:transactions};
+        return Mempool{transactions:transactions, immature:HashMap::new()};
     }
 
     pub fn insert(&mut self, transaction: &SignedTransaction) {
         self.transactions.insert(transaction.hash(),transaction.clone());
     }
+
+    /// Queue `transaction` until it matures.
+    pub fn hold(&mut self, transaction: &SignedTransaction) {
+        self.immature.insert(transaction.hash(),transaction.clone());
+    }
+
+    /// Insert `transaction`, or hold it if it cannot apply in the next proposer block, at
+    /// `height` and not before `timestamp`.
+    pub fn insert_or_hold(&mut self, transaction: &SignedTransaction, height: u32, timestamp: u128) {
+        if transaction.mature(height, timestamp) {
+            self.insert(transaction);
+        } else {
+            self.hold(transaction);
+        }
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.transactions.contains_key(hash) || self.immature.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &H256) -> Option<&SignedTransaction> {
+        self.transactions.get(hash).or_else(|| self.immature.get(hash))
+    }
+
+    /// Move the held transactions that can apply in a proposer block at `height` and
+    /// `timestamp` to the transactions to mine. Returns how many moved.
+    pub fn promote(&mut self, height: u32, timestamp: u128) -> usize {
+        let mature: Vec<H256> = self.immature.iter()
+            .filter(|(_, t)| t.mature(height, timestamp))
+            .map(|(h, _)| *h)
+            .collect();
+        for h in mature.iter() {
+            let t = self.immature.remove(h).unwrap();
+            self.transactions.insert(*h, t);
+        }
+        mature.len()
+    }
+
+    /// Drop the transactions that share a claim with one of `included`, they can no longer apply.
+    pub fn remove_conflicts(&mut self, included: &[SignedTransaction]) {
+        let claims: HashSet<Claim> = included.iter().flat_map(|t| t.claims()).collect();
+        self.transactions.retain(|_, t| t.claims().iter().all(|c| !claims.contains(c)));
+        self.immature.retain(|_, t| t.claims().iter().all(|c| !claims.contains(c)));
+    }
+}
+
+/// A transaction of either ledger model. Blocks, the mempool and the network carry both alike,
+/// and the hash is the one of the inner transaction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SignedTransaction {
+    Account(AccountTransaction),
+    /// Several payments of one account, under one signature and nonce
+    Batch(BatchTransaction),
+    /// A payment from a multisig address, signed by enough of its keys
+    Multisig(MultisigTransaction),
+    Utxo(UtxoTransaction),
+}
+
+impl SignedTransaction {
+    pub fn ledger(&self) -> LedgerMode {
+        match self {
+            SignedTransaction::Account(_) | SignedTransaction::Batch(_) | SignedTransaction::Multisig(_) => LedgerMode::Account,
+            SignedTransaction::Utxo(_) => LedgerMode::Utxo,
+        }
+    }
+
+    /// Whether the signatures are valid and made for `chain_id`.
+    pub fn verify(&self, chain_id: &ChainId) -> bool {
+        match self {
+            SignedTransaction::Account(t) => verify(&t.transaction, &t.public_key, &t.signature, chain_id),
+            SignedTransaction::Batch(t) => t.verify(chain_id),
+            SignedTransaction::Multisig(t) => t.verify(chain_id),
+            SignedTransaction::Utxo(t) => t.verify(chain_id),
+        }
+    }
+
+    /// Sender and nonce of a transaction of the account model.
+    pub fn sender_nonce(&self) -> Option<(H160, u32)> {
+        match self {
+            SignedTransaction::Account(t) => Some((t.public_key[..].into(), t.transaction.accountNonce)),
+            SignedTransaction::Batch(t) => Some((t.public_key[..].into(), t.transaction.accountNonce)),
+            SignedTransaction::Multisig(t) => Some((t.multisig.address(), t.transaction.accountNonce)),
+            SignedTransaction::Utxo(_) => None,
+        }
+    }
+
+    /// Total value paid to the recipients.
+    pub fn value(&self) -> Result<u64, LedgerError> {
+        match self {
+            SignedTransaction::Account(t) => Ok(t.transaction.value),
+            SignedTransaction::Batch(t) => utxo::total_value(&t.transaction.outputs),
+            SignedTransaction::Multisig(t) => Ok(t.transaction.value),
+            SignedTransaction::Utxo(t) => t.output_value(),
+        }
+    }
+
+    /// Whether the time locks, if any, allow applying the transaction in a proposer block at
+    /// `height` and `timestamp`.
+    pub fn mature(&self, height: u32, timestamp: u128) -> bool {
+        match self {
+            SignedTransaction::Account(t) => t.transaction.mature(height, timestamp),
+            SignedTransaction::Multisig(t) => t.transaction.mature(height, timestamp),
+            SignedTransaction::Batch(_) | SignedTransaction::Utxo(_) => true,
+        }
+    }
+
+    /// What the transaction uses up. Two transactions with a claim in common never both apply.
+    pub fn claims(&self) -> Vec<Claim> {
+        match self {
+            SignedTransaction::Utxo(t) => t.inputs.iter().map(|i| Claim::Output(i.outpoint)).collect(),
+            _ => self.sender_nonce().into_iter().map(|(sender, nonce)| Claim::Nonce(sender, nonce)).collect(),
+        }
+    }
+}
+
+/// A (sender, nonce) pair of an account transaction or an output a UTXO transaction spends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Claim {
+    Nonce(H160, u32),
+    Output(OutPoint),
+}
+
+impl Claim {
+    /// Why a transaction whose claim is taken by an earlier one is skipped.
+    pub fn conflict(&self) -> SkipReason {
+        match self {
+            Claim::Nonce(..) => SkipReason::DuplicateNonce,
+            Claim::Output(_) => SkipReason::DoubleSpend,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
-pub struct SignedTransaction {
+pub struct AccountTransaction {
     pub signature: Vec<u8>,
     pub public_key: Vec<u8>,
     pub transaction: Transaction,
@@ -103,23 +801,94 @@ pub struct SignedTransaction {
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Transaction {
     pub recipientAddr: H160,
-    pub value: u32,
+    pub value: u64,
     pub accountNonce: u32,
+    /// Lowest height of a proposer block that may apply the transaction
+    pub lock_height: Option<u32>,
+    /// Earliest timestamp, in milliseconds like block timestamps, of a proposer block that may
+    /// apply the transaction
+    pub lock_time: Option<u128>,
 }
 
-/// Create digital signature of a transaction
-pub fn sign(t: &Transaction, key: &Ed25519KeyPair) -> Signature {
-    let encoded_struct: Vec<u8> = bincode::serialize(t).unwrap();
-    let signature = key.sign(&encoded_struct);
+impl Transaction {
+    pub fn mature(&self, height: u32, timestamp: u128) -> bool {
+        self.lock_height.map_or(true, |h| height >= h) && self.lock_time.map_or(true, |t| timestamp >= t)
+    }
+}
+
+/// Payments of one account to several recipients. The batch applies as a whole against the
+/// balance of the sender, or not at all.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct BatchTransaction {
+    pub signature: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub transaction: Batch,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Batch {
+    pub outputs: Vec<Output>,
+    pub accountNonce: u32,
+}
+
+impl BatchTransaction {
+    pub fn sign(transaction: Batch, key: &Ed25519KeyPair, chain_id: &ChainId) -> BatchTransaction {
+        let payload = signing_payload(chain_id, "batch", &transaction);
+        BatchTransaction {
+            signature: key.sign(&payload).as_ref().to_vec(),
+            public_key: key.public_key().as_ref().to_vec(),
+            transaction,
+        }
+    }
+
+    /// Whether the batch pays someone and the signature is valid.
+    pub fn verify(&self, chain_id: &ChainId) -> bool {
+        if self.transaction.outputs.is_empty() {
+            return false;
+        }
+        let payload = signing_payload(chain_id, "batch", &self.transaction);
+        let peer_public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, &self.public_key);
+        return peer_public_key.verify(&payload, &self.signature).is_ok();
+    }
+}
+
+/// A payment from the address of `multisig`, carrying one signature per signing key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultisigTransaction {
+    pub multisig: Multisig,
+    pub signatures: Vec<KeySignature>,
+    pub transaction: Transaction,
+}
+
+impl MultisigTransaction {
+    /// Sign `transaction` with those of `keys` that belong to `multisig`; they may fall short of
+    /// the threshold, e.g. to collect the other signatures elsewhere.
+    pub fn sign(multisig: Multisig, transaction: Transaction, keys: &[&Ed25519KeyPair], chain_id: &ChainId) -> MultisigTransaction {
+        let payload = signing_payload(chain_id, "multisig", &(&multisig, &transaction));
+        let signatures = multisig.sign(&payload, keys);
+        MultisigTransaction { multisig, signatures, transaction }
+    }
+
+    /// Whether the policy is valid and enough of its keys signed.
+    pub fn verify(&self, chain_id: &ChainId) -> bool {
+        let payload = signing_payload(chain_id, "multisig", &(&self.multisig, &self.transaction));
+        self.multisig.verify(&payload, &self.signatures)
+    }
+}
+
+/// Create digital signature of a transaction for the chain `chain_id`
+pub fn sign(t: &Transaction, key: &Ed25519KeyPair, chain_id: &ChainId) -> Signature {
+    let payload = signing_payload(chain_id, "account", t);
+    let signature = key.sign(&payload);
     return signature;
 }
 
 /// Verify digital signature of a transaction, using public key instead of secret key
 // 
-pub fn verify(t: &Transaction, public_key: &[u8], signature: &[u8]) -> bool {
-    let encoded_struct: Vec<u8> = bincode::serialize(t).unwrap();
+pub fn verify(t: &Transaction, public_key: &[u8], signature: &[u8], chain_id: &ChainId) -> bool {
+    let payload = signing_payload(chain_id, "account", t);
     let peer_public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key);
-    return peer_public_key.verify(&encoded_struct, signature).is_ok();    
+    return peer_public_key.verify(&payload, signature).is_ok();    
 }
 
 impl Hashable for Transaction {
@@ -131,6 +900,33 @@ impl Hashable for Transaction {
 }
 
 impl Hashable for SignedTransaction {
+    fn hash(&self) -> H256 {
+        match self {
+            SignedTransaction::Account(t) => t.hash(),
+            SignedTransaction::Batch(t) => t.hash(),
+            SignedTransaction::Multisig(t) => t.hash(),
+            SignedTransaction::Utxo(t) => t.hash(),
+        }
+    }
+}
+
+impl Hashable for MultisigTransaction {
+    fn hash(&self) -> H256 {
+        let encoded_struct: Vec<u8> = bincode::serialize(&self).unwrap();
+        let hashed_struct = digest::digest(&digest::SHA256, &encoded_struct);
+        return hashed_struct.into();
+    }
+}
+
+impl Hashable for BatchTransaction {
+    fn hash(&self) -> H256 {
+        let encoded_struct: Vec<u8> = bincode::serialize(&self).unwrap();
+        let hashed_struct = digest::digest(&digest::SHA256, &encoded_struct);
+        return hashed_struct.into();
+    }
+}
+
+impl Hashable for AccountTransaction {
     fn hash(&self) -> H256 {
         let encoded_struct: Vec<u8> = bincode::serialize(&self).unwrap();
         let hashed_struct = digest::digest(&digest::SHA256, &encoded_struct);
@@ -142,19 +938,363 @@ impl Hashable for SignedTransaction {
 mod tests {
     use super::*;
     use crate::crypto::key_pair;
+    use crate::metrics::Metrics;
+    use crate::utxo::Output;
+    use std::sync::Arc;
 
     pub fn generate_random_transaction() -> Transaction {
         //Default::default();
         let mut rng = rand::thread_rng();
-        let n1: u8 = rng.gen();
-        Transaction{transaction: n1}
+        let recipient = key_pair::random();
+        Transaction{recipientAddr: recipient.public_key().as_ref().into(), value: rng.gen(), accountNonce: rng.gen(), lock_height: None, lock_time: None}
+    }
+
+    fn chain() -> ChainId {
+        ChainId::new(&[0u8; 32].into(), None)
+    }
+
+    fn verifier() -> Verifier {
+        Verifier::new(chain(), 64, &Arc::new(Metrics::new()))
     }
 
     #[test]
     fn sign_verify() {
-        let t = generate_random_transaction();
+        let mut t = generate_random_transaction();
+        t.value = 1;
+        t.accountNonce = 1;
         let key = key_pair::random();
-        let signature = sign(&t, &key);
-        assert!(verify(&t, &(key.public_key()), &signature.as_ref()));
+        let signature = sign(&t, &key, &chain());
+        assert!(verify(&t, key.public_key().as_ref(), signature.as_ref(), &chain()));
+
+        // a signature made for another chain, or over the bare transaction, does not verify
+        let other = ChainId::new(&[0u8; 32].into(), Some("testnet"));
+        assert_ne!(other, chain());
+        assert!(!verify(&t, key.public_key().as_ref(), signature.as_ref(), &other));
+        let bare = key.sign(&bincode::serialize(&t).unwrap());
+        assert!(!verify(&t, key.public_key().as_ref(), bare.as_ref(), &chain()));
+        let signed = SignedTransaction::Account(AccountTransaction{signature: signature.as_ref().to_vec(), public_key: key.public_key().as_ref().to_vec(), transaction: t});
+        let mut state = State::new();
+        assert_eq!(state.apply(&signed, &Verifier::new(other, 64, &Arc::new(Metrics::new()))), Err(SkipReason::InvalidSignature));
+        assert_eq!(state.apply(&signed, &verifier()), Ok(()));
+    }
+
+    fn signed(key: &Ed25519KeyPair, recipientAddr: H160, value: u64, accountNonce: u32) -> SignedTransaction {
+        let transaction = Transaction{recipientAddr: recipientAddr, value: value, accountNonce: accountNonce, lock_height: None, lock_time: None};
+        let signature = sign(&transaction, key, &chain());
+        SignedTransaction::Account(AccountTransaction{signature: signature.as_ref().to_vec(), public_key: key.public_key().as_ref().to_vec(), transaction: transaction})
+    }
+
+    fn tx_block(data: Vec<SignedTransaction>) -> (H256, Block) {
+        let mut block = crate::block::test::generate_random_block(&[0u8; 32].into());
+        block.content.data = data;
+        (block.hash(), block)
+    }
+
+    #[test]
+    fn apply_tx_blocks_skips() {
+        let alice = key_pair::random();
+        let bob: H160 = key_pair::random().public_key().as_ref().into();
+        let aliceAddr: H160 = alice.public_key().as_ref().into();
+        let pay = signed(&alice, bob, 100, 1);
+        let (first, firstBlock) = tx_block(vec![pay.clone(), pay.clone(), signed(&alice, bob, 50, 1)]);
+        let (second, secondBlock) = tx_block(vec![
+            pay.clone(),
+            signed(&alice, bob, INITIAL_BALANCE, 2),
+            signed(&alice, bob, 10, 3),
+            signed(&alice, bob, 10, 2),
+        ]);
+        let mut txBlocks = HashMap::new();
+        txBlocks.insert(first, firstBlock);
+        txBlocks.insert(second, secondBlock);
+
+        let mut state = State::new();
+        state.apply_tx_blocks(&[first], &txBlocks, 1, 0, &verifier()).unwrap();
+        let reasons: Vec<SkipReason> = state.skipped.iter().map(|s| s.reason).collect();
+        assert_eq!(reasons, vec![SkipReason::DuplicateTransaction, SkipReason::DuplicateNonce]);
+        assert_eq!(state.states[&aliceAddr], (1, INITIAL_BALANCE - 100));
+        assert_eq!(state.states[&bob], (0, INITIAL_BALANCE + 100));
+        // nonces 2 and 3 wait in the unconfirmed second block
+        assert_eq!(PendingNonces::new(&state, &txBlocks, None).next(&aliceAddr), 4);
+        assert_eq!(PendingNonces::new(&state, &HashMap::new(), None).next(&aliceAddr), 2);
+        // transactions accepted later fill the gap they leave
+        let mut pending = PendingNonces::new(&state, &HashMap::new(), None);
+        pending.add(&signed(&alice, bob, 10, 3));
+        assert_eq!(pending.next(&aliceAddr), 2);
+        pending.add(&signed(&alice, bob, 10, 2));
+        assert_eq!(pending.next(&aliceAddr), 4);
+
+        // an unknown transaction block is not confirmed, nor is anything else applied
+        let unknown: H256 = (&[9u8; 32]).into();
+        assert_eq!(state.apply_tx_blocks(&[first, second, unknown], &txBlocks, 2, 0, &verifier()), Err(vec![unknown]));
+        assert_eq!(state.txBlockOrderedList.to_vec(), vec![first]);
+        assert_eq!(state.states[&aliceAddr], (1, INITIAL_BALANCE - 100));
+
+        // the replay in the second block is stale, the overspend is skipped, so nonce 3 leaves a
+        // gap until the second nonce 2
+        state.apply_tx_blocks(&[first, second], &txBlocks, 2, 0, &verifier()).unwrap();
+        let reasons: Vec<SkipReason> = state.skipped.iter().map(|s| s.reason).collect();
+        assert_eq!(reasons, vec![SkipReason::StaleNonce, SkipReason::InsufficientBalance, SkipReason::NonceGap]);
+        assert_eq!(state.states[&aliceAddr], (2, INITIAL_BALANCE - 110));
+        assert_eq!(state.states[&bob], (0, INITIAL_BALANCE + 110));
+        assert_eq!(state.txBlockOrderedList.to_vec(), vec![first, second]);
+    }
+
+    #[test]
+    fn state_root_commits_to_accounts() {
+        let alice = key_pair::random();
+        let aliceAddr: H160 = alice.public_key().as_ref().into();
+        let bob: H160 = key_pair::random().public_key().as_ref().into();
+        let mut state = State::new();
+        assert_eq!(state.state_root(), (&crate::crypto::sparse_merkle::EMPTY_ROOT).into());
+        assert_eq!(state.apply(&signed(&alice, bob, 100, 1), &verifier()), Ok(()));
+        let root = state.state_root();
+
+        // the root depends on the accounts only, not on how the state got them
+        let mut same = State::new();
+        same.insert(bob, INITIAL_BALANCE + 100, 0);
+        same.insert(aliceAddr, INITIAL_BALANCE - 100, 1);
+        same.txBlockOrderedList.confirm(H256::default(), 1);
+        assert_eq!(same.state_root(), root);
+        // a skipped transaction changes nothing
+        assert_eq!(state.apply(&signed(&alice, bob, INITIAL_BALANCE, 2), &verifier()), Err(SkipReason::InsufficientBalance));
+        assert_eq!(state.state_root(), root);
+        assert_eq!(state.apply(&signed(&alice, bob, 1, 2), &verifier()), Ok(()));
+        assert_ne!(state.state_root(), root);
+
+        let mut header = crate::block::test::generate_random_block(&[0u8; 32].into()).header;
+        header.state_root = state.state_root();
+        let proof = AccountProof::new(&aliceAddr, &header, &state);
+        assert_eq!(proof.account, Some((2, INITIAL_BALANCE - 101)));
+        assert!(proof.verify());
+        let mut tampered = proof.clone();
+        tampered.account = Some((2, INITIAL_BALANCE));
+        assert!(!tampered.verify());
+        tampered.account = None;
+        assert!(!tampered.verify());
+        let carol: H160 = key_pair::random().public_key().as_ref().into();
+        let absent = AccountProof::new(&carol, &header, &state);
+        assert_eq!(absent.account, None);
+        assert!(absent.verify());
+        // the proof is against the state root of the header
+        header.state_root = root;
+        assert!(!AccountProof { header, ..proof }.verify());
+    }
+
+    #[test]
+    fn apply_refuses_overflow() {
+        let alice = key_pair::random();
+        let aliceAddr: H160 = alice.public_key().as_ref().into();
+        let bob: H160 = key_pair::random().public_key().as_ref().into();
+        let mut state = State::new();
+        state.insert(bob, u64::max_value(), 0);
+        let before = state.states.clone();
+        assert_eq!(state.apply(&signed(&alice, bob, 1, 1), &verifier()), Err(SkipReason::Overflow));
+        // a refused transfer adds no account, not even the sender's
+        assert_eq!(state.states, before);
+        assert!(!state.knows(&aliceAddr));
+        assert_eq!(state.apply(&signed(&alice, bob, INITIAL_BALANCE + 1, 1), &verifier()), Err(SkipReason::InsufficientBalance));
+        assert_eq!(state.apply(&signed(&alice, bob, 1, 2), &verifier()), Err(SkipReason::NonceGap));
+        assert_eq!(state.states, before);
+        assert_eq!(state.credit(&bob, 1), Err(LedgerError::Overflow));
+        assert_eq!(state.debit(&aliceAddr, INITIAL_BALANCE + 1), Err(LedgerError::InsufficientBalance));
+        assert_eq!(state.states, before);
+
+        // a self-transfer of the whole balance neither underflows nor overflows
+        assert_eq!(state.apply(&signed(&alice, aliceAddr, INITIAL_BALANCE, 1), &verifier()), Ok(()));
+        assert_eq!(state.states[&aliceAddr], (1, INITIAL_BALANCE));
+
+        let outputs = vec![Output{recipientAddr: bob, value: u64::max_value()}, Output{recipientAddr: bob, value: 1}];
+        let utxo = UtxoTransaction::sign(&[(OutPoint::initial(&aliceAddr), &alice)], outputs, &chain());
+        assert_eq!(state.apply(&SignedTransaction::Utxo(utxo), &verifier()), Err(SkipReason::Overflow));
+    }
+
+    #[test]
+    fn apply_batch() {
+        let alice = key_pair::random();
+        let aliceAddr: H160 = alice.public_key().as_ref().into();
+        let bob: H160 = key_pair::random().public_key().as_ref().into();
+        let carol: H160 = key_pair::random().public_key().as_ref().into();
+        let batch = |outputs: Vec<(H160, u64)>, accountNonce: u32| {
+            let outputs = outputs.into_iter().map(|(recipientAddr, value)| Output{recipientAddr, value}).collect();
+            SignedTransaction::Batch(BatchTransaction::sign(Batch{outputs, accountNonce}, &alice, &chain()))
+        };
+        let mut state = State::new();
+        let pay = batch(vec![(bob, 100), (carol, 200), (bob, 1)], 1);
+        assert!(pay.verify(&chain()));
+        assert_eq!(pay.claims(), vec![Claim::Nonce(aliceAddr, 1)]);
+        assert_eq!(state.apply(&pay, &verifier()), Ok(()));
+        assert_eq!(state.states[&aliceAddr], (1, INITIAL_BALANCE - 301));
+        assert_eq!(state.states[&bob], (0, INITIAL_BALANCE + 101));
+        assert_eq!(state.states[&carol], (0, INITIAL_BALANCE + 200));
+
+        // a batch applies as a whole or not at all
+        assert_eq!(state.apply(&batch(vec![(bob, 1), (carol, INITIAL_BALANCE)], 2), &verifier()), Err(SkipReason::InsufficientBalance));
+        assert_eq!(state.apply(&batch(vec![(bob, u64::max_value()), (carol, 1)], 2), &verifier()), Err(SkipReason::Overflow));
+        state.insert(carol, u64::max_value(), 0);
+        assert_eq!(state.apply(&batch(vec![(bob, 1), (carol, 1)], 2), &verifier()), Err(SkipReason::Overflow));
+        assert_eq!(state.states[&aliceAddr], (1, INITIAL_BALANCE - 301));
+        assert_eq!(state.states[&bob], (0, INITIAL_BALANCE + 101));
+
+        assert!(!batch(vec![], 2).verify(&chain()));
+        if let SignedTransaction::Batch(mut tampered) = batch(vec![(bob, 1)], 2) {
+            tampered.transaction.outputs[0].value = 2;
+            assert!(!tampered.verify(&chain()));
+        }
+    }
+
+    #[test]
+    fn apply_multisig() {
+        let keys: Vec<Ed25519KeyPair> = (0..3).map(|_| key_pair::random()).collect();
+        let multisig = Multisig::new(2, keys.iter().map(|k| k.public_key().as_ref().to_vec()).collect()).unwrap();
+        let treasury = multisig.address();
+        let bob: H160 = key_pair::random().public_key().as_ref().into();
+        let pay = |signers: &[&Ed25519KeyPair], accountNonce: u32| {
+            let transaction = Transaction{recipientAddr: bob, value: 100, accountNonce, lock_height: None, lock_time: None};
+            SignedTransaction::Multisig(MultisigTransaction::sign(multisig.clone(), transaction, signers, &chain()))
+        };
+        let mut state = State::new();
+        assert_eq!(state.apply(&pay(&[&keys[1]], 1), &verifier()), Err(SkipReason::InvalidSignature));
+        let paid = pay(&[&keys[0], &keys[2]], 1);
+        assert_eq!(paid.sender_nonce(), Some((treasury, 1)));
+        assert_eq!(state.apply(&paid, &verifier()), Ok(()));
+        assert_eq!(state.states[&treasury], (1, INITIAL_BALANCE - 100));
+        assert_eq!(state.states[&bob], (0, INITIAL_BALANCE + 100));
+        assert!(state.staleCheck(&paid));
+    }
+
+    #[test]
+    fn time_locks() {
+        let alice = key_pair::random();
+        let bob: H160 = key_pair::random().public_key().as_ref().into();
+        let locked = |lock_height: Option<u32>, lock_time: Option<u128>, accountNonce: u32| {
+            let transaction = Transaction{recipientAddr: bob, value: 100, accountNonce, lock_height, lock_time};
+            let signature = sign(&transaction, &alice, &chain());
+            SignedTransaction::Account(AccountTransaction{signature: signature.as_ref().to_vec(), public_key: alice.public_key().as_ref().to_vec(), transaction})
+        };
+        let vesting = locked(Some(3), None, 1);
+        let (first, firstBlock) = tx_block(vec![vesting.clone()]);
+        let (second, secondBlock) = tx_block(vec![vesting.clone(), locked(None, Some(5000), 2)]);
+        let mut txBlocks = HashMap::new();
+        txBlocks.insert(first, firstBlock);
+        txBlocks.insert(second, secondBlock);
+
+        let mut state = State::new();
+        state.apply_tx_blocks(&[first], &txBlocks, 2, 1000, &verifier()).unwrap();
+        let reasons: Vec<SkipReason> = state.skipped.iter().map(|s| s.reason).collect();
+        assert_eq!(reasons, vec![SkipReason::Immature]);
+        assert!(!state.states.contains_key(&bob));
+        state.apply_tx_blocks(&[first, second], &txBlocks, 3, 4999, &verifier()).unwrap();
+        let reasons: Vec<SkipReason> = state.skipped.iter().map(|s| s.reason).collect();
+        assert_eq!(reasons, vec![SkipReason::Immature]);
+        assert_eq!(state.states[&bob], (0, INITIAL_BALANCE + 100));
+
+        // the mempool holds a transaction until the next proposer block can apply it
+        let mut mempool = Mempool::new();
+        mempool.insert_or_hold(&vesting, 2, 0);
+        assert!(mempool.transactions.is_empty() && mempool.contains(&vesting.hash()));
+        assert_eq!(mempool.promote(2, u128::max_value()), 0);
+        assert_eq!(mempool.promote(3, 0), 1);
+        assert!(mempool.transactions.contains_key(&vesting.hash()) && mempool.immature.is_empty());
+    }
+
+    #[test]
+    fn apply_tx_blocks_utxo() {
+        let alice = key_pair::random();
+        let aliceAddr: H160 = alice.public_key().as_ref().into();
+        let bob: H160 = key_pair::random().public_key().as_ref().into();
+        let carol: H160 = key_pair::random().public_key().as_ref().into();
+        let initial = OutPoint::initial(&aliceAddr);
+        let pay = |to: H160| SignedTransaction::Utxo(UtxoTransaction::sign(&[(initial, &alice)], vec![Output{recipientAddr: to, value: INITIAL_BALANCE}], &chain()));
+        let (first, firstBlock) = tx_block(vec![pay(bob), pay(carol)]);
+        let mut txBlocks = HashMap::new();
+        txBlocks.insert(first, firstBlock);
+
+        let mut state = State::new();
+        state.apply_tx_blocks(&[first], &txBlocks, 1, 0, &verifier()).unwrap();
+        assert_eq!(state.skipped.iter().map(|s| s.reason).collect::<Vec<_>>(), vec![SkipReason::DoubleSpend]);
+        assert_eq!(state.utxos.balance(&aliceAddr), 0);
+        assert_eq!(state.utxos.balance(&bob), 2 * INITIAL_BALANCE);
+        assert_eq!(state.utxos.balance(&carol), INITIAL_BALANCE);
+        assert!(state.staleCheck(&pay(carol)));
+
+        // merkle leaves are the hashes of the inner transactions, so outputs refer to them
+        let account = signed(&alice, bob, 1, 1);
+        if let SignedTransaction::Account(inner) = &account {
+            assert_eq!(account.hash(), inner.hash());
+        }
+        assert!(state.utxos.utxos.contains_key(&OutPoint{tx: pay(bob).hash(), index: 0}));
+    }
+
+    #[test]
+    fn spendable_skips_pending_spends() {
+        let alice = key_pair::random();
+        let aliceAddr: H160 = alice.public_key().as_ref().into();
+        let bob = key_pair::random();
+        let bobAddr: H160 = bob.public_key().as_ref().into();
+        let payment = UtxoTransaction::sign(&[(OutPoint::initial(&aliceAddr), &alice)], vec![Output{recipientAddr: bobAddr, value: INITIAL_BALANCE}], &chain());
+        let paid = OutPoint{tx: payment.hash(), index: 0};
+        let (first, firstBlock) = tx_block(vec![SignedTransaction::Utxo(payment)]);
+        let mut txBlocks = HashMap::new();
+        txBlocks.insert(first, firstBlock);
+        let mut state = State::new();
+        state.apply_tx_blocks(&[first], &txBlocks, 1, 0, &verifier()).unwrap();
+        let mut mempool = Mempool::new();
+        let outpoints = |spendable: Vec<(OutPoint, Output)>| spendable.into_iter().map(|(o, _)| o).collect::<Vec<OutPoint>>();
+        assert_eq!(outpoints(spendable(&state, &txBlocks, &mempool, &bobAddr)).len(), 2);
+        assert!(spendable(&state, &txBlocks, &mempool, &aliceAddr).is_empty());
+
+        // a transaction block the state has not confirmed spends the initial output of bob
+        let spend = |outpoint: OutPoint| SignedTransaction::Utxo(UtxoTransaction::sign(&[(outpoint, &bob)], vec![Output{recipientAddr: aliceAddr, value: 1}], &chain()));
+        let (second, secondBlock) = tx_block(vec![spend(OutPoint::initial(&bobAddr))]);
+        txBlocks.insert(second, secondBlock);
+        assert_eq!(outpoints(spendable(&state, &txBlocks, &mempool, &bobAddr)), vec![paid]);
+
+        // and the mempool the payment
+        let pooled = spend(paid);
+        mempool.transactions.insert(pooled.hash(), pooled);
+        assert!(spendable(&state, &txBlocks, &mempool, &bobAddr).is_empty());
+    }
+
+    #[test]
+    fn unconfirmed_tx_blocks_follow_the_tip() {
+        use crate::block::test::generate_random_block;
+        let mut blockchain = Blockchain::new();
+        let genesis = blockchain.genesis;
+        let mut spb = StatePerBlock::new(genesis, State::new());
+        let txBlocks: HashMap<H256, Block> = (0..4).map(|_| generate_random_block(&genesis)).map(|b| (b.hash(), b)).collect();
+        let t: Vec<H256> = txBlocks.keys().copied().collect();
+        let mut unconfirmed = UnconfirmedTxBlocks::new();
+        for h in t.iter() {
+            unconfirmed.insert(*h);
+        }
+        unconfirmed.insert(t[0]);
+        assert_eq!(unconfirmed.len(), 4);
+
+        // adds the proposer block pointing to `tp` on `parent` and follows the tip
+        let mut add = |parent: &H256, tp: Vec<H256>, unconfirmed: &mut UnconfirmedTxBlocks| {
+            let mut block = generate_random_block(parent);
+            block.txPointer.tp = tp.clone();
+            let mut state = spb.spb[parent].clone();
+            for h in tp {
+                state.txBlockOrderedList.confirm(h, blockchain.height[parent] + 1);
+            }
+            let old_tip = blockchain.tip();
+            spb.insert(block.hash(), &state);
+            blockchain.insert(&block);
+            unconfirmed.follow(&blockchain, &old_tip, &spb, &txBlocks);
+            block.hash()
+        };
+        // genesis <- a1 <- a2, then the longer genesis <- b1 <- b2 <- b3
+        let a1 = add(&genesis, vec![t[0]], &mut unconfirmed);
+        add(&a1, vec![t[1]], &mut unconfirmed);
+        assert_eq!(unconfirmed.iter().copied().collect::<Vec<H256>>(), vec![t[2], t[3]]);
+        let b1 = add(&genesis, vec![t[0]], &mut unconfirmed);
+        let b2 = add(&b1, vec![t[2]], &mut unconfirmed);
+        // b2 is no longer than a2, the tip stays
+        assert_eq!(unconfirmed.len(), 2);
+        add(&b2, vec![], &mut unconfirmed);
+        // only a2 confirmed the second one, it is taken back
+        assert_eq!(unconfirmed.iter().copied().collect::<Vec<H256>>(), vec![t[3], t[1]]);
+        assert!(unconfirmed.remove(&t[3]) && !unconfirmed.contains(&t[3]) && !unconfirmed.remove(&t[3]));
     }
 }
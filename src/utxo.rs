@@ -0,0 +1,245 @@
+use serde::{Serialize, Deserialize};
+use ring::digest;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::collections::{HashMap, HashSet};
+use crate::crypto::hash::{H256, Hashable};
+use crate::crypto::address::H160;
+use crate::transaction::{signing_payload, ChainId, LedgerError, SkipReason, INITIAL_BALANCE};
+
+/// An output of an earlier transaction: its hash and the position in its outputs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    pub tx: H256,
+    pub index: u32,
+}
+
+impl OutPoint {
+    /// The initial output of `address`, worth the initial balance of the account model. It exists
+    /// until the address spends it.
+    pub fn initial(address: &H160) -> OutPoint {
+        let tx = digest::digest(&digest::SHA256, address.as_ref()).into();
+        OutPoint { tx, index: u32::max_value() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub recipientAddr: H160,
+    pub value: u64,
+}
+
+/// Spends an output, signed by the key of the address that owns it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Input {
+    pub outpoint: OutPoint,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// A Bitcoin-style transaction. Every input signs the outpoints and the outputs of the whole
+/// transaction, the value of the inputs not paid to an output is burnt.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UtxoTransaction {
+    pub inputs: Vec<Input>,
+    pub outputs: Vec<Output>,
+}
+
+impl UtxoTransaction {
+    /// Build a transaction spending each outpoint with the paired key.
+    pub fn sign(spends: &[(OutPoint, &Ed25519KeyPair)], outputs: Vec<Output>, chain_id: &ChainId) -> UtxoTransaction {
+        let outpoints: Vec<OutPoint> = spends.iter().map(|(o, _)| *o).collect();
+        let payload = signing_payload(chain_id, "utxo", &(&outpoints, &outputs));
+        let inputs = spends
+            .iter()
+            .map(|(outpoint, key)| Input {
+                outpoint: *outpoint,
+                public_key: key.public_key().as_ref().to_vec(),
+                signature: key.sign(&payload).as_ref().to_vec(),
+            })
+            .collect();
+        UtxoTransaction { inputs, outputs }
+    }
+
+    /// Whether the transaction spends something and every input signature is valid. Whether the
+    /// keys own the outputs is up to the UTXO set.
+    pub fn verify(&self, chain_id: &ChainId) -> bool {
+        if self.inputs.is_empty() {
+            return false;
+        }
+        let outpoints: Vec<OutPoint> = self.inputs.iter().map(|i| i.outpoint).collect();
+        let payload = signing_payload(chain_id, "utxo", &(&outpoints, &self.outputs));
+        self.inputs.iter().all(|i| {
+            let public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, &i.public_key);
+            public_key.verify(&payload, &i.signature).is_ok()
+        })
+    }
+
+    /// Total value of the outputs.
+    pub fn output_value(&self) -> Result<u64, LedgerError> {
+        total_value(&self.outputs)
+    }
+}
+
+/// Sum of the values of `outputs`.
+pub fn total_value(outputs: &[Output]) -> Result<u64, LedgerError> {
+    outputs.iter().try_fold(0u64, |sum, o| sum.checked_add(o.value).ok_or(LedgerError::Overflow))
+}
+
+impl Hashable for UtxoTransaction {
+    fn hash(&self) -> H256 {
+        let encoded_struct: Vec<u8> = bincode::serialize(&self).unwrap();
+        let hashed_struct = digest::digest(&digest::SHA256, &encoded_struct);
+        return hashed_struct.into();
+    }
+}
+
+/// The unspent outputs of a state. Initial outputs are not stored, only whether they are spent.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct UtxoSet {
+    pub utxos: HashMap<OutPoint, Output>,
+    pub initial_spent: HashSet<H160>,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        return UtxoSet { utxos: HashMap::new(), initial_spent: HashSet::new() };
+    }
+
+    /// The unspent output `input` refers to. An initial output is only known through its owner,
+    /// the key of the input.
+    fn lookup(&self, input: &Input) -> Option<Output> {
+        let owner: H160 = input.public_key[..].into();
+        if input.outpoint == OutPoint::initial(&owner) {
+            if self.initial_spent.contains(&owner) {
+                return None;
+            }
+            return Some(Output { recipientAddr: owner, value: INITIAL_BALANCE });
+        }
+        self.utxos.get(&input.outpoint).cloned()
+    }
+
+    /// Whether some input of `tx` is already spent or never existed.
+    pub fn spent(&self, tx: &UtxoTransaction) -> bool {
+        tx.inputs.iter().any(|i| self.lookup(i).is_none())
+    }
+
+    /// Unspent outputs of `address`, its initial output first.
+    pub fn unspent(&self, address: &H160) -> Vec<(OutPoint, Output)> {
+        let mut unspent = vec![];
+        if !self.initial_spent.contains(address) {
+            unspent.push((OutPoint::initial(address), Output { recipientAddr: *address, value: INITIAL_BALANCE }));
+        }
+        for (outpoint, output) in self.utxos.iter() {
+            if output.recipientAddr == *address {
+                unspent.push((*outpoint, output.clone()));
+            }
+        }
+        unspent
+    }
+
+    /// Total value of the unspent outputs of `address`, saturating at the largest amount.
+    pub fn balance(&self, address: &H160) -> u64 {
+        self.unspent(address).iter().fold(0, |sum, (_, o)| sum.saturating_add(o.value))
+    }
+
+    /// Whether `address` received or spent anything.
+    pub fn knows(&self, address: &H160) -> bool {
+        self.initial_spent.contains(address) || self.utxos.values().any(|o| o.recipientAddr == *address)
+    }
+
+    /// Whether `tx` can be applied: its inputs are distinct unspent outputs owned by the signing
+    /// keys and worth at least the outputs. Signatures are not checked.
+    pub fn check(&self, tx: &UtxoTransaction) -> Result<(), SkipReason> {
+        let mut outpoints = HashSet::new();
+        let mut input_value: u64 = 0;
+        for input in tx.inputs.iter() {
+            if !outpoints.insert(input.outpoint) {
+                return Err(SkipReason::DoubleSpend);
+            }
+            let output = self.lookup(input).ok_or(SkipReason::MissingInput)?;
+            let owner: H160 = input.public_key[..].into();
+            if output.recipientAddr != owner {
+                return Err(SkipReason::InvalidSignature);
+            }
+            input_value = input_value.checked_add(output.value).ok_or(LedgerError::Overflow)?;
+        }
+        if tx.output_value()? > input_value {
+            return Err(SkipReason::InsufficientBalance);
+        }
+        return Ok(());
+    }
+
+    /// Spend the inputs of `tx`.
+    pub fn spend(&mut self, tx: &UtxoTransaction) {
+        for input in tx.inputs.iter() {
+            let owner: H160 = input.public_key[..].into();
+            if input.outpoint == OutPoint::initial(&owner) {
+                self.initial_spent.insert(owner);
+            } else {
+                self.utxos.remove(&input.outpoint);
+            }
+        }
+    }
+
+    /// Add the outputs of `tx`.
+    pub fn add(&mut self, tx: &UtxoTransaction) {
+        let hash = tx.hash();
+        for (index, output) in tx.outputs.iter().enumerate() {
+            self.utxos.insert(OutPoint { tx: hash, index: index as u32 }, output.clone());
+        }
+    }
+
+    /// Check `tx`, then spend its inputs and add its outputs.
+    pub fn apply(&mut self, tx: &UtxoTransaction) -> Result<(), SkipReason> {
+        self.check(tx)?;
+        self.spend(tx);
+        self.add(tx);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key_pair;
+
+    fn chain() -> ChainId {
+        ChainId::new(&[0u8; 32].into(), None)
+    }
+
+    #[test]
+    fn spend_and_add() {
+        let alice = key_pair::random();
+        let bob = key_pair::random();
+        let aliceAddr: H160 = alice.public_key().as_ref().into();
+        let bobAddr: H160 = bob.public_key().as_ref().into();
+        let mut set = UtxoSet::new();
+        assert_eq!(set.balance(&aliceAddr), INITIAL_BALANCE);
+
+        let pay = UtxoTransaction::sign(
+            &[(OutPoint::initial(&aliceAddr), &alice)],
+            vec![Output { recipientAddr: bobAddr, value: 300 }, Output { recipientAddr: aliceAddr, value: INITIAL_BALANCE - 300 }],
+            &chain(),
+        );
+        assert!(pay.verify(&chain()));
+        set.apply(&pay).unwrap();
+        assert_eq!(set.balance(&aliceAddr), INITIAL_BALANCE - 300);
+        assert_eq!(set.balance(&bobAddr), INITIAL_BALANCE + 300);
+        assert_eq!(set.apply(&pay), Err(SkipReason::MissingInput));
+
+        // bob cannot spend alice's change, and cannot pay out more than he spends
+        let change = OutPoint { tx: pay.hash(), index: 1 };
+        let steal = UtxoTransaction::sign(&[(change, &bob)], vec![Output { recipientAddr: bobAddr, value: 1 }], &chain());
+        assert!(steal.verify(&chain()));
+        assert_eq!(set.check(&steal), Err(SkipReason::InvalidSignature));
+        let received = OutPoint { tx: pay.hash(), index: 0 };
+        let overspend = UtxoTransaction::sign(&[(received, &bob)], vec![Output { recipientAddr: aliceAddr, value: 301 }], &chain());
+        assert_eq!(set.check(&overspend), Err(SkipReason::InsufficientBalance));
+        let twice = UtxoTransaction::sign(&[(received, &bob), (received, &bob)], vec![], &chain());
+        assert_eq!(set.check(&twice), Err(SkipReason::DoubleSpend));
+
+        let mut tampered = pay.clone();
+        tampered.outputs[0].value = INITIAL_BALANCE;
+        assert!(!tampered.verify(&chain()));
+    }
+}
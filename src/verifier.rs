@@ -0,0 +1,173 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use crate::crypto::hash::{Hashable, H256};
+use crate::metrics::Metrics;
+use crate::transaction::{ChainId, SignedTransaction};
+
+/// The fewest signatures worth a thread of their own, smaller batches are verified inline.
+const THREAD_BATCH: usize = 16;
+
+/// Checks transaction signatures for one chain and remembers the hashes of the transactions that
+/// passed. The hash covers the signatures and keys, so a transaction is verified once, whether it
+/// arrives alone, in a transaction block or again when a proposer block points to that block.
+/// Clones share the cache, which keeps the `capacity` most recently used hashes.
+#[derive(Clone)]
+pub struct Verifier {
+    chain_id: ChainId,
+    verified: Arc<Mutex<Lru>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Verifier {
+    pub fn new(chain_id: ChainId, capacity: usize, metrics: &Arc<Metrics>) -> Verifier {
+        Verifier { chain_id, verified: Arc::new(Mutex::new(Lru::new(capacity))), metrics: Arc::clone(metrics) }
+    }
+
+    /// Whether the signatures of `transaction` are valid, verifying them unless already known.
+    pub fn verify(&self, transaction: &SignedTransaction) -> bool {
+        let hash = transaction.hash();
+        if self.verified.lock().unwrap().touch(&hash) {
+            self.metrics.verify_cache_hits.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        self.metrics.verify_cache_misses.fetch_add(1, Ordering::Relaxed);
+        let valid = transaction.verify(&self.chain_id);
+        if valid {
+            self.verified.lock().unwrap().insert(hash);
+        }
+        valid
+    }
+
+    /// Verify the signatures of every transaction not already known, spread over the CPU cores
+    /// when there are enough of them, so that later calls to `verify` find the valid ones. Ring
+    /// has no Ed25519 batch equation, each signature is checked on its own. Meant to run before
+    /// taking the state locks.
+    pub fn verify_batch<'a, I>(&self, transactions: I)
+    where
+        I: IntoIterator<Item = &'a SignedTransaction>,
+    {
+        let mut pending: Vec<(H256, &SignedTransaction)> = vec![];
+        {
+            let mut verified = self.verified.lock().unwrap();
+            let mut seen = HashSet::new();
+            for t in transactions {
+                let hash = t.hash();
+                if !verified.touch(&hash) && seen.insert(hash) {
+                    pending.push((hash, t));
+                }
+            }
+        }
+        if pending.is_empty() {
+            return;
+        }
+        let chain_id = &self.chain_id;
+        let check = move |part: &[(H256, &SignedTransaction)]| part.iter().filter(|(_, t)| t.verify(chain_id)).map(|(h, _)| *h).collect::<Vec<_>>();
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(pending.len() / THREAD_BATCH);
+        let valid: Vec<H256> = if threads <= 1 {
+            check(&pending)
+        } else {
+            let chunk = (pending.len() + threads - 1) / threads;
+            crossbeam::scope(|s| {
+                let handles: Vec<_> = pending.chunks(chunk).map(|part| s.spawn(move |_| check(part))).collect();
+                handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+            })
+            .unwrap()
+        };
+        let mut verified = self.verified.lock().unwrap();
+        for hash in valid {
+            verified.insert(hash);
+        }
+    }
+}
+
+/// A set of hashes that forgets the least recently used one when full.
+struct Lru {
+    capacity: usize,
+    tick: u64,
+    /// The last use of every hash, and the hash of every last use.
+    used: HashMap<H256, u64>,
+    order: BTreeMap<u64, H256>,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Lru {
+        Lru { capacity, tick: 0, used: HashMap::new(), order: BTreeMap::new() }
+    }
+
+    /// Whether `hash` is in the set, marking it as used if so.
+    fn touch(&mut self, hash: &H256) -> bool {
+        let tick = match self.used.get_mut(hash) {
+            Some(tick) => tick,
+            None => return false,
+        };
+        self.order.remove(tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.order.insert(self.tick, *hash);
+        true
+    }
+
+    fn insert(&mut self, hash: H256) {
+        if self.capacity == 0 || self.touch(&hash) {
+            return;
+        }
+        if self.used.len() == self.capacity {
+            let oldest = *self.order.keys().next().unwrap();
+            let evicted = self.order.remove(&oldest).unwrap();
+            self.used.remove(&evicted);
+        }
+        self.tick += 1;
+        self.used.insert(hash, self.tick);
+        self.order.insert(self.tick, hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::address::H160;
+    use crate::crypto::key_pair;
+    use crate::transaction::{sign, AccountTransaction, Transaction};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    #[test]
+    fn batch_then_cached() {
+        let chain_id = ChainId::new(&[0u8; 32].into(), None);
+        let metrics = Arc::new(Metrics::new());
+        let verifier = Verifier::new(chain_id, 8, &metrics);
+        let key = key_pair::random();
+        let signed = |value: u64, signer: &Ed25519KeyPair| {
+            let transaction = Transaction { recipientAddr: H160::default(), value, accountNonce: 1, lock_height: None, lock_time: None };
+            let signature = sign(&transaction, signer, &chain_id);
+            SignedTransaction::Account(AccountTransaction { signature: signature.as_ref().to_vec(), public_key: key.public_key().as_ref().to_vec(), transaction })
+        };
+        let forged = signed(2, &key_pair::random());
+        let transactions: Vec<SignedTransaction> = (0..9).map(|v| signed(v, &key)).collect();
+        // nine valid transactions in a cache of eight, the first one is forgotten
+        verifier.verify_batch(transactions.iter().chain(transactions.iter()).chain(vec![&forged]));
+        assert_eq!(verifier.verified.lock().unwrap().used.len(), 8);
+        assert!(transactions[1..].iter().all(|t| verifier.verify(t)));
+        assert_eq!(metrics.verify_cache_hits.load(Ordering::Relaxed), 8);
+        assert!(verifier.verify(&transactions[0]));
+        // failures are not cached
+        assert!(!verifier.verify(&forged));
+        assert!(!verifier.verify(&forged));
+        assert_eq!(metrics.verify_cache_misses.load(Ordering::Relaxed), 3);
+        // the second one was used least recently and made room for the first
+        assert!(!verifier.verified.lock().unwrap().touch(&transactions[1].hash()));
+        assert!(verifier.verified.lock().unwrap().touch(&transactions[2].hash()));
+
+        // the results belong to one chain, another verifier does not share them
+        let other = Verifier::new(ChainId::new(&[0u8; 32].into(), Some("testnet")), 8, &metrics);
+        assert!(!other.verify(&transactions[0]));
+
+        // a batch large enough to be spread over threads
+        let verifier = Verifier::new(chain_id, 64, &metrics);
+        let transactions: Vec<SignedTransaction> = (0..4 * THREAD_BATCH as u64).map(|v| signed(v, &key)).collect();
+        verifier.verify_batch(transactions.iter().chain(vec![&forged]));
+        assert_eq!(verifier.verified.lock().unwrap().used.len(), 4 * THREAD_BATCH);
+        assert!(!verifier.verified.lock().unwrap().touch(&forged.hash()));
+    }
+}
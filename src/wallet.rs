@@ -0,0 +1,527 @@
+use serde::{Serialize, Deserialize};
+use ring::aead;
+use ring::constant_time;
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use crate::crypto::address::H160;
+use crate::crypto::hd::ExtendedKey;
+use crate::crypto::key_pair;
+use crate::block::Block;
+use crate::crypto::hash::H256;
+use crate::multisig::{Multisig, MultisigError};
+use crate::transaction::{sign, AccountTransaction, Batch, BatchTransaction, ChainId, Claim, Mempool, MultisigTransaction, PendingNonces, State, Transaction};
+use crate::utxo::{self, OutPoint, Output, UtxoTransaction};
+
+const KEYSTORE_VERSION: u32 = 1;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+/// Number of consecutive unused accounts after which restoring from a mnemonic stops scanning.
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+#[derive(Debug)]
+pub enum WalletError {
+    Io(std::io::Error),
+    /// The keystore file is not valid JSON or has an unsupported version.
+    Format(String),
+    /// The keystore could not be decrypted, usually because of a wrong passphrase.
+    Decrypt,
+    /// The passphrase given to export a secret is not the one of the keystore.
+    WrongPassphrase,
+    KeyRejected,
+    UnknownAddress(H160),
+    DuplicateAddress(H160),
+    Mnemonic(String),
+    /// The wallet has no HD seed to derive accounts from.
+    NoMnemonic,
+    /// The unspent outputs of the address are worth less than the payment.
+    InsufficientFunds,
+    Multisig(MultisigError),
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::Io(e) => write!(f, "keystore i/o error: {}", e),
+            WalletError::Format(e) => write!(f, "malformed keystore: {}", e),
+            WalletError::Decrypt => write!(f, "cannot decrypt keystore, wrong passphrase?"),
+            WalletError::WrongPassphrase => write!(f, "wrong passphrase"),
+            WalletError::KeyRejected => write!(f, "not a valid Ed25519 PKCS#8 key"),
+            WalletError::UnknownAddress(a) => write!(f, "address {:x} is not in the wallet", a),
+            WalletError::DuplicateAddress(a) => write!(f, "address {:x} is already in the wallet", a),
+            WalletError::Mnemonic(e) => write!(f, "invalid mnemonic: {}", e),
+            WalletError::NoMnemonic => write!(f, "the wallet has no mnemonic"),
+            WalletError::InsufficientFunds => write!(f, "insufficient balance"),
+            WalletError::Multisig(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for WalletError {
+    fn from(e: std::io::Error) -> Self {
+        WalletError::Io(e)
+    }
+}
+
+/// On-disk keystore: the wallet contents, encrypted with a key derived from the passphrase.
+#[derive(Serialize, Deserialize)]
+struct Keystore {
+    version: u32,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct KeystoreContents {
+    keys: Vec<StoredKey>,
+    contacts: BTreeMap<String, String>,
+    #[serde(default)]
+    mnemonic: Option<String>,
+    #[serde(default)]
+    next_account: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    label: String,
+    pkcs8: String,
+}
+
+pub struct WalletKey {
+    pub label: String,
+    pub address: H160,
+    pkcs8: Vec<u8>,
+    key: Ed25519KeyPair,
+}
+
+impl WalletKey {
+    pub fn public_key(&self) -> &[u8] {
+        self.key.public_key().as_ref()
+    }
+}
+
+/// A set of owned Ed25519 keys plus an address book, persisted in an encrypted keystore file.
+/// Keys are either random or derived from a BIP-39 mnemonic, so that one phrase backs up every
+/// derived account.
+pub struct Wallet {
+    path: PathBuf,
+    passphrase: String,
+    keys: Vec<WalletKey>,
+    contacts: BTreeMap<String, H160>,
+    mnemonic: Option<bip39::Mnemonic>,
+    next_account: u32,
+}
+
+impl Wallet {
+    /// Open the keystore at `path`, creating an empty one if the file does not exist.
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self, WalletError> {
+        let mut wallet = Wallet {
+            path: path.to_path_buf(),
+            passphrase: passphrase.to_string(),
+            keys: vec![],
+            contacts: BTreeMap::new(),
+            mnemonic: None,
+            next_account: 0,
+        };
+        if !path.exists() {
+            wallet.save()?;
+            return Ok(wallet);
+        }
+        let keystore: Keystore = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| WalletError::Format(e.to_string()))?;
+        if keystore.version != KEYSTORE_VERSION {
+            return Err(WalletError::Format(format!("unsupported version {}", keystore.version)));
+        }
+        let salt = decode_hex(&keystore.salt)?;
+        let nonce = decode_hex(&keystore.nonce)?;
+        let mut in_out = decode_hex(&keystore.ciphertext)?;
+        let key = sealing_key(passphrase, &salt, keystore.iterations)?;
+        let nonce = aead::Nonce::try_assume_unique_for_key(&nonce).map_err(|_| WalletError::Decrypt)?;
+        let plaintext = key
+            .open_in_place(nonce, aead::Aad::empty(), &mut in_out)
+            .map_err(|_| WalletError::Decrypt)?;
+        let contents: KeystoreContents = serde_json::from_slice(plaintext)
+            .map_err(|e| WalletError::Format(e.to_string()))?;
+        for stored in contents.keys {
+            wallet.add_key(decode_hex(&stored.pkcs8)?, &stored.label)?;
+        }
+        for (label, address) in contents.contacts {
+            let address = address.parse().map_err(|_| WalletError::Format(format!("bad contact address {}", address)))?;
+            wallet.contacts.insert(label, address);
+        }
+        if let Some(phrase) = contents.mnemonic {
+            wallet.mnemonic = Some(parse_mnemonic(&phrase)?);
+        }
+        wallet.next_account = contents.next_account;
+        Ok(wallet)
+    }
+
+    /// Encrypt the wallet and write it to its keystore file.
+    pub fn save(&self) -> Result<(), WalletError> {
+        let contents = KeystoreContents {
+            keys: self
+                .keys
+                .iter()
+                .map(|k| StoredKey { label: k.label.clone(), pkcs8: hex::encode(&k.pkcs8) })
+                .collect(),
+            contacts: self.contacts.iter().map(|(l, a)| (l.clone(), format!("{:x}", a))).collect(),
+            mnemonic: self.mnemonic.as_ref().map(|m| m.to_string()),
+            next_account: self.next_account,
+        };
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        rng.fill(&mut salt).unwrap();
+        rng.fill(&mut nonce).unwrap();
+        let key = sealing_key(&self.passphrase, &salt, PBKDF2_ITERATIONS)?;
+        let mut in_out = serde_json::to_vec(&contents).unwrap();
+        key.seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::empty(), &mut in_out)
+            .unwrap();
+        let keystore = Keystore {
+            version: KEYSTORE_VERSION,
+            iterations: PBKDF2_ITERATIONS,
+            salt: hex::encode(&salt),
+            nonce: hex::encode(&nonce),
+            ciphertext: hex::encode(&in_out),
+        };
+        // write to a temporary file first so that a crash never leaves a truncated keystore
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&keystore).unwrap())?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn add_key(&mut self, pkcs8: Vec<u8>, label: &str) -> Result<H160, WalletError> {
+        let key = key_pair::from_pkcs8(&pkcs8).map_err(|_| WalletError::KeyRejected)?;
+        let address: H160 = key.public_key().as_ref().into();
+        if self.keys.iter().any(|k| k.address == address) {
+            return Err(WalletError::DuplicateAddress(address));
+        }
+        self.keys.push(WalletKey { label: label.to_string(), address, pkcs8, key });
+        Ok(address)
+    }
+
+    /// Create a fresh key and persist it.
+    pub fn generate(&mut self, label: &str) -> Result<H160, WalletError> {
+        let address = self.add_key(key_pair::random_pkcs8(), label)?;
+        self.save()?;
+        Ok(address)
+    }
+
+    /// Add an existing PKCS#8 key and persist it.
+    pub fn import(&mut self, pkcs8: &[u8], label: &str) -> Result<H160, WalletError> {
+        let address = self.add_key(pkcs8.to_vec(), label)?;
+        self.save()?;
+        Ok(address)
+    }
+
+    /// The PKCS#8 document of an owned key, only given out against the keystore passphrase.
+    pub fn export(&self, address: &H160, passphrase: &str) -> Result<Vec<u8>, WalletError> {
+        self.unlock(passphrase)?;
+        self.get(address).map(|k| k.pkcs8.clone())
+    }
+
+    /// Check `passphrase` against the one of the keystore, in constant time.
+    fn unlock(&self, passphrase: &str) -> Result<(), WalletError> {
+        constant_time::verify_slices_are_equal(passphrase.as_bytes(), self.passphrase.as_bytes())
+            .map_err(|_| WalletError::WrongPassphrase)
+    }
+
+    pub fn keys(&self) -> &[WalletKey] {
+        &self.keys
+    }
+
+    pub fn get(&self, address: &H160) -> Result<&WalletKey, WalletError> {
+        self.keys.iter().find(|k| k.address == *address).ok_or(WalletError::UnknownAddress(*address))
+    }
+
+    /// Load a standalone copy of an owned key pair.
+    pub fn key_pair(&self, address: &H160) -> Result<Ed25519KeyPair, WalletError> {
+        key_pair::from_pkcs8(&self.get(address)?.pkcs8).map_err(|_| WalletError::KeyRejected)
+    }
+
+    /// Create a fresh 12 word mnemonic for a wallet that does not have one yet. The phrase is
+    /// returned, so the keystore passphrase is required as for `export_mnemonic`.
+    pub fn new_mnemonic(&mut self, passphrase: &str) -> Result<String, WalletError> {
+        self.unlock(passphrase)?;
+        if self.mnemonic.is_some() {
+            return Err(WalletError::Mnemonic("the wallet already has a mnemonic".to_string()));
+        }
+        let mut entropy = [0u8; 16];
+        SystemRandom::new().fill(&mut entropy).unwrap();
+        let mnemonic = bip39::Mnemonic::from_entropy(&entropy).map_err(|e| WalletError::Mnemonic(e.to_string()))?;
+        self.mnemonic = Some(mnemonic);
+        self.next_account = 0;
+        self.save()?;
+        self.export_mnemonic(passphrase)
+    }
+
+    /// The mnemonic backing the HD accounts, only given out against the keystore passphrase.
+    pub fn export_mnemonic(&self, passphrase: &str) -> Result<String, WalletError> {
+        self.unlock(passphrase)?;
+        self.mnemonic.as_ref().map(|m| m.to_string()).ok_or(WalletError::NoMnemonic)
+    }
+
+    /// Derive the next HD account and add it to the wallet.
+    pub fn derive_next(&mut self, label: &str) -> Result<H160, WalletError> {
+        let seed = self.seed()?;
+        let account = self.next_account;
+        let address = self.add_key(ExtendedKey::account(&seed, account).pkcs8(), label)?;
+        self.next_account = account + 1;
+        self.save()?;
+        Ok(address)
+    }
+
+    /// Adopt `phrase` as the wallet mnemonic and rediscover its used accounts: accounts are
+    /// derived in order until `gap_limit` consecutive ones never appear in `state`, and every
+    /// used account is added to the wallet. Returns the recovered addresses.
+    pub fn restore_mnemonic(&mut self, phrase: &str, state: &State, gap_limit: u32) -> Result<Vec<H160>, WalletError> {
+        if self.mnemonic.is_some() {
+            return Err(WalletError::Mnemonic("the wallet already has a mnemonic".to_string()));
+        }
+        let mnemonic = parse_mnemonic(phrase)?;
+        let seed = mnemonic.to_seed("");
+        let mut recovered = vec![];
+        let mut next_account = 0;
+        let mut account = 0;
+        while account < next_account + gap_limit {
+            let key = ExtendedKey::account(&seed, account);
+            let address: H160 = key.key_pair().public_key().as_ref().into();
+            if state.knows(&address) {
+                match self.add_key(key.pkcs8(), &format!("hd-{}", account)) {
+                    Ok(_) | Err(WalletError::DuplicateAddress(_)) => {}
+                    Err(e) => return Err(e),
+                }
+                recovered.push(address);
+                next_account = account + 1;
+            }
+            account += 1;
+        }
+        self.mnemonic = Some(mnemonic);
+        self.next_account = next_account;
+        self.save()?;
+        Ok(recovered)
+    }
+
+    fn seed(&self) -> Result<[u8; 64], WalletError> {
+        self.mnemonic.as_ref().map(|m| m.to_seed("")).ok_or(WalletError::NoMnemonic)
+    }
+
+    pub fn add_contact(&mut self, label: &str, address: H160) -> Result<(), WalletError> {
+        self.contacts.insert(label.to_string(), address);
+        self.save()
+    }
+
+    pub fn contacts(&self) -> &BTreeMap<String, H160> {
+        &self.contacts
+    }
+
+    /// Resolve a recipient given either as an address book label, an owned key label, or a hex address.
+    pub fn resolve(&self, name: &str) -> Option<H160> {
+        if let Some(address) = self.contacts.get(name) {
+            return Some(*address);
+        }
+        if let Some(key) = self.keys.iter().find(|k| k.label == name) {
+            return Some(key.address);
+        }
+        name.parse().ok()
+    }
+
+    /// Sign a payment from an owned address, setting the nonce of `transaction` to the next
+    /// pending nonce of the address. The transaction is not checked against the balance; that is
+    /// up to the caller.
+    pub fn transfer(&self, from: &H160, mut transaction: Transaction, state: &State, txBlocks: &HashMap<H256, Block>, mempool: &Mempool, chain_id: &ChainId) -> Result<AccountTransaction, WalletError> {
+        let key = &self.get(from)?.key;
+        transaction.accountNonce = PendingNonces::new(state, txBlocks, Some(mempool)).next(from);
+        let signature = sign(&transaction, key, chain_id);
+        Ok(AccountTransaction {
+            signature: signature.as_ref().to_vec(),
+            public_key: key.public_key().as_ref().to_vec(),
+            transaction,
+        })
+    }
+
+    /// Build and sign a batch of payments from an owned address under its next pending nonce.
+    /// As with `transfer`, the balance is up to the caller.
+    pub fn transfer_batch(&self, from: &H160, outputs: Vec<Output>, state: &State, txBlocks: &HashMap<H256, Block>, mempool: &Mempool, chain_id: &ChainId) -> Result<BatchTransaction, WalletError> {
+        let key = &self.get(from)?.key;
+        let nonce = PendingNonces::new(state, txBlocks, Some(mempool)).next(from);
+        Ok(BatchTransaction::sign(Batch { outputs, accountNonce: nonce }, key, chain_id))
+    }
+
+    /// Sign a payment from the address of `multisig` with every wallet key of it, setting the
+    /// nonce as `transfer` does. The wallet must hold at least `threshold` of the keys.
+    pub fn transfer_multisig(&self, multisig: Multisig, mut transaction: Transaction, state: &State, txBlocks: &HashMap<H256, Block>, mempool: &Mempool, chain_id: &ChainId) -> Result<MultisigTransaction, WalletError> {
+        let keys: Vec<&Ed25519KeyPair> = self.keys.iter().map(|k| &k.key).collect();
+        transaction.accountNonce = PendingNonces::new(state, txBlocks, Some(mempool)).next(&multisig.address());
+        let signed = MultisigTransaction::sign(multisig, transaction, &keys, chain_id);
+        if signed.signatures.len() < signed.multisig.threshold as usize {
+            return Err(WalletError::Multisig(MultisigError::NotEnoughSignatures));
+        }
+        Ok(signed)
+    }
+
+    /// Build and sign a UTXO transaction from an owned address paying `payments`, spending its
+    /// unspent outputs that no mempool transaction spends yet and paying the change back to it.
+    pub fn transfer_utxo(&self, from: &H160, payments: Vec<Output>, state: &State, mempool: &Mempool, chain_id: &ChainId) -> Result<UtxoTransaction, WalletError> {
+        let key = &self.get(from)?.key;
+        let value = utxo::total_value(&payments).map_err(|_| WalletError::InsufficientFunds)?;
+        let pending: Vec<Claim> = mempool.transactions.values().flat_map(|t| t.claims()).collect();
+        let mut spends: Vec<(OutPoint, &Ed25519KeyPair)> = vec![];
+        let mut total: u64 = 0;
+        for (outpoint, output) in state.utxos.unspent(from) {
+            if total >= value {
+                break;
+            }
+            if pending.contains(&Claim::Output(outpoint)) {
+                continue;
+            }
+            spends.push((outpoint, key));
+            total = total.saturating_add(output.value);
+        }
+        if spends.is_empty() || total < value {
+            return Err(WalletError::InsufficientFunds);
+        }
+        let mut outputs = payments;
+        if total > value {
+            outputs.push(Output { recipientAddr: *from, value: total - value });
+        }
+        Ok(UtxoTransaction::sign(&spends, outputs, chain_id))
+    }
+}
+
+fn parse_mnemonic(phrase: &str) -> Result<bip39::Mnemonic, WalletError> {
+    bip39::Mnemonic::parse(phrase).map_err(|e| WalletError::Mnemonic(e.to_string()))
+}
+
+fn sealing_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<aead::LessSafeKey, WalletError> {
+    let iterations = NonZeroU32::new(iterations).ok_or_else(|| WalletError::Format("zero iterations".to_string()))?;
+    let mut key = [0u8; 32];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), &mut key);
+    let unbound = aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &key).unwrap();
+    Ok(aead::LessSafeKey::new(unbound))
+}
+
+fn decode_hex(s: &str) -> Result<Vec<u8>, WalletError> {
+    hex::decode(s).map_err(|e| WalletError::Format(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::Hashable;
+    use crate::transaction::{account_info, verify, SignedTransaction, INITIAL_BALANCE};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("prism-wallet-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn keystore_roundtrip() {
+        let path = temp_path("roundtrip");
+        let mut wallet = Wallet::open(&path, "secret").unwrap();
+        let a = wallet.generate("a").unwrap();
+        let b = wallet.import(&key_pair::random_pkcs8(), "b").unwrap();
+        wallet.add_contact("alice", b).unwrap();
+        let exported = wallet.export(&a, "secret").unwrap();
+        assert!(matches!(wallet.export(&a, "wrong"), Err(WalletError::WrongPassphrase)));
+
+        let reopened = Wallet::open(&path, "secret").unwrap();
+        let addresses: Vec<H160> = reopened.keys().iter().map(|k| k.address).collect();
+        assert_eq!(addresses, vec![a, b]);
+        assert_eq!(reopened.export(&a, "secret").unwrap(), exported);
+        assert_eq!(reopened.resolve("alice"), Some(b));
+        assert_eq!(reopened.resolve("a"), Some(a));
+        assert_eq!(reopened.resolve(&format!("{:x}", a)), Some(a));
+
+        match Wallet::open(&path, "wrong") {
+            Err(WalletError::Decrypt) => {}
+            _ => panic!("opened keystore with the wrong passphrase"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mnemonic_restore() {
+        let path = temp_path("mnemonic");
+        let mut wallet = Wallet::open(&path, "").unwrap();
+        assert!(matches!(wallet.new_mnemonic("wrong"), Err(WalletError::WrongPassphrase)));
+        let phrase = wallet.new_mnemonic("").unwrap();
+        assert!(matches!(wallet.export_mnemonic("wrong"), Err(WalletError::WrongPassphrase)));
+        assert_eq!(wallet.export_mnemonic("").unwrap(), phrase);
+        let derived: Vec<H160> = (0..4).map(|i| wallet.derive_next(&format!("hd-{}", i)).unwrap()).collect();
+        fs::remove_file(&path).unwrap();
+
+        // accounts 0 and 3 were used on chain, 1 and 2 never were
+        let mut state = State::new();
+        state.insert(derived[0], 1000, 1);
+        state.insert(derived[3], 1000, 0);
+        let mut restored = Wallet::open(&path, "").unwrap();
+        assert_eq!(restored.restore_mnemonic(&phrase, &state, 3).unwrap(), vec![derived[0], derived[3]]);
+        assert_eq!(restored.derive_next("next").unwrap(), ExtendedKey::account(&restored.seed().unwrap(), 4).key_pair().public_key().as_ref().into());
+        // a gap limit of two stops before account 3
+        let mut path_short = path.clone();
+        path_short.set_extension("short");
+        let mut short = Wallet::open(&path_short, "").unwrap();
+        assert_eq!(short.restore_mnemonic(&phrase, &state, 2).unwrap(), vec![derived[0]]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&path_short).unwrap();
+    }
+
+    #[test]
+    fn transfer_uses_pending_nonce() {
+        let pay = |to: H160, value: u64| Transaction { recipientAddr: to, value, accountNonce: 0, lock_height: None, lock_time: None };
+        let path = temp_path("transfer");
+        let mut wallet = Wallet::open(&path, "").unwrap();
+        let from = wallet.generate("from").unwrap();
+        let to = wallet.generate("to").unwrap();
+        let mut state = State::new();
+        state.insert(from, 500, 3);
+        let mut mempool = Mempool::new();
+        let chain_id = ChainId(H256::default());
+
+        let mut txBlocks = HashMap::new();
+        let first = wallet.transfer(&from, pay(to, 10), &state, &txBlocks, &mempool, &chain_id).unwrap();
+        assert_eq!(first.transaction.accountNonce, 4);
+        assert!(verify(&first.transaction, &first.public_key, &first.signature, &chain_id));
+        mempool.insert(&SignedTransaction::Account(first.clone()));
+        let second = wallet.transfer(&from, pay(to, 10), &state, &txBlocks, &mempool, &chain_id).unwrap();
+        assert_eq!(second.transaction.accountNonce, 5);
+
+        // transactions in transaction blocks the state has not confirmed count as well
+        let mut block = crate::block::test::generate_random_block(&H256::default());
+        block.content.data.push(SignedTransaction::Account(second));
+        txBlocks.insert(block.hash(), block);
+        let third = wallet.transfer(&from, pay(to, 10), &state, &txBlocks, &mempool, &chain_id).unwrap();
+        assert_eq!(third.transaction.accountNonce, 6);
+
+        let info = account_info(&to, &state, &txBlocks, &mempool);
+        assert_eq!((info.balance, info.nonce, info.pending_nonce), (INITIAL_BALANCE, 0, 1));
+
+        // a batch takes one nonce for all its payments
+        let outputs = vec![Output { recipientAddr: to, value: 1 }, Output { recipientAddr: from, value: 2 }];
+        let batch = wallet.transfer_batch(&from, outputs, &state, &txBlocks, &mempool, &chain_id).unwrap();
+        assert_eq!(batch.transaction.accountNonce, 6);
+        assert!(batch.verify(&chain_id));
+
+        // a 2-of-3 address the wallet holds two keys of
+        let outsider = key_pair::random();
+        let public_keys = vec![wallet.get(&from).unwrap().public_key().to_vec(), wallet.get(&to).unwrap().public_key().to_vec(), outsider.public_key().as_ref().to_vec()];
+        let multisig = Multisig::new(2, public_keys.clone()).unwrap();
+        let treasury = wallet.transfer_multisig(multisig, pay(to, 10), &state, &txBlocks, &mempool, &chain_id).unwrap();
+        assert_eq!((treasury.transaction.accountNonce, treasury.signatures.len()), (1, 2));
+        assert!(treasury.verify(&chain_id));
+        let multisig = Multisig::new(3, public_keys).unwrap();
+        assert!(wallet.transfer_multisig(multisig, pay(to, 10), &state, &txBlocks, &mempool, &chain_id).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}